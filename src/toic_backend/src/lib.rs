@@ -1,7 +1,15 @@
 use candid::Principal;
-use ic_cdk::{caller, export_candid, query, update};
+use ic_cdk::{api::is_controller, caller, export_candid, init, post_upgrade, query, update};
 use serde_bytes::ByteBuf;
-use services::{draft::DRAFT_SERVICE, story::STORY_SERVICE, user::USER_SERVICE};
+use services::{
+    draft::DRAFT_SERVICE,
+    library::LIBRARY_SERVICE,
+    llm::LLM_SERVICE,
+    statistics::{restart_statistics_rebuild, resume_statistics_rebuild, STATISTICS_SERVICE},
+    story::{start_engagement_prune_timer, start_rank_refresh_timer, STORY_SERVICE},
+    treasury::{start_emission_timer, TREASURY_SERVICE},
+    user::USER_SERVICE,
+};
 
 mod memory;
 mod repositories;
@@ -26,12 +34,42 @@ fn get_and_validate_caller() -> ApiResult<Principal> {
     Ok(identity)
 }
 
+fn get_and_validate_controller() -> ApiResult<Principal> {
+    let identity = caller();
+    if !is_controller(&identity) {
+        return Err(ServiceError::IdentityUnauthorized {
+            identity: identity.to_string(),
+        })
+        .map_err(api_err);
+    }
+    Ok(identity)
+}
+
 fn api_err(err: ServiceError) -> ErrorResponse {
     return ErrorResponse {
         message: err.to_string(),
     };
 }
 
+#[init]
+fn init() {
+    resume_statistics_rebuild();
+    start_emission_timer();
+    start_rank_refresh_timer();
+    start_engagement_prune_timer();
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    // the counters are recounted in batches after the upgrade, walking every entity here could
+    // trap the upgrade at the instruction limit
+    resume_statistics_rebuild();
+    token::staking::migrate_stake_positions(timestamp());
+    start_emission_timer();
+    start_rank_refresh_timer();
+    start_engagement_prune_timer();
+}
+
 #[update]
 async fn create_draft(args: SaveDraftArgs) -> ApiResult<Draft> {
     let identity = get_and_validate_caller()?;
@@ -80,6 +118,34 @@ fn get_draft(id: u64) -> ApiResult<(Draft, StoryContent)> {
     DRAFT_SERVICE.get_draft(&id).map_err(api_err)
 }
 
+#[update]
+async fn chat_with_assistant(draft_id: u64, message: String) -> ApiResult<AssistantChatMessage> {
+    let identity = get_and_validate_caller()?;
+
+    DRAFT_SERVICE
+        .chat_with_assistant(draft_id, message, identity)
+        .await
+        .map_err(api_err)
+}
+
+#[query]
+fn get_assistant_chat(draft_id: u64) -> ApiResult<Vec<AssistantChatMessage>> {
+    let identity = get_and_validate_caller()?;
+
+    DRAFT_SERVICE
+        .get_assistant_chat(draft_id, identity)
+        .map_err(api_err)
+}
+
+#[update]
+fn clear_assistant_chat(draft_id: u64) -> ApiResult<()> {
+    let identity = get_and_validate_caller()?;
+
+    DRAFT_SERVICE
+        .clear_assistant_chat(draft_id, identity)
+        .map_err(api_err)
+}
+
 #[query]
 fn get_drafts() -> ApiResult<Vec<Draft>> {
     let identity = get_and_validate_caller()?;
@@ -91,20 +157,41 @@ fn get_drafts() -> ApiResult<Vec<Draft>> {
 fn get_story(id: u64) -> ApiResult<(Story, StoryContent)> {
     // anon can read
 
-    STORY_SERVICE.get_story(&id).map_err(api_err)
+    STORY_SERVICE.get_story(&id, caller()).map_err(api_err)
+}
+
+#[query]
+fn get_story_translations(id: u64) -> ApiResult<Vec<Story>> {
+    // anon can read
+
+    STORY_SERVICE
+        .get_story_translations(id, caller())
+        .map_err(api_err)
 }
 
 #[query]
 fn get_recommended_stories(
     args: FetchStoriesByScoreArgs,
 ) -> ApiResult<(Option<(Score, u64)>, Vec<Story>)> {
-    get_and_validate_caller()?;
+    let identity = get_and_validate_caller()?;
 
     STORY_SERVICE
-        .get_recommended_stories(args.cursor, args.limit.unwrap_or(15))
+        .get_recommended_stories(args.cursor, args.limit.unwrap_or(15), identity)
         .map_err(api_err)
 }
 
+#[query]
+fn get_ranking_config() -> RankingConfig {
+    STORY_SERVICE.get_ranking_config()
+}
+
+#[update]
+fn set_ranking_config(config: RankingConfig) -> ApiResult<RankingConfig> {
+    get_and_validate_controller()?;
+
+    STORY_SERVICE.set_ranking_config(config).map_err(api_err)
+}
+
 #[query]
 fn get_stories_by_author(args: FetchStoriesArgs) -> ApiResult<(Option<u64>, Vec<Story>)> {
     // anon can read
@@ -117,7 +204,12 @@ fn get_stories_by_author(args: FetchStoriesArgs) -> ApiResult<(Option<u64>, Vec<
     }
 
     STORY_SERVICE
-        .get_stories_by_author(args.author.unwrap(), args.cursor, args.limit.unwrap_or(15))
+        .get_stories_by_author(
+            args.author.unwrap(),
+            args.cursor,
+            args.limit.unwrap_or(15),
+            caller(),
+        )
         .map_err(api_err)
 }
 
@@ -135,12 +227,47 @@ fn get_stories_by_category(args: FetchStoriesArgs) -> ApiResult<(Option<u64>, Ve
     STORY_SERVICE
         .get_stories_by_category(
             args.category.unwrap(),
+            args.language,
             args.cursor,
             args.limit.unwrap_or(15),
+            caller(),
         )
         .map_err(api_err)
 }
 
+#[query]
+fn get_stories_by_tag(
+    tag: String,
+    cursor: Option<u64>,
+    limit: Option<usize>,
+) -> ApiResult<(Option<u64>, Vec<Story>)> {
+    // anon can read
+
+    STORY_SERVICE
+        .get_stories_by_tag(tag, cursor, limit.unwrap_or(15), caller())
+        .map_err(api_err)
+}
+
+#[query]
+fn get_categories() -> Vec<CategoryInfo> {
+    // anon can read
+    STORY_SERVICE.get_categories()
+}
+
+#[update]
+fn add_category(name: String) -> ApiResult<CategoryDefinition> {
+    get_and_validate_controller()?;
+
+    STORY_SERVICE.add_category(name).map_err(api_err)
+}
+
+#[update]
+fn update_category(id: u16, args: UpdateCategoryArgs) -> ApiResult<CategoryDefinition> {
+    get_and_validate_controller()?;
+
+    STORY_SERVICE.update_category(id, args).map_err(api_err)
+}
+
 #[update]
 async fn support_story(args: StoryInteractionArgs) -> ApiResult<bool> {
     let identity = get_and_validate_caller()?;
@@ -151,6 +278,74 @@ async fn support_story(args: StoryInteractionArgs) -> ApiResult<bool> {
         .map_err(api_err)
 }
 
+#[query]
+fn get_related_stories(id: u64, limit: Option<usize>) -> ApiResult<Vec<Story>> {
+    // anon can read
+
+    STORY_SERVICE
+        .get_related_stories(id, limit.unwrap_or(5).min(20), caller())
+        .map_err(api_err)
+}
+
+#[update]
+fn record_story_view(id: u64) -> ApiResult<bool> {
+    let identity = get_and_validate_caller()?;
+
+    STORY_SERVICE.record_view(id, identity).map_err(api_err)
+}
+
+#[query]
+fn get_trending_stories(window: LeaderboardWindow, limit: Option<usize>) -> ApiResult<Vec<Story>> {
+    // anon can read
+
+    STORY_SERVICE
+        .get_trending_stories(window, limit.unwrap_or(10).min(50), caller())
+        .map_err(api_err)
+}
+
+#[query]
+fn get_top_authors(
+    window: LeaderboardWindow,
+    limit: Option<usize>,
+) -> ApiResult<Vec<AuthorStanding>> {
+    // anon can read
+
+    STORY_SERVICE
+        .get_top_authors(window, limit.unwrap_or(10).min(50))
+        .map_err(api_err)
+}
+
+#[query]
+fn get_author_analytics(args: AuthorAnalyticsArgs) -> ApiResult<AuthorAnalytics> {
+    let identity = get_and_validate_caller()?;
+
+    let to = args.to.unwrap_or_else(timestamp);
+    let from = args
+        .from
+        .unwrap_or_else(|| to.saturating_sub(30 * utils::DAY_NANOS));
+    STORY_SERVICE
+        .get_author_analytics(identity, from, to)
+        .map_err(api_err)
+}
+
+#[update]
+fn reindex_story_terms(cursor: Option<u64>) -> ApiResult<Option<u64>> {
+    get_and_validate_controller()?;
+
+    STORY_SERVICE
+        .reindex_story_terms(cursor, 100)
+        .map_err(api_err)
+}
+
+#[update]
+fn detect_story_languages(cursor: Option<u64>) -> ApiResult<Option<u64>> {
+    get_and_validate_controller()?;
+
+    STORY_SERVICE
+        .detect_story_languages(cursor, 100)
+        .map_err(api_err)
+}
+
 #[query]
 fn get_story_supporter(id: u64) -> ApiResult<Vec<UserOutline>> {
     get_and_validate_caller()?;
@@ -162,16 +357,72 @@ fn get_story_supporter(id: u64) -> ApiResult<Vec<UserOutline>> {
 async fn assist_action(args: AssistActionArgs) -> ApiResult<String> {
     let identity = &get_and_validate_caller()?;
 
-    match args {
-        AssistActionArgs::ExpandWriting(id) => STORY_SERVICE
-            .assist_expand_writing(&id, identity)
-            .await
-            .map_err(api_err),
-        AssistActionArgs::GenerateDescription(id) => STORY_SERVICE
-            .assist_story_description(&id, identity)
-            .await
-            .map_err(api_err),
-    }
+    STORY_SERVICE.assist(&args, identity).await.map_err(api_err)
+}
+
+#[update]
+async fn assist_selection(
+    args: AssistActionArgs,
+    selection: AssistSelection,
+) -> ApiResult<AssistReplacement> {
+    let identity = &get_and_validate_caller()?;
+
+    STORY_SERVICE
+        .assist_selection(&args, &selection, identity)
+        .await
+        .map_err(api_err)
+}
+
+#[query]
+fn get_ai_quota() -> ApiResult<AiQuota> {
+    let identity = get_and_validate_caller()?;
+
+    Ok(USER_SERVICE.get_ai_quota(&identity, timestamp()))
+}
+
+#[query]
+fn get_staking_tiers() -> Vec<StakingTier> {
+    USER_SERVICE.get_staking_tiers().0
+}
+
+#[update]
+fn set_staking_tiers(tiers: Vec<StakingTier>) -> ApiResult<Vec<StakingTier>> {
+    get_and_validate_controller()?;
+
+    USER_SERVICE
+        .set_staking_tiers(tiers)
+        .map(|t| t.0)
+        .map_err(api_err)
+}
+
+#[query]
+fn get_moderation_queue() -> ApiResult<Vec<ModerationEntry>> {
+    get_and_validate_controller()?;
+
+    Ok(STORY_SERVICE.get_moderation_queue())
+}
+
+#[update]
+fn resolve_moderation(id: u64, labels: Vec<SafetyLabel>) -> ApiResult<Story> {
+    get_and_validate_controller()?;
+
+    STORY_SERVICE
+        .resolve_moderation(id, labels)
+        .map_err(api_err)
+}
+
+#[query]
+fn get_llm_model_config() -> ApiResult<LlmModelConfig> {
+    get_and_validate_caller()?;
+
+    Ok(LLM_SERVICE.get_model_config())
+}
+
+#[update]
+fn set_llm_model_config(config: LlmModelConfig) -> ApiResult<LlmModelConfig> {
+    get_and_validate_controller()?;
+
+    LLM_SERVICE.set_model_config(config).map_err(api_err)
 }
 
 #[update]
@@ -187,6 +438,245 @@ async fn login() -> ApiResult<User> {
     }
 }
 
+#[update]
+fn update_profile(args: UpdateProfileArgs) -> ApiResult<User> {
+    let identity = get_and_validate_caller()?;
+
+    USER_SERVICE.update_profile(identity, args).map_err(api_err)
+}
+
+#[query]
+fn resolve_handle(handle: String) -> ApiResult<UserOutline> {
+    // anon can read
+
+    USER_SERVICE
+        .resolve_handle(&handle)
+        .map(|user| UserOutline {
+            id: user.id,
+            name: user.name,
+            bio: user.bio,
+        })
+        .map_err(api_err)
+}
+
+#[query]
+fn get_user_profile(id: Principal) -> ApiResult<UserProfile> {
+    // anon can read
+
+    STORY_SERVICE.get_user_profile(id).map_err(api_err)
+}
+
+#[update]
+fn follow_author(author: Principal) -> ApiResult<bool> {
+    let identity = get_and_validate_caller()?;
+
+    USER_SERVICE
+        .follow_author(identity, author, timestamp())
+        .map_err(api_err)
+}
+
+#[update]
+fn unfollow_author(author: Principal) -> ApiResult<bool> {
+    let identity = get_and_validate_caller()?;
+
+    USER_SERVICE
+        .unfollow_author(identity, author, timestamp())
+        .map_err(api_err)
+}
+
+#[query]
+fn get_notifications(args: FetchNotificationsArgs) -> ApiResult<(Option<u64>, Vec<Notification>)> {
+    let identity = get_and_validate_caller()?;
+
+    USER_SERVICE
+        .get_notifications(identity, args.cursor, args.limit.unwrap_or(15).min(50))
+        .map_err(api_err)
+}
+
+#[update]
+fn mark_notifications_read(ids: Option<Vec<u64>>) -> ApiResult<usize> {
+    let identity = get_and_validate_caller()?;
+
+    USER_SERVICE
+        .mark_notifications_read(identity, ids)
+        .map_err(api_err)
+}
+
+#[query]
+fn get_unread_notification_count() -> ApiResult<u64> {
+    let identity = get_and_validate_caller()?;
+
+    Ok(USER_SERVICE.get_unread_notification_count(identity))
+}
+
+#[update]
+fn mute_user(user: Principal) -> ApiResult<bool> {
+    let identity = get_and_validate_caller()?;
+
+    USER_SERVICE.mute_user(identity, user).map_err(api_err)
+}
+
+#[update]
+fn unmute_user(user: Principal) -> ApiResult<bool> {
+    let identity = get_and_validate_caller()?;
+
+    USER_SERVICE.unmute_user(identity, user).map_err(api_err)
+}
+
+#[query]
+fn get_muted_users() -> ApiResult<Vec<Principal>> {
+    let identity = get_and_validate_caller()?;
+
+    Ok(USER_SERVICE.get_muted_users(identity))
+}
+
+#[update]
+fn block_user(user: Principal) -> ApiResult<bool> {
+    let identity = get_and_validate_caller()?;
+
+    USER_SERVICE
+        .block_user(identity, user, timestamp())
+        .map_err(api_err)
+}
+
+#[update]
+fn unblock_user(user: Principal) -> ApiResult<bool> {
+    let identity = get_and_validate_caller()?;
+
+    USER_SERVICE.unblock_user(identity, user).map_err(api_err)
+}
+
+#[query]
+fn get_blocked_users() -> ApiResult<Vec<Principal>> {
+    let identity = get_and_validate_caller()?;
+
+    Ok(USER_SERVICE.get_blocked_users(identity))
+}
+
+#[update]
+fn bookmark_story(id: u64) -> ApiResult<bool> {
+    let identity = get_and_validate_caller()?;
+
+    LIBRARY_SERVICE
+        .bookmark_story(identity, id, timestamp())
+        .map_err(api_err)
+}
+
+#[update]
+fn remove_bookmark(id: u64) -> ApiResult<bool> {
+    let identity = get_and_validate_caller()?;
+
+    LIBRARY_SERVICE
+        .remove_bookmark(identity, id)
+        .map_err(api_err)
+}
+
+#[query]
+fn get_bookmarks(args: FetchBookmarksArgs) -> ApiResult<(Option<BookmarkCursor>, Vec<Story>)> {
+    let identity = get_and_validate_caller()?;
+
+    LIBRARY_SERVICE
+        .get_bookmarks(identity, args.cursor, args.limit.unwrap_or(15).min(50))
+        .map_err(api_err)
+}
+
+#[update]
+fn save_reading_progress(story_id: u64, position: u64) -> ApiResult<ReadingProgress> {
+    let identity = get_and_validate_caller()?;
+
+    LIBRARY_SERVICE
+        .save_reading_progress(identity, story_id, position, timestamp())
+        .map_err(api_err)
+}
+
+#[query]
+fn get_reading_progress(story_id: u64) -> ApiResult<Option<ReadingProgress>> {
+    let identity = get_and_validate_caller()?;
+
+    Ok(LIBRARY_SERVICE.get_reading_progress(identity, story_id))
+}
+
+#[query]
+fn get_reading_history(
+    args: FetchReadingHistoryArgs,
+) -> ApiResult<(Option<ReadingHistoryCursor>, Vec<ReadingHistoryEntry>)> {
+    let identity = get_and_validate_caller()?;
+
+    LIBRARY_SERVICE
+        .get_reading_history(identity, args.cursor, args.limit.unwrap_or(15).min(50))
+        .map_err(api_err)
+}
+
+#[update]
+fn create_reading_list(args: ReadingListArgs) -> ApiResult<ReadingList> {
+    let identity = get_and_validate_caller()?;
+
+    LIBRARY_SERVICE
+        .create_reading_list(identity, args)
+        .map_err(api_err)
+}
+
+#[update]
+fn update_reading_list(id: u64, args: ReadingListArgs) -> ApiResult<ReadingList> {
+    let identity = get_and_validate_caller()?;
+
+    LIBRARY_SERVICE
+        .update_reading_list(identity, id, args)
+        .map_err(api_err)
+}
+
+#[update]
+fn delete_reading_list(id: u64) -> ApiResult<u64> {
+    let identity = get_and_validate_caller()?;
+
+    LIBRARY_SERVICE
+        .delete_reading_list(identity, id)
+        .map_err(api_err)
+}
+
+#[update]
+fn add_to_reading_list(id: u64, story_id: u64, position: Option<usize>) -> ApiResult<ReadingList> {
+    let identity = get_and_validate_caller()?;
+
+    LIBRARY_SERVICE
+        .add_to_reading_list(identity, id, story_id, position)
+        .map_err(api_err)
+}
+
+#[update]
+fn remove_from_reading_list(id: u64, story_id: u64) -> ApiResult<ReadingList> {
+    let identity = get_and_validate_caller()?;
+
+    LIBRARY_SERVICE
+        .remove_from_reading_list(identity, id, story_id)
+        .map_err(api_err)
+}
+
+#[update]
+fn reorder_reading_list(id: u64, story_ids: Vec<u64>) -> ApiResult<ReadingList> {
+    let identity = get_and_validate_caller()?;
+
+    LIBRARY_SERVICE
+        .reorder_reading_list(identity, id, story_ids)
+        .map_err(api_err)
+}
+
+#[query]
+fn get_reading_list(id: u64) -> ApiResult<ReadingListDetail> {
+    // anon can read public lists
+
+    LIBRARY_SERVICE
+        .get_reading_list(caller(), id)
+        .map_err(api_err)
+}
+
+#[query]
+fn get_reading_lists(owner: Principal) -> ApiResult<Vec<ReadingList>> {
+    // anon can read public lists
+
+    Ok(LIBRARY_SERVICE.get_reading_lists(caller(), owner))
+}
+
 #[update]
 async fn complete_onboarding(args: OnboardingArgs) -> ApiResult<bool> {
     let identity = get_and_validate_caller()?;
@@ -217,6 +707,72 @@ async fn complete_onboarding(args: OnboardingArgs) -> ApiResult<bool> {
     Ok(false)
 }
 
+#[query]
+fn get_statistics() -> Statistics {
+    // anon can read
+
+    STATISTICS_SERVICE.get_statistics()
+}
+
+#[update]
+fn rebuild_statistics() -> ApiResult<Statistics> {
+    get_and_validate_controller()?;
+
+    restart_statistics_rebuild();
+    Ok(STATISTICS_SERVICE.get_statistics())
+}
+
+#[query]
+fn get_treasury_state() -> TreasuryState {
+    TREASURY_SERVICE.get_state()
+}
+
+#[update]
+fn update_emission_schedule(schedule: EmissionSchedule) -> ApiResult<TreasuryState> {
+    get_and_validate_controller()?;
+
+    TREASURY_SERVICE.update_schedule(schedule).map_err(api_err)
+}
+
+#[update]
+fn set_emission_paused(paused: bool) -> ApiResult<TreasuryState> {
+    get_and_validate_controller()?;
+
+    TREASURY_SERVICE.set_paused(paused).map_err(api_err)
+}
+
+#[update]
+fn run_reward_round() -> ApiResult<RewardRound> {
+    get_and_validate_controller()?;
+
+    TREASURY_SERVICE.run_round(timestamp()).map_err(api_err)
+}
+
+#[update]
+fn retry_reward_allocations(round_id: u64) -> ApiResult<RewardRound> {
+    get_and_validate_controller()?;
+
+    TREASURY_SERVICE
+        .retry_failed_allocations(round_id)
+        .map_err(api_err)
+}
+
+#[query]
+fn get_reward_rounds(args: FetchRewardRoundsArgs) -> ApiResult<(Option<u64>, Vec<RewardRound>)> {
+    // anon can read
+
+    TREASURY_SERVICE
+        .get_rounds(args.cursor, args.limit.unwrap_or(15))
+        .map_err(api_err)
+}
+
+#[query]
+fn get_reward_round(id: u64) -> ApiResult<(RewardRound, Vec<RewardAllocation>)> {
+    // anon can read
+
+    TREASURY_SERVICE.get_round(id).map_err(api_err)
+}
+
 // debuging
 
 #[query]
@@ -30,3 +30,52 @@ pub const TOKEN_CONFIG_MEM_ID: MemoryId = MemoryId::new(13);
 pub const TOKEN_TX_LOG_MEM_ID: MemoryId = MemoryId::new(14);
 pub const TOKEN_ACCOUNT_BALANCE_MEM_ID: MemoryId = MemoryId::new(15);
 pub const TOKEN_ACCOUNT_STAKING_MEM_ID: MemoryId = MemoryId::new(16);
+
+pub const TREASURY_STATE_MEM_ID: MemoryId = MemoryId::new(17);
+pub const SERIAL_REWARD_ROUND_MEM_ID: MemoryId = MemoryId::new(18);
+pub const ET_REWARD_ROUND_MEM_ID: MemoryId = MemoryId::new(19);
+pub const IDX_REWARD_ALLOCATION_MEM_ID: MemoryId = MemoryId::new(20);
+pub const ET_PENDING_ENGAGEMENT_MEM_ID: MemoryId = MemoryId::new(21);
+
+pub const AI_STAKING_TIER_MEM_ID: MemoryId = MemoryId::new(22);
+pub const IDX_AI_USAGE_MEM_ID: MemoryId = MemoryId::new(23);
+
+pub const TOKEN_STAKE_POSITION_MEM_ID: MemoryId = MemoryId::new(24);
+pub const TOKEN_STAKING_CONFIG_MEM_ID: MemoryId = MemoryId::new(25);
+pub const TOKEN_CONFIG_AUDIT_MEM_ID: MemoryId = MemoryId::new(26);
+pub const LLM_MODEL_CONFIG_MEM_ID: MemoryId = MemoryId::new(27);
+pub const ET_DRAFT_CHAT_MEM_ID: MemoryId = MemoryId::new(28);
+pub const ET_MODERATION_QUEUE_MEM_ID: MemoryId = MemoryId::new(29);
+pub const ET_STORY_TERMS_MEM_ID: MemoryId = MemoryId::new(30);
+pub const IDX_TERM_STORY_MEM_ID: MemoryId = MemoryId::new(31);
+pub const IDX_TERM_DOC_FREQ_MEM_ID: MemoryId = MemoryId::new(32);
+pub const STORY_RANKING_CONFIG_MEM_ID: MemoryId = MemoryId::new(33);
+pub const IDX_STORY_DAILY_ENGAGEMENT_MEM_ID: MemoryId = MemoryId::new(34);
+pub const IDX_AUTHOR_DAILY_ENGAGEMENT_MEM_ID: MemoryId = MemoryId::new(35);
+pub const PLATFORM_COUNTERS_MEM_ID: MemoryId = MemoryId::new(36);
+pub const IDX_STORY_DAILY_ACTIVITY_MEM_ID: MemoryId = MemoryId::new(37);
+pub const IDX_AUTHOR_DAILY_FOLLOWERS_MEM_ID: MemoryId = MemoryId::new(38);
+pub const IDX_USER_HANDLE_MEM_ID: MemoryId = MemoryId::new(39);
+pub const ET_USER_AVATAR_MEM_ID: MemoryId = MemoryId::new(40);
+pub const ET_NOTIFICATION_MEM_ID: MemoryId = MemoryId::new(41);
+pub const SERIAL_NOTIFICATION_MEM_ID: MemoryId = MemoryId::new(42);
+pub const IDX_AUTHOR_FOLLOWER_MEM_ID: MemoryId = MemoryId::new(43);
+pub const IDX_USER_BOOKMARK_MEM_ID: MemoryId = MemoryId::new(44);
+pub const IDX_STORY_BOOKMARK_MEM_ID: MemoryId = MemoryId::new(45);
+pub const ET_READING_LIST_MEM_ID: MemoryId = MemoryId::new(46);
+pub const SERIAL_READING_LIST_MEM_ID: MemoryId = MemoryId::new(47);
+pub const IDX_READING_LIST_OWNER_MEM_ID: MemoryId = MemoryId::new(48);
+pub const ET_READING_PROGRESS_MEM_ID: MemoryId = MemoryId::new(49);
+pub const IDX_USER_READING_HISTORY_MEM_ID: MemoryId = MemoryId::new(50);
+pub const IDX_USER_MUTE_MEM_ID: MemoryId = MemoryId::new(51);
+pub const IDX_USER_BLOCK_MEM_ID: MemoryId = MemoryId::new(52);
+pub const ET_CATEGORY_MEM_ID: MemoryId = MemoryId::new(53);
+pub const IDX_STORY_TAG_MEM_ID: MemoryId = MemoryId::new(54);
+pub const IDX_STORY_LANGUAGE_MEM_ID: MemoryId = MemoryId::new(55);
+pub const IDX_STORY_TRANSLATION_MEM_ID: MemoryId = MemoryId::new(56);
+pub const IDX_STORY_DAILY_VIEW_MEM_ID: MemoryId = MemoryId::new(57);
+pub const ET_STORY_ALL_TIME_ENGAGEMENT_MEM_ID: MemoryId = MemoryId::new(58);
+pub const IDX_STORY_ALL_TIME_POINTS_MEM_ID: MemoryId = MemoryId::new(59);
+pub const ET_AUTHOR_ALL_TIME_ENGAGEMENT_MEM_ID: MemoryId = MemoryId::new(60);
+pub const IDX_AUTHOR_ALL_TIME_POINTS_MEM_ID: MemoryId = MemoryId::new(61);
+pub const IDX_STORY_CATEGORY_SCORE_MEM_ID: MemoryId = MemoryId::new(62);
@@ -0,0 +1,90 @@
+use std::{cell::RefCell, sync::Arc};
+
+use candid::Principal;
+use ic_stable_structures::{BTreeMap, Cell};
+use lazy_static::lazy_static;
+
+use crate::{
+    memory::{AI_STAKING_TIER_MEM_ID, IDX_AI_USAGE_MEM_ID, MEMORY_MANAGER},
+    types::{
+        BTreeMapRefCell, RepositoryError, RepositoryResult, StakingTiers, StorablePrincipal,
+        VMemory,
+    },
+};
+
+thread_local! {
+    static STAKING_TIERS: RefCell<Cell<StakingTiers, VMemory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(AI_STAKING_TIER_MEM_ID)),
+            StakingTiers::default()
+        ).expect("failed to init STAKING_TIERS")
+    );
+
+    // (user, day index) -> number of assist requests
+    static AI_USAGE: BTreeMapRefCell<(StorablePrincipal, u64), u32> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(IDX_AI_USAGE_MEM_ID))
+        )
+    );
+}
+
+lazy_static! {
+    pub static ref AI_QUOTA_REPOSITORY: Arc<AiQuotaRepository> =
+        Arc::new(AiQuotaRepository::default());
+}
+
+#[derive(Debug, Default)]
+pub struct AiQuotaRepository;
+
+impl AiQuotaRepository {
+    pub fn get_tiers(&self) -> StakingTiers {
+        STAKING_TIERS.with_borrow(|cell| cell.get().clone())
+    }
+
+    pub fn set_tiers(&self, tiers: StakingTiers) -> RepositoryResult<StakingTiers> {
+        STAKING_TIERS.with_borrow_mut(|cell| {
+            cell.set(tiers.clone())
+                .map_err(|e| RepositoryError::IllegalUpdate {
+                    reason: format!("{:?}", e),
+                })
+        })?;
+        Ok(tiers)
+    }
+
+    pub fn get_usage(&self, user: Principal, day: u64) -> u32 {
+        AI_USAGE.with_borrow(|m| m.get(&(StorablePrincipal(user), day)).unwrap_or_default())
+    }
+
+    /// Increments the usage of the day and drops the usage of the previous days
+    pub fn increment_usage(&self, user: Principal, day: u64) -> u32 {
+        AI_USAGE.with_borrow_mut(|m| {
+            let stale = m
+                .range((StorablePrincipal(user), 0)..(StorablePrincipal(user), day))
+                .map(|(k, _)| k)
+                .collect::<Vec<_>>();
+            for key in stale {
+                m.remove(&key);
+            }
+
+            let key = (StorablePrincipal(user), day);
+            let used = m.get(&key).unwrap_or_default() + 1;
+            m.insert(key, used);
+            used
+        })
+    }
+
+    pub fn decrement_usage(&self, user: Principal, day: u64) {
+        AI_USAGE.with_borrow_mut(|m| {
+            let key = (StorablePrincipal(user), day);
+            match m.get(&key) {
+                Some(used) if used > 1 => {
+                    m.insert(key, used - 1);
+                }
+                Some(_) => {
+                    m.remove(&key);
+                }
+                None => {}
+            }
+        });
+    }
+}
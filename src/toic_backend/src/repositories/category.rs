@@ -0,0 +1,84 @@
+use std::{cell::RefCell, sync::Arc};
+
+use ic_stable_structures::BTreeMap;
+use lazy_static::lazy_static;
+
+use crate::{
+    memory::{ET_CATEGORY_MEM_ID, MEMORY_MANAGER},
+    types::{BTreeMapRefCell, Category, CategoryDefinition, RepositoryError, RepositoryResult},
+};
+
+thread_local! {
+    // custom category id -> definition
+    static CATEGORY: BTreeMapRefCell<u16, CategoryDefinition> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(ET_CATEGORY_MEM_ID))
+        )
+    );
+}
+
+lazy_static! {
+    pub static ref CATEGORY_REPOSITORY: Arc<CategoryRepository> =
+        Arc::new(CategoryRepository::default());
+}
+
+#[derive(Debug, Default)]
+pub struct CategoryRepository;
+
+impl CategoryRepository {
+    pub fn get(&self, id: u16) -> Option<CategoryDefinition> {
+        CATEGORY.with_borrow(|m| m.get(&id))
+    }
+
+    /// Whether a category can be picked for a story or followed, built-in ones always can
+    pub fn is_available(&self, category: &Category) -> bool {
+        match category {
+            Category::Custom(id) => self.get(*id).is_some_and(|c| c.active),
+            _ => true,
+        }
+    }
+
+    pub fn get_all(&self) -> Vec<CategoryDefinition> {
+        CATEGORY.with_borrow(|m| m.values().collect())
+    }
+
+    /// Adds a category with the next free id, names are unique regardless of case
+    pub fn insert(&self, name: String, now: u64) -> RepositoryResult<CategoryDefinition> {
+        self.ensure_unique_name(&name, None)?;
+        let id = CATEGORY.with_borrow(|m| {
+            m.last_key_value()
+                .map_or(Some(0), |(id, _)| id.checked_add(1))
+        });
+        let id = id.ok_or(RepositoryError::IllegalArgument {
+            reason: "No category id left".to_string(),
+        })?;
+        let definition = CategoryDefinition {
+            id,
+            name,
+            active: true,
+            created_at: now,
+        };
+        CATEGORY.with_borrow_mut(|m| m.insert(id, definition.clone()));
+        Ok(definition)
+    }
+
+    pub fn update(&self, definition: CategoryDefinition) -> RepositoryResult<CategoryDefinition> {
+        if self.get(definition.id).is_none() {
+            return Err(RepositoryError::NotFound);
+        }
+        self.ensure_unique_name(&definition.name, Some(definition.id))?;
+        CATEGORY.with_borrow_mut(|m| m.insert(definition.id, definition.clone()));
+        Ok(definition)
+    }
+
+    fn ensure_unique_name(&self, name: &str, except: Option<u16>) -> RepositoryResult<()> {
+        let taken = CATEGORY.with_borrow(|m| {
+            m.iter()
+                .any(|(id, c)| Some(id) != except && c.name.eq_ignore_ascii_case(name))
+        });
+        if taken {
+            return Err(RepositoryError::Conflict);
+        }
+        Ok(())
+    }
+}
@@ -6,16 +6,16 @@ use lazy_static::lazy_static;
 
 use crate::{
     memory::{
-        ET_DRAFT_CONTENT_MEM_ID, ET_DRAFT_MEM_ID, IDX_DRAFT_AUTHOR_MEM_ID, MEMORY_MANAGER,
-        SERIAL_DRAFT_MEM_ID,
+        ET_DRAFT_CHAT_MEM_ID, ET_DRAFT_CONTENT_MEM_ID, ET_DRAFT_MEM_ID, IDX_DRAFT_AUTHOR_MEM_ID,
+        MEMORY_MANAGER, SERIAL_DRAFT_MEM_ID,
     },
     structure::{
         AuditableRepository, BinaryTreeRepository, IndexRepository, IndexableRepository,
         Repository, SerialIdRepository,
     },
     types::{
-        BTreeMapRefCell, Draft, RepositoryError, RepositoryResult, SerialRefCell, SortOrder,
-        StoryContent, VMemory,
+        AssistantChatMessage, BTreeMapRefCell, Draft, RepositoryError, RepositoryResult,
+        SerialRefCell, SortOrder, StoryContent, VMemory,
     },
 };
 
@@ -44,12 +44,21 @@ thread_local! {
             MEMORY_MANAGER.with_borrow(|m| m.get(IDX_DRAFT_AUTHOR_MEM_ID))
         )
     );
+
+    // (draft id, message sequence) -> message
+    static DRAFT_CHAT: BTreeMapRefCell<(u64, u64), AssistantChatMessage> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(ET_DRAFT_CHAT_MEM_ID))
+        )
+    );
 }
 
 lazy_static! {
     pub static ref DRAFT_REPOSITORY: Arc<DraftRepository> = Arc::new(DraftRepository::default());
     pub static ref DRAFT_CONTENT_REPOSITORY: Arc<DraftContentRepository> =
         Arc::new(DraftContentRepository::default());
+    pub static ref DRAFT_CHAT_REPOSITORY: Arc<DraftChatRepository> =
+        Arc::new(DraftChatRepository::default());
 }
 
 #[derive(Debug, Default)]
@@ -163,3 +172,50 @@ impl DraftRepository {
         Ok(drafts)
     }
 }
+
+#[derive(Debug, Default)]
+pub struct DraftChatRepository;
+
+impl DraftChatRepository {
+    /// Returns the conversation of a draft, oldest message first
+    pub fn get_messages(&self, draft_id: u64) -> Vec<AssistantChatMessage> {
+        DRAFT_CHAT.with_borrow(|m| {
+            m.range((draft_id, 0)..=(draft_id, u64::MAX))
+                .map(|(_, msg)| msg)
+                .collect()
+        })
+    }
+
+    /// Appends the messages to the conversation, dropping the oldest ones beyond `max_messages`
+    pub fn append(&self, draft_id: u64, messages: Vec<AssistantChatMessage>, max_messages: usize) {
+        DRAFT_CHAT.with_borrow_mut(|m| {
+            let next_seq = m
+                .range((draft_id, 0)..=(draft_id, u64::MAX))
+                .next_back()
+                .map_or(0, |((_, seq), _)| seq + 1);
+            for (seq, message) in (next_seq..).zip(messages) {
+                m.insert((draft_id, seq), message);
+            }
+
+            let keys = m
+                .range((draft_id, 0)..=(draft_id, u64::MAX))
+                .map(|(k, _)| k)
+                .collect::<Vec<_>>();
+            for key in keys.iter().take(keys.len().saturating_sub(max_messages)) {
+                m.remove(key);
+            }
+        });
+    }
+
+    pub fn clear(&self, draft_id: u64) {
+        DRAFT_CHAT.with_borrow_mut(|m| {
+            let keys = m
+                .range((draft_id, 0)..=(draft_id, u64::MAX))
+                .map(|(k, _)| k)
+                .collect::<Vec<_>>();
+            for key in keys {
+                m.remove(&key);
+            }
+        });
+    }
+}
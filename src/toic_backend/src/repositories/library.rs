@@ -0,0 +1,297 @@
+use std::{cell::RefCell, cmp::Reverse, ops::Bound, sync::Arc};
+
+use candid::Principal;
+use ic_stable_structures::{BTreeMap, Cell};
+use lazy_static::lazy_static;
+
+use crate::{
+    memory::{
+        ET_READING_LIST_MEM_ID, ET_READING_PROGRESS_MEM_ID, IDX_READING_LIST_OWNER_MEM_ID,
+        IDX_STORY_BOOKMARK_MEM_ID, IDX_USER_BOOKMARK_MEM_ID, IDX_USER_READING_HISTORY_MEM_ID,
+        MEMORY_MANAGER, SERIAL_READING_LIST_MEM_ID,
+    },
+    structure::{
+        AuditableRepository, BinaryTreeRepository, IndexableRepository, SerialIdRepository,
+    },
+    types::{
+        BTreeMapRefCell, BookmarkCursor, ReadingHistoryCursor, ReadingList, ReadingProgress,
+        SerialRefCell, StorablePrincipal, VMemory,
+    },
+};
+
+thread_local! {
+    // (user, Reverse(bookmarked at), story id)
+    static USER_BOOKMARK_INDEX: BTreeMapRefCell<(StorablePrincipal, Reverse<u64>, u64), ()> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(IDX_USER_BOOKMARK_MEM_ID))
+        )
+    );
+
+    // (story id, user) -> bookmarked at
+    static STORY_BOOKMARK_INDEX: BTreeMapRefCell<(u64, StorablePrincipal), u64> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(IDX_STORY_BOOKMARK_MEM_ID))
+        )
+    );
+
+    static NEXT_READING_LIST_ID: SerialRefCell = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(SERIAL_READING_LIST_MEM_ID)), 1
+        ).expect("failed to init NEXT_READING_LIST_ID")
+    );
+
+    static READING_LIST: BTreeMapRefCell<u64, ReadingList> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(ET_READING_LIST_MEM_ID))
+        )
+    );
+
+    // (story id, reader) -> reading progress
+    static READING_PROGRESS: BTreeMapRefCell<(u64, StorablePrincipal), ReadingProgress> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(ET_READING_PROGRESS_MEM_ID))
+        )
+    );
+
+    // (reader, Reverse(read at), story id)
+    static USER_READING_HISTORY_INDEX: BTreeMapRefCell<(StorablePrincipal, Reverse<u64>, u64), ()> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(IDX_USER_READING_HISTORY_MEM_ID))
+        )
+    );
+
+    // (owner, reading list id)
+    static READING_LIST_OWNER_INDEX: BTreeMapRefCell<(StorablePrincipal, u64), ()> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(IDX_READING_LIST_OWNER_MEM_ID))
+        )
+    );
+}
+
+lazy_static! {
+    pub static ref BOOKMARK_REPOSITORY: Arc<BookmarkRepository> =
+        Arc::new(BookmarkRepository::default());
+    pub static ref READING_HISTORY_REPOSITORY: Arc<ReadingHistoryRepository> =
+        Arc::new(ReadingHistoryRepository::default());
+    pub static ref READING_LIST_REPOSITORY: Arc<ReadingListRepository> =
+        Arc::new(ReadingListRepository::default());
+}
+
+#[derive(Debug, Default)]
+pub struct BookmarkRepository;
+
+impl BookmarkRepository {
+    /// Bookmarks a story for a user, returns false when it is already bookmarked
+    pub fn add(&self, user: Principal, story_id: u64, now: u64) -> bool {
+        let user = StorablePrincipal(user);
+        if STORY_BOOKMARK_INDEX.with_borrow(|m| m.contains_key(&(story_id, user.clone()))) {
+            return false;
+        }
+        STORY_BOOKMARK_INDEX.with_borrow_mut(|m| m.insert((story_id, user.clone()), now));
+        USER_BOOKMARK_INDEX.with_borrow_mut(|m| m.insert((user, Reverse(now), story_id), ()));
+        true
+    }
+
+    pub fn remove(&self, user: Principal, story_id: u64) -> bool {
+        let user = StorablePrincipal(user);
+        match STORY_BOOKMARK_INDEX.with_borrow_mut(|m| m.remove(&(story_id, user.clone()))) {
+            Some(at) => {
+                USER_BOOKMARK_INDEX.with_borrow_mut(|m| m.remove(&(user, Reverse(at), story_id)));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn count(&self, user: Principal) -> usize {
+        let user = StorablePrincipal(user);
+        USER_BOOKMARK_INDEX.with_borrow(|m| {
+            m.range((user.clone(), Reverse(u64::MAX), 0)..=(user, Reverse(0), u64::MAX))
+                .count()
+        })
+    }
+
+    /// Gets the bookmarks of a user as (bookmarked at, story id), latest first
+    pub fn find_by_user(
+        &self,
+        user: Principal,
+        cursor: Option<BookmarkCursor>,
+        limit: usize,
+    ) -> Vec<BookmarkCursor> {
+        let user = StorablePrincipal(user);
+        let start = match cursor {
+            Some((at, story_id)) => Bound::Excluded((user.clone(), Reverse(at), story_id)),
+            None => Bound::Included((user.clone(), Reverse(u64::MAX), 0)),
+        };
+        let end = Bound::Included((user, Reverse(0), u64::MAX));
+        USER_BOOKMARK_INDEX.with_borrow(|m| {
+            m.range((start, end))
+                .take(limit)
+                .map(|((_, at, story_id), _)| (at.0, story_id))
+                .collect()
+        })
+    }
+
+    /// Removes every bookmark of a story, returns the number of bookmarks removed
+    pub fn remove_story(&self, story_id: u64) -> usize {
+        let bookmarks = STORY_BOOKMARK_INDEX.with_borrow(|m| {
+            m.range(
+                (
+                    story_id,
+                    StorablePrincipal(Principal::management_canister()),
+                )..,
+            )
+            .take_while(|((id, _), _)| *id == story_id)
+            .map(|((_, user), at)| (user, at))
+            .collect::<Vec<_>>()
+        });
+        for (user, at) in &bookmarks {
+            STORY_BOOKMARK_INDEX.with_borrow_mut(|m| m.remove(&(story_id, user.clone())));
+            USER_BOOKMARK_INDEX
+                .with_borrow_mut(|m| m.remove(&(user.clone(), Reverse(*at), story_id)));
+        }
+        bookmarks.len()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ReadingHistoryRepository;
+
+impl ReadingHistoryRepository {
+    pub fn get(&self, reader: Principal, story_id: u64) -> Option<ReadingProgress> {
+        READING_PROGRESS.with_borrow(|m| m.get(&(story_id, StorablePrincipal(reader))))
+    }
+
+    /// Saves the progress of a reader and moves the story to the top of their history.
+    /// The least recently read stories beyond `retention` are dropped
+    pub fn save(&self, reader: Principal, progress: ReadingProgress, retention: usize) {
+        let reader = StorablePrincipal(reader);
+        let story_id = progress.story_id;
+        let updated_at = progress.updated_at;
+        if let Some(old) =
+            READING_PROGRESS.with_borrow_mut(|m| m.insert((story_id, reader.clone()), progress))
+        {
+            USER_READING_HISTORY_INDEX.with_borrow_mut(|m| {
+                m.remove(&(reader.clone(), Reverse(old.updated_at), story_id))
+            });
+        }
+        USER_READING_HISTORY_INDEX.with_borrow_mut(|m| {
+            m.insert((reader.clone(), Reverse(updated_at), story_id), ());
+            let expired = m
+                .range(
+                    (reader.clone(), Reverse(u64::MAX), 0)..=(reader.clone(), Reverse(0), u64::MAX),
+                )
+                .skip(retention)
+                .map(|(k, _)| k)
+                .collect::<Vec<_>>();
+            for key in expired {
+                m.remove(&key);
+                READING_PROGRESS.with_borrow_mut(|p| p.remove(&(key.2, reader.clone())));
+            }
+        });
+    }
+
+    /// Gets the reading progress of a reader, most recently read first
+    pub fn find_by_reader(
+        &self,
+        reader: Principal,
+        cursor: Option<ReadingHistoryCursor>,
+        limit: usize,
+    ) -> Vec<ReadingProgress> {
+        let reader = StorablePrincipal(reader);
+        let start = match cursor {
+            Some((at, story_id)) => Bound::Excluded((reader.clone(), Reverse(at), story_id)),
+            None => Bound::Included((reader.clone(), Reverse(u64::MAX), 0)),
+        };
+        let end = Bound::Included((reader.clone(), Reverse(0), u64::MAX));
+        let story_ids = USER_READING_HISTORY_INDEX.with_borrow(|m| {
+            m.range((start, end))
+                .take(limit)
+                .map(|((_, _, story_id), _)| story_id)
+                .collect::<Vec<_>>()
+        });
+        story_ids
+            .into_iter()
+            .filter_map(|story_id| self.get(reader.0, story_id))
+            .collect()
+    }
+
+    /// Removes the progress of every reader of a story, returns the number of readers removed
+    pub fn remove_story(&self, story_id: u64) -> usize {
+        let readers = READING_PROGRESS.with_borrow(|m| {
+            m.range(
+                (
+                    story_id,
+                    StorablePrincipal(Principal::management_canister()),
+                )..,
+            )
+            .take_while(|((id, _), _)| *id == story_id)
+            .map(|((_, reader), progress)| (reader, progress.updated_at))
+            .collect::<Vec<_>>()
+        });
+        for (reader, at) in &readers {
+            READING_PROGRESS.with_borrow_mut(|m| m.remove(&(story_id, reader.clone())));
+            USER_READING_HISTORY_INDEX
+                .with_borrow_mut(|m| m.remove(&(reader.clone(), Reverse(*at), story_id)));
+        }
+        readers.len()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ReadingListRepository;
+
+impl SerialIdRepository<VMemory> for ReadingListRepository {
+    fn with_generator<F, R>(f: F) -> R
+    where
+        F: FnOnce(&mut Cell<u64, VMemory>) -> R,
+    {
+        NEXT_READING_LIST_ID.with_borrow_mut(f)
+    }
+}
+
+impl BinaryTreeRepository<u64, ReadingList, VMemory> for ReadingListRepository {
+    fn with_ref<F, R>(f: F) -> R
+    where
+        F: FnOnce(&RefCell<BTreeMap<u64, ReadingList, VMemory>>) -> R,
+    {
+        READING_LIST.with(f)
+    }
+}
+
+impl AuditableRepository<ReadingList, VMemory> for ReadingListRepository {}
+
+impl IndexableRepository<ReadingList> for ReadingListRepository {
+    fn remove_indexes(&self, value: &ReadingList) {
+        READING_LIST_OWNER_INDEX
+            .with_borrow_mut(|m| m.remove(&(StorablePrincipal(value.owner), value.id)));
+    }
+
+    fn add_indexes(&self, value: &ReadingList) {
+        READING_LIST_OWNER_INDEX
+            .with_borrow_mut(|m| m.insert((StorablePrincipal(value.owner), value.id), ()));
+    }
+
+    fn clear_indexes(&self) {
+        READING_LIST_OWNER_INDEX.with_borrow_mut(|m| m.clear_new());
+    }
+}
+
+impl ReadingListRepository {
+    /// Gets the reading lists of an owner, oldest first
+    pub fn find_by_owner(&self, owner: Principal) -> Vec<ReadingList> {
+        let owner = StorablePrincipal(owner);
+        let ids = READING_LIST_OWNER_INDEX.with_borrow(|m| {
+            m.range((owner.clone(), 0)..=(owner, u64::MAX))
+                .map(|((_, id), _)| id)
+                .collect::<Vec<_>>()
+        });
+        ids.into_iter().filter_map(|id| self.get(&id)).collect()
+    }
+
+    pub fn count_by_owner(&self, owner: Principal) -> usize {
+        let owner = StorablePrincipal(owner);
+        READING_LIST_OWNER_INDEX
+            .with_borrow(|m| m.range((owner.clone(), 0)..=(owner, u64::MAX)).count())
+    }
+}
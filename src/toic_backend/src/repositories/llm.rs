@@ -0,0 +1,42 @@
+use std::{cell::RefCell, sync::Arc};
+
+use ic_stable_structures::Cell;
+use lazy_static::lazy_static;
+
+use crate::{
+    memory::{LLM_MODEL_CONFIG_MEM_ID, MEMORY_MANAGER},
+    types::{LlmModelConfig, RepositoryError, RepositoryResult, VMemory},
+};
+
+thread_local! {
+    static LLM_MODEL_CONFIG: RefCell<Cell<LlmModelConfig, VMemory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(LLM_MODEL_CONFIG_MEM_ID)),
+            LlmModelConfig::default()
+        ).expect("failed to init LLM_MODEL_CONFIG")
+    );
+}
+
+lazy_static! {
+    pub static ref LLM_CONFIG_REPOSITORY: Arc<LlmConfigRepository> =
+        Arc::new(LlmConfigRepository::default());
+}
+
+#[derive(Debug, Default)]
+pub struct LlmConfigRepository;
+
+impl LlmConfigRepository {
+    pub fn get(&self) -> LlmModelConfig {
+        LLM_MODEL_CONFIG.with_borrow(|cell| cell.get().clone())
+    }
+
+    pub fn set(&self, config: LlmModelConfig) -> RepositoryResult<LlmModelConfig> {
+        LLM_MODEL_CONFIG.with_borrow_mut(|cell| {
+            cell.set(config.clone())
+                .map_err(|e| RepositoryError::IllegalUpdate {
+                    reason: format!("{:?}", e),
+                })
+        })?;
+        Ok(config)
+    }
+}
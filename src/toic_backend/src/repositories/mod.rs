@@ -1,3 +1,10 @@
+pub mod ai_quota;
+pub mod category;
 pub mod draft;
+pub mod library;
+pub mod llm;
+pub mod notification;
+pub mod statistics;
 pub mod story;
+pub mod treasury;
 pub mod user;
@@ -0,0 +1,109 @@
+use std::{cell::RefCell, sync::Arc};
+
+use candid::Principal;
+use ic_stable_structures::{BTreeMap, Cell};
+use lazy_static::lazy_static;
+
+use crate::{
+    memory::{ET_NOTIFICATION_MEM_ID, MEMORY_MANAGER, SERIAL_NOTIFICATION_MEM_ID},
+    structure::SerialIdRepository,
+    types::{BTreeMapRefCell, Notification, SerialRefCell, StorablePrincipal, VMemory},
+};
+
+thread_local! {
+    static NEXT_NOTIFICATION_ID: SerialRefCell = RefCell::new(Cell::init(
+        MEMORY_MANAGER.with_borrow(|m| m.get(SERIAL_NOTIFICATION_MEM_ID)), 1
+    ).expect("failed to init NEXT_NOTIFICATION_ID"));
+
+    // (recipient, notification id) -> notification
+    static NOTIFICATION: BTreeMapRefCell<(StorablePrincipal, u64), Notification> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(ET_NOTIFICATION_MEM_ID))
+        )
+    );
+}
+
+lazy_static! {
+    pub static ref NOTIFICATION_REPOSITORY: Arc<NotificationRepository> =
+        Arc::new(NotificationRepository::default());
+}
+
+#[derive(Debug, Default)]
+pub struct NotificationRepository;
+
+impl SerialIdRepository<VMemory> for NotificationRepository {
+    fn with_generator<F, R>(f: F) -> R
+    where
+        F: FnOnce(&mut Cell<u64, VMemory>) -> R,
+    {
+        NEXT_NOTIFICATION_ID.with_borrow_mut(f)
+    }
+}
+
+impl NotificationRepository {
+    /// Stores a notification in the inbox of its recipient, assigning its id.
+    /// The oldest notifications of the recipient beyond `retention` are dropped
+    pub fn insert(&self, mut notification: Notification, retention: usize) -> Notification {
+        let id = self.next_id();
+        notification.id = id;
+        let recipient = StorablePrincipal(notification.recipient);
+        NOTIFICATION.with_borrow_mut(|m| {
+            m.insert((recipient.clone(), id), notification.clone());
+            let expired = m
+                .range((recipient.clone(), 0)..=(recipient, u64::MAX))
+                .rev()
+                .skip(retention)
+                .map(|(k, _)| k)
+                .collect::<Vec<_>>();
+            for key in expired {
+                m.remove(&key);
+            }
+        });
+        notification
+    }
+
+    /// Gets the notifications of a recipient, latest first
+    pub fn find_by_recipient(
+        &self,
+        recipient: Principal,
+        cursor: Option<u64>,
+        limit: usize,
+    ) -> Vec<Notification> {
+        let until_id = cursor.map_or(u64::MAX, |c| c.saturating_sub(1));
+        let recipient = StorablePrincipal(recipient);
+        NOTIFICATION.with_borrow(|m| {
+            m.range((recipient.clone(), 0)..=(recipient, until_id))
+                .rev()
+                .take(limit)
+                .map(|(_, notification)| notification)
+                .collect()
+        })
+    }
+
+    /// Marks notifications of a recipient as read, all of them when no ids are given.
+    /// Returns the number of notifications changed
+    pub fn mark_read(&self, recipient: Principal, ids: Option<&[u64]>) -> usize {
+        let recipient = StorablePrincipal(recipient);
+        NOTIFICATION.with_borrow_mut(|m| {
+            let unread = m
+                .range((recipient.clone(), 0)..=(recipient, u64::MAX))
+                .filter(|(_, n)| !n.read && ids.is_none_or(|ids| ids.contains(&n.id)))
+                .collect::<Vec<_>>();
+            let changed = unread.len();
+            for (key, mut notification) in unread {
+                notification.read = true;
+                m.insert(key, notification);
+            }
+            changed
+        })
+    }
+
+    pub fn count_unread(&self, recipient: Principal) -> u64 {
+        let recipient = StorablePrincipal(recipient);
+        NOTIFICATION.with_borrow(|m| {
+            m.range((recipient.clone(), 0)..=(recipient, u64::MAX))
+                .filter(|(_, n)| !n.read)
+                .count() as u64
+        })
+    }
+}
@@ -0,0 +1,110 @@
+use std::{cell::RefCell, sync::Arc};
+
+use candid::Principal;
+use ic_stable_structures::Cell;
+use lazy_static::lazy_static;
+
+use crate::{
+    memory::{MEMORY_MANAGER, PLATFORM_COUNTERS_MEM_ID},
+    types::{Category, PlatformCounters, VMemory},
+};
+
+thread_local! {
+    static PLATFORM_COUNTERS: RefCell<Cell<PlatformCounters, VMemory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(PLATFORM_COUNTERS_MEM_ID)),
+            PlatformCounters::default()
+        ).expect("failed to init PLATFORM_COUNTERS")
+    );
+}
+
+lazy_static! {
+    pub static ref STATISTICS_REPOSITORY: Arc<StatisticsRepository> =
+        Arc::new(StatisticsRepository::default());
+}
+
+#[derive(Debug, Default)]
+pub struct StatisticsRepository;
+
+impl StatisticsRepository {
+    pub fn get(&self) -> PlatformCounters {
+        PLATFORM_COUNTERS.with_borrow(|cell| cell.get().clone())
+    }
+
+    pub fn set(&self, counters: PlatformCounters) {
+        PLATFORM_COUNTERS.with_borrow_mut(|cell| {
+            cell.set(counters)
+                .expect("failed to save PLATFORM_COUNTERS");
+        });
+    }
+
+    pub fn add_user(&self) {
+        self.update(|c| c.total_users = c.total_users.saturating_add(1));
+    }
+
+    pub fn add_category_followers(&self, user: Principal, categories: &[Category]) {
+        self.update(|c| {
+            if c.rebuild
+                .as_ref()
+                .is_some_and(|r| !r.is_user_counted(&user))
+            {
+                return;
+            }
+            for category in categories {
+                let followers = c.category_followers.entry(*category).or_default();
+                *followers = followers.saturating_add(1);
+            }
+        });
+    }
+
+    pub fn remove_category_followers(&self, user: Principal, categories: &[Category]) {
+        self.update(|c| {
+            if c.rebuild
+                .as_ref()
+                .is_some_and(|r| !r.is_user_counted(&user))
+            {
+                return;
+            }
+            for category in categories {
+                let followers = c.category_followers.entry(*category).or_default();
+                *followers = followers.saturating_sub(1);
+            }
+        });
+    }
+
+    pub fn add_draft(&self) {
+        self.update(|c| c.total_drafts = c.total_drafts.saturating_add(1));
+    }
+
+    pub fn remove_draft(&self) {
+        self.update(|c| c.total_drafts = c.total_drafts.saturating_sub(1));
+    }
+
+    pub fn add_story(&self, id: u64, category: Category) {
+        self.update(|c| {
+            c.total_stories = c.total_stories.saturating_add(1);
+            if c.rebuild.as_ref().is_some_and(|r| !r.is_story_counted(id)) {
+                return;
+            }
+            let stories = c.category_stories.entry(category).or_default();
+            *stories = stories.saturating_add(1);
+        });
+    }
+
+    pub fn remove_story(&self, id: u64, category: Category) {
+        self.update(|c| {
+            c.total_stories = c.total_stories.saturating_sub(1);
+            if c.rebuild.as_ref().is_some_and(|r| !r.is_story_counted(id)) {
+                return;
+            }
+            let stories = c.category_stories.entry(category).or_default();
+            *stories = stories.saturating_sub(1);
+        });
+    }
+
+    fn update<F: FnOnce(&mut PlatformCounters)>(&self, f: F) {
+        let mut counters = self.get();
+        f(&mut counters);
+        self.set(counters);
+    }
+}
@@ -2,13 +2,26 @@ use candid::Principal;
 use ic_stable_structures::{BTreeMap, Cell};
 use itertools::Itertools;
 use lazy_static::lazy_static;
+use std::collections::BTreeMap as HeapBTreeMap;
 use std::{cell::RefCell, cmp::Reverse, sync::Arc, u64};
 
 use crate::{
     memory::{
-        ET_STORY_CONTENT_MEM_ID, ET_STORY_MEM_ID, IDX_STORY_AUTHOR_MEM_ID,
-        IDX_STORY_CATEGORY_MEM_ID, IDX_STORY_SCORING_MEM_ID, IDX_STORY_SUPPORTER_MEM_ID,
-        MEMORY_MANAGER, SERIAL_STORY_MEM_ID,
+        ET_AUTHOR_ALL_TIME_ENGAGEMENT_MEM_ID, ET_MODERATION_QUEUE_MEM_ID,
+        ET_STORY_ALL_TIME_ENGAGEMENT_MEM_ID, ET_STORY_CONTENT_MEM_ID, ET_STORY_MEM_ID,
+        ET_STORY_TERMS_MEM_ID, IDX_AUTHOR_ALL_TIME_POINTS_MEM_ID,
+        IDX_AUTHOR_DAILY_ENGAGEMENT_MEM_ID, IDX_STORY_ALL_TIME_POINTS_MEM_ID,
+        IDX_STORY_AUTHOR_MEM_ID, IDX_STORY_CATEGORY_MEM_ID, IDX_STORY_CATEGORY_SCORE_MEM_ID,
+        IDX_STORY_DAILY_ACTIVITY_MEM_ID, IDX_STORY_DAILY_ENGAGEMENT_MEM_ID,
+        IDX_STORY_DAILY_VIEW_MEM_ID, IDX_STORY_LANGUAGE_MEM_ID, IDX_STORY_SCORING_MEM_ID,
+        IDX_STORY_SUPPORTER_MEM_ID, IDX_STORY_TAG_MEM_ID, IDX_STORY_TRANSLATION_MEM_ID,
+        IDX_TERM_DOC_FREQ_MEM_ID, IDX_TERM_STORY_MEM_ID, MEMORY_MANAGER, SERIAL_STORY_MEM_ID,
+        STORY_RANKING_CONFIG_MEM_ID,
+    },
+    repositories::{
+        category::CategoryRepository,
+        library::{BookmarkRepository, ReadingHistoryRepository},
+        statistics::StatisticsRepository,
     },
     structure::{
         AuditableRepository, BinaryTreeRepository, IndexRepository, IndexableRepository,
@@ -16,9 +29,12 @@ use crate::{
     },
     token::{StorableToken, Tokens},
     types::{
-        BTreeMapRefCell, Category, RepositoryError, RepositoryResult, Score, SerialRefCell,
-        SortOrder, StorablePrincipal, Story, StoryContent, SupportGiven, SupportSize, VMemory,
+        BTreeMapRefCell, Category, CategoryDefinition, CategoryInfo, Engagement, EngagementTotal,
+        ModerationEntry, RankingConfig, RepositoryError, RepositoryResult, Score, SerialRefCell,
+        SortOrder, StorablePrincipal, StorableTerm, Story, StoryActivity, StoryContent, StoryTerms,
+        SupportGiven, SupportSize, VMemory,
     },
+    utils::language_index_keys,
 };
 
 thread_local! {
@@ -50,6 +66,27 @@ thread_local! {
         )
     );
 
+    static STORY_TAG_INDEX: BTreeMapRefCell<(StorableTerm, Reverse<u64>), ()> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(IDX_STORY_TAG_MEM_ID))
+        )
+    );
+
+    // (language, (category, Reverse(story id))), nested as the triple codec expects its middle
+    // element padded to the fixed size `Category` declares, which only the pair codec does
+    static STORY_LANGUAGE_INDEX: BTreeMapRefCell<(StorableTerm, (Category, Reverse<u64>)), ()> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(IDX_STORY_LANGUAGE_MEM_ID))
+        )
+    );
+
+    // (original story id, translation story id)
+    static STORY_TRANSLATION_INDEX: BTreeMapRefCell<(u64, u64), ()> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(IDX_STORY_TRANSLATION_MEM_ID))
+        )
+    );
+
     static STORY_SUPPORTER_INDEX: BTreeMapRefCell<(u64, StorablePrincipal), SupportGiven> = RefCell::new(
         BTreeMap::init(
             MEMORY_MANAGER.with_borrow(|m| m.get(IDX_STORY_SUPPORTER_MEM_ID))
@@ -62,6 +99,104 @@ thread_local! {
         )
     );
 
+    // (category, (score, story id))
+    static STORY_CATEGORY_SCORE_INDEX: BTreeMapRefCell<(Category, (Score, u64)), ()> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(IDX_STORY_CATEGORY_SCORE_MEM_ID))
+        )
+    );
+
+    static MODERATION_QUEUE: BTreeMapRefCell<u64, ModerationEntry> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(ET_MODERATION_QUEUE_MEM_ID))
+        )
+    );
+
+    static STORY_TERMS: BTreeMapRefCell<u64, StoryTerms> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(ET_STORY_TERMS_MEM_ID))
+        )
+    );
+
+    // (term, story id) -> occurrences
+    static TERM_STORY_INDEX: BTreeMapRefCell<(StorableTerm, u64), u32> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(IDX_TERM_STORY_MEM_ID))
+        )
+    );
+
+    // term -> number of stories containing it
+    static TERM_DOC_FREQ: BTreeMapRefCell<StorableTerm, u64> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(IDX_TERM_DOC_FREQ_MEM_ID))
+        )
+    );
+
+    static RANKING_CONFIG: RefCell<Cell<RankingConfig, VMemory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(STORY_RANKING_CONFIG_MEM_ID)),
+            RankingConfig::default()
+        ).expect("failed to init RANKING_CONFIG")
+    );
+
+    // (day index, story id) -> engagement received that day
+    static STORY_DAILY_ENGAGEMENT: BTreeMapRefCell<(u64, u64), Engagement> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(IDX_STORY_DAILY_ENGAGEMENT_MEM_ID))
+        )
+    );
+
+    // (day index, author) -> engagement received that day
+    static AUTHOR_DAILY_ENGAGEMENT: BTreeMapRefCell<(u64, StorablePrincipal), Engagement> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(IDX_AUTHOR_DAILY_ENGAGEMENT_MEM_ID))
+        )
+    );
+
+    // (story id, day index) -> activity of that day, kept for the author analytics
+    static STORY_DAILY_ACTIVITY: BTreeMapRefCell<(u64, u64), StoryActivity> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(IDX_STORY_DAILY_ACTIVITY_MEM_ID))
+        )
+    );
+
+    // story id -> all-time engagement, kept apart from the story to index its points
+    static STORY_ALL_TIME_ENGAGEMENT: BTreeMapRefCell<u64, EngagementTotal> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(ET_STORY_ALL_TIME_ENGAGEMENT_MEM_ID))
+        )
+    );
+
+    // (Reverse(points), story id)
+    static STORY_ALL_TIME_POINTS_INDEX: BTreeMapRefCell<(Reverse<u64>, u64), ()> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(IDX_STORY_ALL_TIME_POINTS_MEM_ID))
+        )
+    );
+
+    // author -> all-time engagement received by their stories
+    static AUTHOR_ALL_TIME_ENGAGEMENT: BTreeMapRefCell<StorablePrincipal, EngagementTotal> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(ET_AUTHOR_ALL_TIME_ENGAGEMENT_MEM_ID))
+        )
+    );
+
+    // (Reverse(points), author)
+    static AUTHOR_ALL_TIME_POINTS_INDEX: BTreeMapRefCell<(Reverse<u64>, StorablePrincipal), ()> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(IDX_AUTHOR_ALL_TIME_POINTS_MEM_ID))
+        )
+    );
+
+    // (day index, story id, viewer), a view counts once per viewer and day
+    static STORY_DAILY_VIEW: BTreeMapRefCell<(u64, u64, StorablePrincipal), ()> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(IDX_STORY_DAILY_VIEW_MEM_ID))
+        )
+    );
+
+    // next story id visited by the rank refresh, heap only as a restart from 1 is harmless
+    static RANK_REFRESH_CURSOR: std::cell::Cell<u64> = const { std::cell::Cell::new(1) };
 }
 
 lazy_static! {
@@ -103,6 +238,39 @@ impl IndexRepository<(Score, u64), u64, VMemory> for StoryScoringSortIndexReposi
     }
 }
 
+#[derive(Debug, Default)]
+pub struct StoryCategoryScoreIndexRepository;
+
+impl IndexRepository<(Category, (Score, u64)), u64, VMemory> for StoryCategoryScoreIndexRepository {
+    type Criteria = Category;
+    type Cursor = (Score, u64); // (score, story_id)
+
+    fn with_ref<F, R>(f: F) -> R
+    where
+        F: FnOnce(&RefCell<BTreeMap<(Category, (Score, u64)), (), VMemory>>) -> R,
+    {
+        STORY_CATEGORY_SCORE_INDEX.with(f)
+    }
+
+    fn find(
+        &self,
+        criteria: Self::Criteria,
+        _: Option<SortOrder>,
+        cursor: Option<Self::Cursor>,
+        limit: usize,
+    ) -> Vec<u64> {
+        let start = (criteria, (0, 0));
+        let end = (criteria, cursor.unwrap_or((u64::MAX, u64::MAX)));
+        STORY_CATEGORY_SCORE_INDEX.with_borrow(|m| {
+            m.range(start..end)
+                .rev()
+                .take(limit)
+                .map(|((_, (_, k)), _)| k)
+                .collect()
+        })
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct StoryCategoryIndexRepository;
 
@@ -173,6 +341,112 @@ impl IndexRepository<(StorablePrincipal, Reverse<u64>), u64, VMemory>
     }
 }
 
+#[derive(Debug, Default)]
+pub struct StoryTagIndexRepository;
+
+impl IndexRepository<(StorableTerm, Reverse<u64>), u64, VMemory> for StoryTagIndexRepository {
+    type Criteria = String;
+    type Cursor = u64;
+
+    fn with_ref<F, R>(f: F) -> R
+    where
+        F: FnOnce(&RefCell<BTreeMap<(StorableTerm, Reverse<u64>), (), VMemory>>) -> R,
+    {
+        STORY_TAG_INDEX.with(f)
+    }
+
+    fn find(
+        &self,
+        criteria: Self::Criteria,
+        _: Option<SortOrder>,
+        cursor: Option<Self::Cursor>,
+        limit: usize,
+    ) -> Vec<u64> {
+        // default sort order is descending by Id (latest first)
+        let until_id = cursor.map_or(u64::MAX, |c| c.saturating_sub(1));
+        let start = (StorableTerm(criteria.clone()), Reverse(until_id));
+        let end = (StorableTerm(criteria), Reverse(1));
+        STORY_TAG_INDEX.with_borrow(|m| {
+            m.range(start..=end)
+                .take(limit)
+                .map(|((_, k), _)| k.0)
+                .collect()
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct StoryLanguageIndexRepository;
+
+impl IndexRepository<(StorableTerm, (Category, Reverse<u64>)), u64, VMemory>
+    for StoryLanguageIndexRepository
+{
+    type Criteria = (String, Category);
+    type Cursor = u64;
+
+    fn with_ref<F, R>(f: F) -> R
+    where
+        F: FnOnce(&RefCell<BTreeMap<(StorableTerm, (Category, Reverse<u64>)), (), VMemory>>) -> R,
+    {
+        STORY_LANGUAGE_INDEX.with(f)
+    }
+
+    fn find(
+        &self,
+        criteria: Self::Criteria,
+        _: Option<SortOrder>,
+        cursor: Option<Self::Cursor>,
+        limit: usize,
+    ) -> Vec<u64> {
+        // default sort order is descending by Id (latest first)
+        let (language, category) = criteria;
+        let until_id = cursor.map_or(u64::MAX, |c| c.saturating_sub(1));
+        let start = (
+            StorableTerm(language.clone()),
+            (category, Reverse(until_id)),
+        );
+        let end = (StorableTerm(language), (category, Reverse(1)));
+        STORY_LANGUAGE_INDEX.with_borrow(|m| {
+            m.range(start..=end)
+                .take(limit)
+                .map(|((_, (_, k)), _)| k.0)
+                .collect()
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct StoryTranslationIndexRepository;
+
+impl IndexRepository<(u64, u64), u64, VMemory> for StoryTranslationIndexRepository {
+    type Criteria = u64;
+    type Cursor = u64;
+
+    fn with_ref<F, R>(f: F) -> R
+    where
+        F: FnOnce(&RefCell<BTreeMap<(u64, u64), (), VMemory>>) -> R,
+    {
+        STORY_TRANSLATION_INDEX.with(f)
+    }
+
+    /// Finds the translations of an original story, oldest first
+    fn find(
+        &self,
+        criteria: Self::Criteria,
+        _: Option<SortOrder>,
+        cursor: Option<Self::Cursor>,
+        limit: usize,
+    ) -> Vec<u64> {
+        let from_id = cursor.map_or(0, |c| c.saturating_add(1));
+        STORY_TRANSLATION_INDEX.with_borrow(|m| {
+            m.range((criteria, from_id)..=(criteria, u64::MAX))
+                .take(limit)
+                .map(|((_, k), _)| k)
+                .collect()
+        })
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct StoryContentRepository;
 
@@ -209,8 +483,20 @@ impl Repository<u64, StoryContent, VMemory> for StoryContentRepository {
 pub struct StoryRepository {
     category_index: StoryCategoryIndexRepository,
     author_index: StoryAuthorIndexRepository,
+    tag_index: StoryTagIndexRepository,
+    language_index: StoryLanguageIndexRepository,
+    translation_index: StoryTranslationIndexRepository,
     scoring_index: StoryScoringSortIndexRepository,
+    category_score_index: StoryCategoryScoreIndexRepository,
     supporter: StorySupporterRepository,
+    moderation: StoryModerationRepository,
+    terms: StoryTermsRepository,
+    ranking: StoryRankingRepository,
+    engagement: StoryEngagementRepository,
+    statistics: StatisticsRepository,
+    bookmark: BookmarkRepository,
+    reading_history: ReadingHistoryRepository,
+    category: CategoryRepository,
 }
 
 impl SerialIdRepository<VMemory> for StoryRepository {
@@ -238,6 +524,11 @@ impl AuditableRepository<Story, VMemory> for StoryRepository {
         if let Some(old_value) = old {
             self.remove_indexes(&old_value);
             self.supporter.remove_story_supporter(id.clone());
+            self.moderation.remove(*id);
+            self.terms.remove(*id);
+            self.bookmark.remove_story(*id);
+            self.engagement.remove_story(*id);
+            self.reading_history.remove_story(*id);
             Ok(id.clone())
         } else {
             Err(RepositoryError::NotFound)
@@ -251,7 +542,24 @@ impl IndexableRepository<Story> for StoryRepository {
             .remove(&(value.detail.category, Reverse(value.id)));
         self.author_index
             .remove(&(StorablePrincipal(value.author), Reverse(value.id)));
+        for tag in &value.detail.tags {
+            self.tag_index
+                .remove(&(StorableTerm(tag.clone()), Reverse(value.id)));
+        }
+        for language in language_index_keys(value.detail.language.as_deref()) {
+            self.language_index.remove(&(
+                StorableTerm(language),
+                (value.detail.category, Reverse(value.id)),
+            ));
+        }
+        if let Some(original) = value.detail.translation_of {
+            self.translation_index.remove(&(original, value.id));
+        }
         self.scoring_index.remove(&(value.score, value.id));
+        self.category_score_index
+            .remove(&(value.detail.category, (value.score, value.id)));
+        self.statistics
+            .remove_story(value.id, value.detail.category);
     }
 
     fn add_indexes(&self, value: &Story) {
@@ -259,17 +567,311 @@ impl IndexableRepository<Story> for StoryRepository {
             .insert((value.detail.category, Reverse(value.id)));
         self.author_index
             .insert((StorablePrincipal(value.author), Reverse(value.id)));
+        for tag in &value.detail.tags {
+            self.tag_index
+                .insert((StorableTerm(tag.clone()), Reverse(value.id)));
+        }
+        self.index_language(value);
+        if let Some(original) = value.detail.translation_of {
+            self.translation_index.insert((original, value.id));
+        }
         self.scoring_index.insert((value.score, value.id));
+        self.index_category_score(value);
+        self.statistics.add_story(value.id, value.detail.category);
     }
 
     fn clear_indexes(&self) {
         self.category_index.clear();
         self.author_index.clear();
+        self.tag_index.clear();
+        self.language_index.clear();
+        self.translation_index.clear();
         self.scoring_index.clear();
+        self.category_score_index.clear();
     }
 }
 
 impl StoryRepository {
+    pub fn get_ranking_config(&self) -> RankingConfig {
+        self.ranking.get_config()
+    }
+
+    pub fn set_ranking_config(&self, config: RankingConfig) -> RepositoryResult<RankingConfig> {
+        self.ranking.set_config(config)
+    }
+
+    pub fn get_rank_refresh_cursor(&self) -> u64 {
+        self.ranking.refresh_cursor()
+    }
+
+    pub fn set_rank_refresh_cursor(&self, cursor: u64) {
+        self.ranking.set_refresh_cursor(cursor);
+    }
+
+    /// Indexes the score of a story within its category, also used to index the stories ranked
+    /// before the index existed
+    pub fn index_category_score(&self, story: &Story) {
+        self.category_score_index
+            .insert((story.detail.category, (story.score, story.id)));
+    }
+
+    /// Replaces the score of a story without touching its update time
+    pub fn set_story_score(&self, id: u64, score: Score) -> RepositoryResult<Story> {
+        let mut story = self.get(&id).ok_or(RepositoryError::NotFound)?;
+        if story.score != score {
+            self.scoring_index.remove(&(story.score, id));
+            self.category_score_index
+                .remove(&(story.detail.category, (story.score, id)));
+            story.score = score;
+            STORY.with_borrow_mut(|m| m.insert(id, story.clone()));
+            self.scoring_index.insert((score, id));
+            self.index_category_score(&story);
+        }
+        Ok(story)
+    }
+
+    /// Indexes a story under its language tags, also used to index the stories published
+    /// before the language index
+    pub fn index_language(&self, story: &Story) {
+        for language in language_index_keys(story.detail.language.as_deref()) {
+            self.language_index.insert((
+                StorableTerm(language),
+                (story.detail.category, Reverse(story.id)),
+            ));
+        }
+    }
+
+    /// Replaces the language of a story without touching its update time
+    pub fn set_story_language(&self, id: u64, language: String) -> RepositoryResult<Story> {
+        let mut story = self.get(&id).ok_or(RepositoryError::NotFound)?;
+        if story.detail.language.as_ref() != Some(&language) {
+            self.remove_indexes(&story);
+            story.detail.language = Some(language);
+            STORY.with_borrow_mut(|m| m.insert(id, story.clone()));
+            self.add_indexes(&story);
+        }
+        Ok(story)
+    }
+
+    /// Counts one more view without touching the update time
+    pub fn increment_story_views(&self, id: u64) -> RepositoryResult<Story> {
+        let mut story = self.get(&id).ok_or(RepositoryError::NotFound)?;
+        story.total_views = story.total_views.saturating_add(1);
+        STORY.with_borrow_mut(|m| m.insert(id, story.clone()));
+        Ok(story)
+    }
+
+    /// Adds engagement to the daily buckets of the story and of its author
+    pub fn record_engagement(
+        &self,
+        day: u64,
+        id: u64,
+        author: Principal,
+        engagement: &Engagement,
+        new_supporter: bool,
+    ) -> RepositoryResult<()> {
+        if !self.exists(&id) {
+            return Err(RepositoryError::NotFound);
+        }
+        self.engagement.record(day, id, author, engagement);
+        self.engagement
+            .record_activity(day, id, engagement, new_supporter);
+        Ok(())
+    }
+
+    /// Gets the daily activity of a story between two day indexes, both inclusive
+    pub fn get_story_activity(
+        &self,
+        id: u64,
+        from_day: u64,
+        to_day: u64,
+    ) -> Vec<(u64, StoryActivity)> {
+        self.engagement.activity(id, from_day, to_day)
+    }
+
+    /// Sums the engagement of every story from the bucket of `from_day` onwards
+    pub fn get_story_engagement_since(&self, from_day: u64) -> HeapBTreeMap<u64, Engagement> {
+        self.engagement.stories_since(from_day)
+    }
+
+    /// Sums the engagement of every author from the bucket of `from_day` onwards
+    pub fn get_author_engagement_since(
+        &self,
+        from_day: u64,
+    ) -> HeapBTreeMap<Principal, Engagement> {
+        self.engagement.authors_since(from_day)
+    }
+
+    /// Removes the buckets older than `day`, returns the number of buckets removed
+    pub fn prune_engagement_before(&self, day: u64) -> usize {
+        self.engagement.prune_before(day)
+    }
+
+    /// Replaces the all-time engagement a story is indexed under and carries the difference over
+    /// to its author. Returns the new all-time engagement of the author
+    pub fn set_story_all_time_engagement(
+        &self,
+        id: u64,
+        author: Principal,
+        total: EngagementTotal,
+    ) -> Engagement {
+        self.engagement.set_story_total(id, author, total)
+    }
+
+    pub fn set_author_all_time_points(&self, author: Principal, points: u64) {
+        self.engagement.set_author_points(author, points);
+    }
+
+    /// Gets the stories with the most all-time points as (story id, total), highest first
+    pub fn get_top_story_totals(&self, limit: usize) -> Vec<(u64, EngagementTotal)> {
+        self.engagement.top_stories(limit)
+    }
+
+    /// Gets the authors with the most all-time points as (author, total), highest first
+    pub fn get_top_author_totals(&self, limit: usize) -> Vec<(Principal, EngagementTotal)> {
+        self.engagement.top_authors(limit)
+    }
+
+    /// Records that a viewer read a story on a day, returns false when they already did
+    pub fn record_story_view(&self, day: u64, id: u64, viewer: Principal) -> bool {
+        self.engagement.record_view(day, id, viewer)
+    }
+
+    /// Removes the recorded views older than `day`, returns the number of views removed
+    pub fn prune_story_views_before(&self, day: u64) -> usize {
+        self.engagement.prune_views_before(day)
+    }
+
+    /// Gets the stories in ascending order of id, starting from `start`
+    pub fn get_stories_from(&self, start: u64, limit: usize) -> Vec<Story> {
+        STORY.with_borrow(|m| m.range(start..).take(limit).map(|(_, s)| s).collect())
+    }
+
+    /// Replaces the terms of a story, keeping the term frequencies up to date
+    pub fn save_story_terms(&self, id: u64, terms: StoryTerms) -> RepositoryResult<()> {
+        if !self.exists(&id) {
+            return Err(RepositoryError::NotFound);
+        }
+        self.terms.remove(id);
+        self.terms.insert(id, terms);
+        Ok(())
+    }
+
+    pub fn get_story_terms(&self, id: u64) -> Option<StoryTerms> {
+        self.terms.get(id)
+    }
+
+    /// Gets the ids of at most `limit` stories containing the term, latest first
+    pub fn find_stories_by_term(&self, term: &str, limit: usize) -> Vec<u64> {
+        self.terms.find_by_term(term, limit)
+    }
+
+    pub fn get_term_frequency(&self, term: &str) -> u64 {
+        self.terms.document_frequency(term)
+    }
+
+    pub fn count_stories_with_terms(&self) -> u64 {
+        self.terms.count()
+    }
+
+    pub fn queue_moderation(&self, entry: ModerationEntry) -> RepositoryResult<()> {
+        if !self.exists(&entry.story_id) {
+            return Err(RepositoryError::NotFound);
+        }
+        self.moderation.insert(entry);
+        Ok(())
+    }
+
+    pub fn get_moderation_queue(&self) -> Vec<ModerationEntry> {
+        self.moderation.get_all()
+    }
+
+    pub fn remove_moderation(&self, id: u64) -> RepositoryResult<ModerationEntry> {
+        self.moderation.remove(id).ok_or(RepositoryError::NotFound)
+    }
+
+    pub fn get_stories_by_tag(
+        &self,
+        tag: String,
+        cursor: Option<u64>,
+        limit: usize,
+    ) -> RepositoryResult<Vec<Story>> {
+        let stories = self
+            .tag_index
+            .find(tag, None, cursor, limit)
+            .into_iter()
+            .filter_map(|id| self.get(&id))
+            .collect();
+        Ok(stories)
+    }
+
+    /// Gets the stories of a category written in any of the languages, latest first
+    pub fn get_stories_by_languages(
+        &self,
+        languages: &[String],
+        category: Category,
+        cursor: Option<u64>,
+        limit: usize,
+    ) -> RepositoryResult<Vec<Story>> {
+        let stories = languages
+            .iter()
+            .flat_map(|language| {
+                self.language_index
+                    .find((language.clone(), category), None, cursor, limit)
+            })
+            .sorted_unstable_by(|a, b| b.cmp(a))
+            .dedup()
+            .take(limit)
+            .filter_map(|id| self.get(&id))
+            .collect();
+        Ok(stories)
+    }
+
+    /// Gets the ids of the translations of an original story, oldest first
+    pub fn get_translation_ids(&self, original: u64, limit: usize) -> Vec<u64> {
+        self.translation_index.find(original, None, None, limit)
+    }
+
+    pub fn is_category_available(&self, category: &Category) -> bool {
+        self.category.is_available(category)
+    }
+
+    /// Gets the built-in categories followed by the active custom ones
+    pub fn get_categories(&self) -> Vec<CategoryInfo> {
+        Category::BUILT_IN
+            .iter()
+            .map(|category| CategoryInfo {
+                category: *category,
+                name: format!("{:?}", category),
+            })
+            .chain(
+                self.category
+                    .get_all()
+                    .into_iter()
+                    .filter(|c| c.active)
+                    .map(|c| CategoryInfo {
+                        category: Category::Custom(c.id),
+                        name: c.name,
+                    }),
+            )
+            .collect()
+    }
+
+    pub fn get_category_definition(&self, id: u16) -> Option<CategoryDefinition> {
+        self.category.get(id)
+    }
+
+    pub fn add_category(&self, name: String, now: u64) -> RepositoryResult<CategoryDefinition> {
+        self.category.insert(name, now)
+    }
+
+    pub fn update_category(
+        &self,
+        definition: CategoryDefinition,
+    ) -> RepositoryResult<CategoryDefinition> {
+        self.category.update(definition)
+    }
+
     pub fn get_stories_by_author(
         &self,
         author: Principal,
@@ -341,6 +943,28 @@ impl StoryRepository {
         Ok(stories)
     }
 
+    /// Gets the highest scored stories of the categories, the cursor is the (score, id) of the
+    /// last story of the previous page
+    pub fn get_stories_by_category_score(
+        &self,
+        categories: &[Category],
+        cursor: Option<(Score, u64)>,
+        limit: usize,
+    ) -> Vec<Story> {
+        categories
+            .iter()
+            .sorted()
+            .dedup()
+            .flat_map(|category| {
+                self.category_score_index
+                    .find(*category, None, cursor, limit)
+            })
+            .filter_map(|id| self.get(&id))
+            .sorted_unstable_by_key(|s| Reverse((s.score, s.id)))
+            .take(limit)
+            .collect()
+    }
+
     pub fn get_story_supporters(
         &self,
         id: u64,
@@ -457,3 +1081,282 @@ impl StorySupporterRepository {
         });
     }
 }
+
+#[derive(Debug, Default)]
+pub struct StoryModerationRepository;
+
+impl StoryModerationRepository {
+    fn insert(&self, entry: ModerationEntry) {
+        MODERATION_QUEUE.with_borrow_mut(|m| m.insert(entry.story_id, entry));
+    }
+
+    fn get_all(&self) -> Vec<ModerationEntry> {
+        MODERATION_QUEUE.with_borrow(|m| m.values().collect())
+    }
+
+    fn remove(&self, id: u64) -> Option<ModerationEntry> {
+        MODERATION_QUEUE.with_borrow_mut(|m| m.remove(&id))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct StoryTermsRepository;
+
+impl StoryTermsRepository {
+    fn get(&self, id: u64) -> Option<StoryTerms> {
+        STORY_TERMS.with_borrow(|m| m.get(&id))
+    }
+
+    fn count(&self) -> u64 {
+        STORY_TERMS.with_borrow(|m| m.len())
+    }
+
+    fn insert(&self, id: u64, terms: StoryTerms) {
+        TERM_STORY_INDEX.with_borrow_mut(|index| {
+            TERM_DOC_FREQ.with_borrow_mut(|freq| {
+                for (term, occurrences) in &terms.terms {
+                    let term = StorableTerm(term.clone());
+                    index.insert((term.clone(), id), *occurrences);
+                    let count = freq.get(&term).unwrap_or_default();
+                    freq.insert(term, count + 1);
+                }
+            })
+        });
+        STORY_TERMS.with_borrow_mut(|m| m.insert(id, terms));
+    }
+
+    fn remove(&self, id: u64) {
+        let Some(old) = STORY_TERMS.with_borrow_mut(|m| m.remove(&id)) else {
+            return;
+        };
+        TERM_STORY_INDEX.with_borrow_mut(|index| {
+            TERM_DOC_FREQ.with_borrow_mut(|freq| {
+                for (term, _) in old.terms {
+                    let term = StorableTerm(term);
+                    index.remove(&(term.clone(), id));
+                    match freq.get(&term) {
+                        Some(count) if count > 1 => {
+                            freq.insert(term, count - 1);
+                        }
+                        _ => {
+                            freq.remove(&term);
+                        }
+                    }
+                }
+            })
+        });
+    }
+
+    fn find_by_term(&self, term: &str, limit: usize) -> Vec<u64> {
+        let term = StorableTerm(term.to_string());
+        TERM_STORY_INDEX.with_borrow(|m| {
+            m.range((term.clone(), 0)..=(term, u64::MAX))
+                .rev()
+                .take(limit)
+                .map(|((_, id), _)| id)
+                .collect()
+        })
+    }
+
+    fn document_frequency(&self, term: &str) -> u64 {
+        TERM_DOC_FREQ.with_borrow(|m| m.get(&StorableTerm(term.to_string())).unwrap_or_default())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct StoryRankingRepository;
+
+impl StoryRankingRepository {
+    fn get_config(&self) -> RankingConfig {
+        RANKING_CONFIG.with_borrow(|cell| cell.get().clone())
+    }
+
+    fn set_config(&self, config: RankingConfig) -> RepositoryResult<RankingConfig> {
+        RANKING_CONFIG.with_borrow_mut(|cell| {
+            cell.set(config.clone())
+                .map_err(|e| RepositoryError::IllegalUpdate {
+                    reason: format!("{:?}", e),
+                })
+        })?;
+        Ok(config)
+    }
+
+    fn refresh_cursor(&self) -> u64 {
+        RANK_REFRESH_CURSOR.get()
+    }
+
+    fn set_refresh_cursor(&self, cursor: u64) {
+        RANK_REFRESH_CURSOR.set(cursor);
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct StoryEngagementRepository;
+
+impl StoryEngagementRepository {
+    fn record(&self, day: u64, id: u64, author: Principal, engagement: &Engagement) {
+        STORY_DAILY_ENGAGEMENT.with_borrow_mut(|m| {
+            let mut current = m.get(&(day, id)).unwrap_or_default();
+            current.merge(engagement);
+            m.insert((day, id), current);
+        });
+        AUTHOR_DAILY_ENGAGEMENT.with_borrow_mut(|m| {
+            let key = (day, StorablePrincipal(author));
+            let mut current = m.get(&key).unwrap_or_default();
+            current.merge(engagement);
+            m.insert(key, current);
+        });
+    }
+
+    fn record_activity(&self, day: u64, id: u64, engagement: &Engagement, new_supporter: bool) {
+        STORY_DAILY_ACTIVITY.with_borrow_mut(|m| {
+            let mut current = m.get(&(id, day)).unwrap_or_default();
+            current.record(engagement, new_supporter);
+            m.insert((id, day), current);
+        });
+    }
+
+    fn activity(&self, id: u64, from_day: u64, to_day: u64) -> Vec<(u64, StoryActivity)> {
+        STORY_DAILY_ACTIVITY.with_borrow(|m| {
+            m.range((id, from_day)..=(id, to_day))
+                .map(|((_, day), activity)| (day, activity))
+                .collect()
+        })
+    }
+
+    fn stories_since(&self, from_day: u64) -> HeapBTreeMap<u64, Engagement> {
+        let mut totals = HeapBTreeMap::<u64, Engagement>::new();
+        STORY_DAILY_ENGAGEMENT.with_borrow(|m| {
+            for ((_, id), engagement) in m.range((from_day, 0)..) {
+                totals.entry(id).or_default().merge(&engagement);
+            }
+        });
+        totals
+    }
+
+    fn authors_since(&self, from_day: u64) -> HeapBTreeMap<Principal, Engagement> {
+        let mut totals = HeapBTreeMap::<Principal, Engagement>::new();
+        let start = (
+            from_day,
+            StorablePrincipal(Principal::management_canister()),
+        );
+        AUTHOR_DAILY_ENGAGEMENT.with_borrow(|m| {
+            for ((_, author), engagement) in m.range(start..) {
+                totals.entry(author.0).or_default().merge(&engagement);
+            }
+        });
+        totals
+    }
+
+    fn set_story_total(&self, id: u64, author: Principal, total: EngagementTotal) -> Engagement {
+        let old = STORY_ALL_TIME_ENGAGEMENT
+            .with_borrow_mut(|m| m.insert(id, total.clone()))
+            .unwrap_or_default();
+        STORY_ALL_TIME_POINTS_INDEX.with_borrow_mut(|m| {
+            m.remove(&(Reverse(old.points), id));
+            m.insert((Reverse(total.points), id), ());
+        });
+        AUTHOR_ALL_TIME_ENGAGEMENT.with_borrow_mut(|m| {
+            let key = StorablePrincipal(author);
+            let mut author_total = m.get(&key).unwrap_or_default();
+            author_total.engagement.subtract(&old.engagement);
+            author_total.engagement.merge(&total.engagement);
+            m.insert(key, author_total.clone());
+            author_total.engagement
+        })
+    }
+
+    fn set_author_points(&self, author: Principal, points: u64) {
+        let key = StorablePrincipal(author);
+        let mut total = AUTHOR_ALL_TIME_ENGAGEMENT
+            .with_borrow(|m| m.get(&key))
+            .unwrap_or_default();
+        AUTHOR_ALL_TIME_POINTS_INDEX.with_borrow_mut(|m| {
+            m.remove(&(Reverse(total.points), key.clone()));
+            m.insert((Reverse(points), key.clone()), ());
+        });
+        total.points = points;
+        AUTHOR_ALL_TIME_ENGAGEMENT.with_borrow_mut(|m| m.insert(key, total));
+    }
+
+    fn top_stories(&self, limit: usize) -> Vec<(u64, EngagementTotal)> {
+        let ids = STORY_ALL_TIME_POINTS_INDEX.with_borrow(|m| {
+            m.iter()
+                .take_while(|((points, _), _)| points.0 > 0)
+                .take(limit)
+                .map(|((_, id), _)| id)
+                .collect_vec()
+        });
+        STORY_ALL_TIME_ENGAGEMENT.with_borrow(|m| {
+            ids.into_iter()
+                .filter_map(|id| m.get(&id).map(|total| (id, total)))
+                .collect()
+        })
+    }
+
+    fn top_authors(&self, limit: usize) -> Vec<(Principal, EngagementTotal)> {
+        let authors = AUTHOR_ALL_TIME_POINTS_INDEX.with_borrow(|m| {
+            m.iter()
+                .take_while(|((points, _), _)| points.0 > 0)
+                .take(limit)
+                .map(|((_, author), _)| author)
+                .collect_vec()
+        });
+        AUTHOR_ALL_TIME_ENGAGEMENT.with_borrow(|m| {
+            authors
+                .into_iter()
+                .filter_map(|author| m.get(&author).map(|total| (author.0, total)))
+                .collect()
+        })
+    }
+
+    /// Drops a deleted story from the all-time index, its author keeps the engagement received
+    /// like the daily author buckets do
+    fn remove_story(&self, id: u64) {
+        if let Some(old) = STORY_ALL_TIME_ENGAGEMENT.with_borrow_mut(|m| m.remove(&id)) {
+            STORY_ALL_TIME_POINTS_INDEX.with_borrow_mut(|m| m.remove(&(Reverse(old.points), id)));
+        }
+    }
+
+    fn record_view(&self, day: u64, id: u64, viewer: Principal) -> bool {
+        STORY_DAILY_VIEW
+            .with_borrow_mut(|m| m.insert((day, id, StorablePrincipal(viewer)), ()))
+            .is_none()
+    }
+
+    fn prune_views_before(&self, day: u64) -> usize {
+        STORY_DAILY_VIEW.with_borrow_mut(|m| {
+            let keys = m
+                .iter()
+                .map(|(k, _)| k)
+                .take_while(|(d, _, _)| *d < day)
+                .collect_vec();
+            keys.iter().for_each(|k| {
+                m.remove(k);
+            });
+            keys.len()
+        })
+    }
+
+    fn prune_before(&self, day: u64) -> usize {
+        let stories = STORY_DAILY_ENGAGEMENT.with_borrow_mut(|m| {
+            let keys = m.range(..(day, 0)).map(|(k, _)| k).collect_vec();
+            keys.iter().for_each(|k| {
+                m.remove(k);
+            });
+            keys.len()
+        });
+        let authors = AUTHOR_DAILY_ENGAGEMENT.with_borrow_mut(|m| {
+            let keys = m
+                .iter()
+                .map(|(k, _)| k)
+                .take_while(|(d, _)| *d < day)
+                .collect_vec();
+            keys.iter().for_each(|k| {
+                m.remove(k);
+            });
+            keys.len()
+        });
+        stories + authors
+    }
+}
@@ -0,0 +1,192 @@
+use std::{cell::RefCell, sync::Arc};
+
+use candid::Principal;
+use ic_stable_structures::{BTreeMap, Cell};
+use lazy_static::lazy_static;
+
+use crate::{
+    memory::{
+        ET_PENDING_ENGAGEMENT_MEM_ID, ET_REWARD_ROUND_MEM_ID, IDX_REWARD_ALLOCATION_MEM_ID,
+        MEMORY_MANAGER, SERIAL_REWARD_ROUND_MEM_ID, TREASURY_STATE_MEM_ID,
+    },
+    structure::{
+        AuditableRepository, BinaryTreeRepository, IndexableRepository, SerialIdRepository,
+    },
+    types::{
+        BTreeMapRefCell, Engagement, RepositoryError, RepositoryResult, RewardAllocation,
+        RewardRound, SerialRefCell, StorablePrincipal, TreasuryState, VMemory,
+    },
+};
+
+thread_local! {
+    static TREASURY_STATE: RefCell<Cell<TreasuryState, VMemory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(TREASURY_STATE_MEM_ID)),
+            TreasuryState::default()
+        ).expect("failed to init TREASURY_STATE")
+    );
+
+    static NEXT_REWARD_ROUND_ID: SerialRefCell = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(SERIAL_REWARD_ROUND_MEM_ID)), 1
+        ).expect("failed to init NEXT_REWARD_ROUND_ID")
+    );
+
+    static REWARD_ROUND: BTreeMapRefCell<u64, RewardRound> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(ET_REWARD_ROUND_MEM_ID))
+        )
+    );
+
+    static REWARD_ALLOCATION: BTreeMapRefCell<(u64, StorablePrincipal), RewardAllocation> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(IDX_REWARD_ALLOCATION_MEM_ID))
+        )
+    );
+
+    static PENDING_ENGAGEMENT: BTreeMapRefCell<StorablePrincipal, Engagement> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(ET_PENDING_ENGAGEMENT_MEM_ID))
+        )
+    );
+}
+
+lazy_static! {
+    pub static ref TREASURY_STATE_REPOSITORY: Arc<TreasuryStateRepository> =
+        Arc::new(TreasuryStateRepository::default());
+    pub static ref REWARD_ROUND_REPOSITORY: Arc<RewardRoundRepository> =
+        Arc::new(RewardRoundRepository::default());
+    pub static ref PENDING_ENGAGEMENT_REPOSITORY: Arc<PendingEngagementRepository> =
+        Arc::new(PendingEngagementRepository::default());
+}
+
+#[derive(Debug, Default)]
+pub struct TreasuryStateRepository;
+
+impl TreasuryStateRepository {
+    pub fn get(&self) -> TreasuryState {
+        TREASURY_STATE.with_borrow(|cell| cell.get().clone())
+    }
+
+    pub fn set(&self, state: TreasuryState) -> RepositoryResult<TreasuryState> {
+        TREASURY_STATE.with_borrow_mut(|cell| {
+            cell.set(state.clone())
+                .map_err(|e| RepositoryError::IllegalUpdate {
+                    reason: format!("{:?}", e),
+                })
+        })?;
+        Ok(state)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RewardRoundRepository {
+    allocation: RewardAllocationRepository,
+}
+
+impl SerialIdRepository<VMemory> for RewardRoundRepository {
+    fn with_generator<F, R>(f: F) -> R
+    where
+        F: FnOnce(&mut Cell<u64, VMemory>) -> R,
+    {
+        NEXT_REWARD_ROUND_ID.with_borrow_mut(f)
+    }
+}
+
+impl BinaryTreeRepository<u64, RewardRound, VMemory> for RewardRoundRepository {
+    fn with_ref<F, R>(f: F) -> R
+    where
+        F: FnOnce(&RefCell<BTreeMap<u64, RewardRound, VMemory>>) -> R,
+    {
+        REWARD_ROUND.with(f)
+    }
+}
+
+impl AuditableRepository<RewardRound, VMemory> for RewardRoundRepository {}
+
+impl IndexableRepository<RewardRound> for RewardRoundRepository {
+    // rounds are only looked up by id, allocations are managed explicitly
+    fn remove_indexes(&self, _: &RewardRound) {}
+
+    fn add_indexes(&self, _: &RewardRound) {}
+
+    fn clear_indexes(&self) {}
+}
+
+impl RewardRoundRepository {
+    /// Returns the rounds in descending order of id (latest first)
+    pub fn get_rounds(&self, cursor: Option<u64>, limit: usize) -> Vec<RewardRound> {
+        let until_id = cursor.map_or(u64::MAX, |c| c.saturating_sub(1));
+        REWARD_ROUND.with_borrow(|m| {
+            m.range(..=until_id)
+                .rev()
+                .take(limit)
+                .map(|(_, round)| round)
+                .collect()
+        })
+    }
+
+    pub fn get_allocations(&self, round_id: u64) -> RepositoryResult<Vec<RewardAllocation>> {
+        if !self.exists(&round_id) {
+            return Err(RepositoryError::NotFound);
+        }
+        Ok(self.allocation.find_by_round(round_id))
+    }
+
+    pub fn save_allocation(&self, allocation: RewardAllocation) -> RepositoryResult<()> {
+        if !self.exists(&allocation.round_id) {
+            return Err(RepositoryError::NotFound);
+        }
+        self.allocation.insert(allocation);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RewardAllocationRepository;
+
+impl RewardAllocationRepository {
+    fn find_by_round(&self, round_id: u64) -> Vec<RewardAllocation> {
+        let start = (round_id, StorablePrincipal(Principal::from_slice(&[0])));
+        let end = (
+            round_id,
+            StorablePrincipal(Principal::from_slice(&[255; 29])),
+        );
+        REWARD_ALLOCATION.with_borrow(|m| m.range(start..=end).map(|(_, a)| a).collect())
+    }
+
+    fn insert(&self, allocation: RewardAllocation) {
+        REWARD_ALLOCATION.with_borrow_mut(|m| {
+            m.insert(
+                (allocation.round_id, StorablePrincipal(allocation.recipient)),
+                allocation,
+            )
+        });
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PendingEngagementRepository;
+
+impl PendingEngagementRepository {
+    /// Accumulates the engagement received by an author during the current reward period
+    pub fn record(&self, author: Principal, engagement: &Engagement) {
+        PENDING_ENGAGEMENT.with_borrow_mut(|m| {
+            let key = StorablePrincipal(author);
+            let mut current = m.get(&key).unwrap_or_default();
+            current.merge(engagement);
+            m.insert(key, current);
+        });
+    }
+
+    pub fn get_all(&self) -> Vec<(Principal, Engagement)> {
+        PENDING_ENGAGEMENT.with_borrow(|m| m.iter().map(|(k, v)| (k.0, v)).collect())
+    }
+
+    /// Takes every pending engagement out of the repository, leaving it empty
+    pub fn drain(&self) -> Vec<(Principal, Engagement)> {
+        let pending = self.get_all();
+        PENDING_ENGAGEMENT.with_borrow_mut(|m| m.clear_new());
+        pending
+    }
+}
@@ -1,14 +1,24 @@
-use std::{cell::RefCell, sync::Arc};
+use std::{cell::RefCell, ops::Bound, sync::Arc, thread::LocalKey};
 
 use candid::Principal;
 use ic_stable_structures::BTreeMap;
 use lazy_static::lazy_static;
 
+#[cfg(all(test, not(rust_analyzer)))]
+use crate::utils::mocks::timestamp;
+#[cfg(any(not(test), rust_analyzer))]
+use crate::utils::timestamp;
 use crate::{
-    memory::{ET_USER_MEM_ID, MEMORY_MANAGER},
+    memory::{
+        ET_USER_AVATAR_MEM_ID, ET_USER_MEM_ID, IDX_AUTHOR_DAILY_FOLLOWERS_MEM_ID,
+        IDX_AUTHOR_FOLLOWER_MEM_ID, IDX_USER_BLOCK_MEM_ID, IDX_USER_HANDLE_MEM_ID,
+        IDX_USER_MUTE_MEM_ID, MEMORY_MANAGER,
+    },
     structure::{BinaryTreeRepository, Repository},
-    types::{BTreeMapRefCell, RepositoryError, RepositoryResult, User, VMemory},
-    utils::timestamp,
+    types::{
+        BTreeMapRefCell, FollowerChange, RepositoryError, RepositoryResult, StorableHandle,
+        StorablePrincipal, User, VMemory,
+    },
 };
 
 thread_local! {
@@ -17,6 +27,46 @@ thread_local! {
             MEMORY_MANAGER.with_borrow(|m| m.get(ET_USER_MEM_ID))
         )
     );
+
+    // (author, day index) -> followers gained and lost that day
+    static AUTHOR_DAILY_FOLLOWERS: BTreeMapRefCell<(StorablePrincipal, u64), FollowerChange> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(IDX_AUTHOR_DAILY_FOLLOWERS_MEM_ID))
+        )
+    );
+
+    // (author, follower)
+    static AUTHOR_FOLLOWER_INDEX: BTreeMapRefCell<(StorablePrincipal, StorablePrincipal), ()> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(IDX_AUTHOR_FOLLOWER_MEM_ID))
+        )
+    );
+
+    // (user, muted user)
+    static USER_MUTE_INDEX: BTreeMapRefCell<(StorablePrincipal, StorablePrincipal), ()> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(IDX_USER_MUTE_MEM_ID))
+        )
+    );
+
+    // (user, blocked user)
+    static USER_BLOCK_INDEX: BTreeMapRefCell<(StorablePrincipal, StorablePrincipal), ()> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(IDX_USER_BLOCK_MEM_ID))
+        )
+    );
+
+    static USER_HANDLE_INDEX: BTreeMapRefCell<StorableHandle, Principal> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(IDX_USER_HANDLE_MEM_ID))
+        )
+    );
+
+    static USER_AVATAR: BTreeMapRefCell<Principal, String> = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(ET_USER_AVATAR_MEM_ID))
+        )
+    );
 }
 
 lazy_static! {
@@ -56,3 +106,164 @@ impl Repository<Principal, User, VMemory> for UserRepository {
         Ok(value)
     }
 }
+
+impl UserRepository {
+    /// Gets the users ordered by principal, starting after `last`
+    pub fn get_users_after(&self, last: Option<Principal>, limit: usize) -> Vec<User> {
+        let start = last.map_or(Bound::Unbounded, Bound::Excluded);
+        USER.with_borrow(|m| {
+            m.range((start, Bound::Unbounded))
+                .take(limit)
+                .map(|(_, user)| user)
+                .collect()
+        })
+    }
+
+    /// Gets the owner of a normalized handle
+    pub fn find_by_handle(&self, handle: &str) -> Option<Principal> {
+        USER_HANDLE_INDEX.with_borrow(|m| m.get(&StorableHandle(handle.to_string())))
+    }
+
+    /// Moves the handle of a user, fails when the new handle is taken by another user
+    pub fn set_handle(
+        &self,
+        user: Principal,
+        old: Option<&str>,
+        new: Option<&str>,
+    ) -> RepositoryResult<()> {
+        if let Some(owner) = new.and_then(|h| self.find_by_handle(h)) {
+            if owner != user {
+                return Err(RepositoryError::Conflict);
+            }
+        }
+        USER_HANDLE_INDEX.with_borrow_mut(|m| {
+            if let Some(old) = old {
+                m.remove(&StorableHandle(old.to_string()));
+            }
+            if let Some(new) = new {
+                m.insert(StorableHandle(new.to_string()), user);
+            }
+        });
+        Ok(())
+    }
+
+    pub fn get_avatar(&self, user: &Principal) -> Option<String> {
+        USER_AVATAR.with_borrow(|m| m.get(user))
+    }
+
+    pub fn set_avatar(&self, user: Principal, avatar: Option<String>) {
+        USER_AVATAR.with_borrow_mut(|m| match avatar {
+            Some(avatar) => m.insert(user, avatar),
+            None => m.remove(&user),
+        });
+    }
+
+    pub fn record_follower_change(&self, author: Principal, day: u64, gained: bool) {
+        AUTHOR_DAILY_FOLLOWERS.with_borrow_mut(|m| {
+            let key = (StorablePrincipal(author), day);
+            let mut change = m.get(&key).unwrap_or_default();
+            if gained {
+                change.gained += 1;
+            } else {
+                change.lost += 1;
+            }
+            m.insert(key, change);
+        });
+    }
+
+    pub fn add_follower(&self, author: Principal, follower: Principal) {
+        AUTHOR_FOLLOWER_INDEX.with_borrow_mut(|m| {
+            m.insert((StorablePrincipal(author), StorablePrincipal(follower)), ())
+        });
+    }
+
+    pub fn remove_follower(&self, author: Principal, follower: Principal) {
+        AUTHOR_FOLLOWER_INDEX.with_borrow_mut(|m| {
+            m.remove(&(StorablePrincipal(author), StorablePrincipal(follower)))
+        });
+    }
+
+    /// Gets up to `limit` followers of an author
+    pub fn find_followers(&self, author: Principal, limit: usize) -> Vec<Principal> {
+        find_relations(&AUTHOR_FOLLOWER_INDEX, author, limit)
+    }
+
+    /// Mutes a user, returns false when already muted
+    pub fn mute(&self, user: Principal, muted: Principal) -> bool {
+        USER_MUTE_INDEX.with_borrow_mut(|m| {
+            m.insert((StorablePrincipal(user), StorablePrincipal(muted)), ())
+                .is_none()
+        })
+    }
+
+    pub fn unmute(&self, user: Principal, muted: Principal) -> bool {
+        USER_MUTE_INDEX.with_borrow_mut(|m| {
+            m.remove(&(StorablePrincipal(user), StorablePrincipal(muted)))
+                .is_some()
+        })
+    }
+
+    pub fn find_muted(&self, user: Principal, limit: usize) -> Vec<Principal> {
+        find_relations(&USER_MUTE_INDEX, user, limit)
+    }
+
+    /// Blocks a user, returns false when already blocked
+    pub fn block(&self, user: Principal, blocked: Principal) -> bool {
+        USER_BLOCK_INDEX.with_borrow_mut(|m| {
+            m.insert((StorablePrincipal(user), StorablePrincipal(blocked)), ())
+                .is_none()
+        })
+    }
+
+    pub fn unblock(&self, user: Principal, blocked: Principal) -> bool {
+        USER_BLOCK_INDEX.with_borrow_mut(|m| {
+            m.remove(&(StorablePrincipal(user), StorablePrincipal(blocked)))
+                .is_some()
+        })
+    }
+
+    pub fn is_blocked(&self, user: Principal, blocked: Principal) -> bool {
+        USER_BLOCK_INDEX
+            .with_borrow(|m| m.contains_key(&(StorablePrincipal(user), StorablePrincipal(blocked))))
+    }
+
+    pub fn find_blocked(&self, user: Principal, limit: usize) -> Vec<Principal> {
+        find_relations(&USER_BLOCK_INDEX, user, limit)
+    }
+
+    /// Gets the daily follower changes of an author between two day indexes, both inclusive
+    pub fn get_follower_changes(
+        &self,
+        author: Principal,
+        from_day: u64,
+        to_day: u64,
+    ) -> Vec<(u64, FollowerChange)> {
+        let author = StorablePrincipal(author);
+        AUTHOR_DAILY_FOLLOWERS.with_borrow(|m| {
+            m.range((author.clone(), from_day)..=(author, to_day))
+                .map(|((_, day), change)| (day, change))
+                .collect()
+        })
+    }
+}
+
+/// Gets up to `limit` users related to a user in a (user, related user) index
+fn find_relations(
+    index: &'static LocalKey<BTreeMapRefCell<(StorablePrincipal, StorablePrincipal), ()>>,
+    user: Principal,
+    limit: usize,
+) -> Vec<Principal> {
+    let user = StorablePrincipal(user);
+    index.with_borrow(|m| {
+        m.range(
+            (
+                user.clone(),
+                StorablePrincipal(Principal::management_canister()),
+            )..,
+        )
+        .take_while(|((u, _), _)| *u == user)
+        .take(limit)
+        .map(|((_, related), _)| related.0)
+        .collect()
+    })
+}
@@ -3,38 +3,64 @@ use std::sync::Arc;
 use candid::Principal;
 use lazy_static::lazy_static;
 
+#[cfg(all(test, not(rust_analyzer)))]
+use crate::utils::mocks::timestamp;
+#[cfg(any(not(test), rust_analyzer))]
+use crate::utils::timestamp;
 use crate::{
     repositories::{
         draft::{
-            self, DraftContentRepository, DraftRepository, DRAFT_CONTENT_REPOSITORY,
-            DRAFT_REPOSITORY,
+            self, DraftChatRepository, DraftContentRepository, DraftRepository,
+            DRAFT_CHAT_REPOSITORY, DRAFT_CONTENT_REPOSITORY, DRAFT_REPOSITORY,
         },
+        statistics::StatisticsRepository,
         story::{
             StoryContentRepository, StoryRepository, STORY_CONTENT_REPOSITORY, STORY_REPOSITORY,
         },
     },
     structure::{AuditableRepository, BinaryTreeRepository, Repository},
     types::{
-        Draft, RepositoryError, SaveDraftArgs, ServiceError, ServiceResult, Story, StoryContent,
+        AssistantChatMessage, ChatRole, Draft, ModerationEntry, RepositoryError, SaveDraftArgs,
+        ServiceError, ServiceResult, Story, StoryContent, StoryDetail,
     },
-    utils::estimate_read_time,
+    utils::{
+        detect_language, estimate_read_time, extract_mentions, normalize_language, normalize_tag,
+    },
+};
+
+use super::{
+    llm::{LlmService, SafetyClassification, LLM_SERVICE},
+    story::{build_story_terms, calculate_rank},
+    user::{UserService, MAX_MENTIONS, USER_SERVICE},
 };
 
+pub const MAX_CHAT_MESSAGE_SIZE: usize = 2_000;
+const MAX_STORY_TAGS: usize = 5;
+/// Messages kept in the assistant conversation of a draft
+pub const MAX_STORED_CHAT_MESSAGES: usize = 100;
+
 lazy_static! {
     pub static ref DRAFT_SERVICE: Arc<DraftService> = Arc::new(DraftService::new(
         DRAFT_REPOSITORY.clone(),
         DRAFT_CONTENT_REPOSITORY.clone(),
         STORY_REPOSITORY.clone(),
         STORY_CONTENT_REPOSITORY.clone(),
+        DRAFT_CHAT_REPOSITORY.clone(),
+        LLM_SERVICE.clone(),
+        USER_SERVICE.clone(),
     ));
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct DraftService {
     draft_repository: Arc<DraftRepository>,
     draft_content_repository: Arc<DraftContentRepository>,
     story_repository: Arc<StoryRepository>,
     story_content_repository: Arc<StoryContentRepository>,
+    draft_chat_repository: Arc<DraftChatRepository>,
+    statistics: StatisticsRepository,
+    llm_service: Arc<LlmService>,
+    user_service: Arc<UserService>,
 }
 
 impl DraftService {
@@ -43,12 +69,19 @@ impl DraftService {
         draft_content_repository: Arc<DraftContentRepository>,
         story_repository: Arc<StoryRepository>,
         story_content_repository: Arc<StoryContentRepository>,
+        draft_chat_repository: Arc<DraftChatRepository>,
+        llm_service: Arc<LlmService>,
+        user_service: Arc<UserService>,
     ) -> Self {
         Self {
             draft_repository,
             draft_content_repository,
             story_repository,
             story_content_repository,
+            draft_chat_repository,
+            statistics: StatisticsRepository::default(),
+            llm_service,
+            user_service,
         }
     }
 
@@ -59,7 +92,11 @@ impl DraftService {
     ) -> ServiceResult<Draft> {
         validate_empty_save_args(&args, "Nothing to save")?;
 
-        let draft = Draft::new(args.title.unwrap_or_default(), args.detail, identity);
+        let detail = args
+            .detail
+            .map(|detail| self.validate_detail(detail))
+            .transpose()?;
+        let draft = Draft::new(args.title.unwrap_or_default(), detail, identity);
         let content = args.content.unwrap_or_default();
 
         let draft = self.draft_repository.insert(draft).map_err(|e| match e {
@@ -91,6 +128,7 @@ impl DraftService {
             // successfully rollback, and report the error
             return Err(content_err.clone());
         }
+        self.statistics.add_draft();
         Ok(draft)
     }
 
@@ -113,7 +151,10 @@ impl DraftService {
         }
         let is_detail_changed = args.detail.is_some();
         if is_detail_changed {
-            draft.detail = args.detail;
+            draft.detail = args
+                .detail
+                .map(|detail| self.validate_detail(detail))
+                .transpose()?;
         }
 
         if let Some(new_content) = args.content {
@@ -168,16 +209,47 @@ impl DraftService {
                 reason: "Title and content cannot be empty".to_string(),
             });
         }
-        let detail = draft
+        let mut detail = draft
             .detail
             .clone()
             .filter(|detail| !detail.description.is_empty())
             .ok_or_else(|| ServiceError::UnprocessableEntity {
                 reason: "Story detail is required".to_string(),
             })?;
+        if detail.language.is_none() {
+            detail.language = detect_language(&format!(
+                "{}\n{}\n{}",
+                draft.title, detail.description, d_content.content
+            ));
+        }
+        // the category may have been deactivated since the draft was saved
+        let detail = self.validate_detail(detail)?;
+
+        // classify before writing anything. Other calls run during the await, so the draft is
+        // read again and the publish aborts if it was published, deleted or edited meanwhile
+        let safety = self.classify_safety(&d_content.content).await;
+        let unchanged = self
+            .draft_repository
+            .get(&id)
+            .is_some_and(|d| d.updated_at == draft.updated_at)
+            && self
+                .draft_content_repository
+                .get(&id)
+                .is_some_and(|c| c.content == d_content.content);
+        if !unchanged {
+            return Err(ServiceError::Conflict {
+                entity: "Draft".to_string(),
+            });
+        }
 
         // Promote to story
-        let story = Story::new(draft, detail);
+        let mut story = Story::new(draft, detail);
+        if let Some(safety) = &safety {
+            story.safety_labels = safety.labels.clone();
+            if !safety.labels.is_empty() {
+                story.detail.mature_content = true;
+            }
+        }
         let story = self.story_repository.insert(story).map_err(|e| match e {
             RepositoryError::Conflict => ServiceError::Conflict {
                 entity: "Story".to_string(),
@@ -187,6 +259,17 @@ impl DraftService {
             },
         })?;
 
+        // ranked once the creation time is known
+        let rank = calculate_rank(&story, &self.story_repository.get_ranking_config());
+        let story = self
+            .story_repository
+            .set_story_score(story.id, rank)
+            .map_err(|e| ServiceError::InternalError {
+                reason: format!("Failed to rank story: {}", e),
+            })?;
+
+        let terms = build_story_terms(&story, &d_content.content);
+        let mentions = extract_mentions(&d_content.content, MAX_MENTIONS);
         let s_content = StoryContent::new(story.id, d_content.content, identity);
         let s_content = self
             .story_content_repository
@@ -219,10 +302,74 @@ impl DraftService {
                 reason: format!("Failed to delete draft: {}", e),
             },
         })?;
+        self.statistics.remove_draft();
+        self.draft_chat_repository.clear(id);
+        self.story_repository
+            .save_story_terms(story.id, terms)
+            .map_err(|e| ServiceError::InternalError {
+                reason: format!("Failed to index story terms: {}", e),
+            })?;
+
+        if let Some(safety) = safety.filter(|s| !s.uncertain.is_empty()) {
+            self.story_repository
+                .queue_moderation(ModerationEntry {
+                    story_id: story.id,
+                    labels: safety.labels,
+                    uncertain: safety.uncertain,
+                    queued_at: timestamp(),
+                })
+                .map_err(|e| ServiceError::InternalError {
+                    reason: format!("Failed to queue story moderation: {}", e),
+                })?;
+        }
+        self.user_service
+            .notify_mentions(story.author, story.id, &mentions, timestamp());
+        self.user_service
+            .notify_followers(story.author, story.id, timestamp());
 
         Ok(story)
     }
 
+    /// Checks the category can be picked, normalizes the tags and language of a story detail
+    /// and checks the story it translates
+    fn validate_detail(&self, mut detail: StoryDetail) -> ServiceResult<StoryDetail> {
+        if !self
+            .story_repository
+            .is_category_available(&detail.category)
+        {
+            return Err(ServiceError::UnprocessableEntity {
+                reason: "Category is not available.".to_string(),
+            });
+        }
+        detail.tags = normalize_tags(&detail.tags)?;
+        if let Some(language) = &detail.language {
+            detail.language = Some(normalize_language(language).ok_or_else(|| {
+                ServiceError::UnprocessableEntity {
+                    reason: format!("Invalid language: {}", language),
+                }
+            })?);
+        }
+        if let Some(original_id) = detail.translation_of {
+            let original = self
+                .story_repository
+                .get(&original_id)
+                .ok_or(ServiceError::StoryNotFound)?;
+            // translations link to the root original, never to another translation
+            if original.detail.translation_of.is_some() {
+                return Err(ServiceError::UnprocessableEntity {
+                    reason: "A translation must link to the original story.".to_string(),
+                });
+            }
+            if detail.language.is_some() && detail.language == original.detail.language {
+                return Err(ServiceError::UnprocessableEntity {
+                    reason: "A translation must be in another language than the original."
+                        .to_string(),
+                });
+            }
+        }
+        Ok(detail)
+    }
+
     pub async fn delete_draft(&self, id: u64, identity: Principal) -> ServiceResult<u64> {
         let draft = self
             .draft_repository
@@ -235,12 +382,114 @@ impl DraftService {
             .map_err(|e| ServiceError::InternalError {
                 reason: format!("Failed to delete draft content: {}", e),
             })?;
-        self.draft_repository.delete(&id).map_err(|e| match e {
+        self.draft_chat_repository.clear(id);
+        let id = self.draft_repository.delete(&id).map_err(|e| match e {
             RepositoryError::NotFound => ServiceError::DraftNotFound,
             _ => ServiceError::InternalError {
                 reason: format!("Failed to delete draft: {}", e),
             },
-        })
+        })?;
+        self.statistics.remove_draft();
+        Ok(id)
+    }
+
+    /// Sends a message to the assistant of a draft, the conversation is only kept when the
+    /// assistant answered and the draft still exists, otherwise the quota is refunded
+    pub async fn chat_with_assistant(
+        &self,
+        id: u64,
+        message: String,
+        identity: Principal,
+    ) -> ServiceResult<AssistantChatMessage> {
+        let message = message.trim().to_string();
+        if message.is_empty() || message.chars().count() > MAX_CHAT_MESSAGE_SIZE {
+            return Err(ServiceError::UnprocessableEntity {
+                reason: format!(
+                    "Message must be between 1 and {} characters",
+                    MAX_CHAT_MESSAGE_SIZE
+                ),
+            });
+        }
+        let content = self
+            .draft_content_repository
+            .get(&id)
+            .ok_or(ServiceError::DraftNotFound)?;
+        validate_draft_author(content.author, identity)?;
+
+        let history = self.draft_chat_repository.get_messages(id);
+        let now = timestamp();
+        self.user_service.consume_ai_quota(&identity, now)?;
+        let reply = self
+            .llm_service
+            .chat(&content.content, &history, message.clone())
+            .await
+            .map_err(|e| {
+                self.user_service.refund_ai_quota(&identity, now);
+                ServiceError::AiModelError(e)
+            })?;
+        // publishing or deleting the draft during the call already cleared its conversation
+        if self.draft_repository.get(&id).is_none()
+            || self.draft_content_repository.get(&id).is_none()
+        {
+            self.user_service.refund_ai_quota(&identity, now);
+            return Err(ServiceError::DraftNotFound);
+        }
+
+        let reply = AssistantChatMessage {
+            role: ChatRole::Assistant,
+            content: reply,
+            created_at: now,
+        };
+        self.draft_chat_repository.append(
+            id,
+            vec![
+                AssistantChatMessage {
+                    role: ChatRole::User,
+                    content: message,
+                    created_at: now,
+                },
+                reply.clone(),
+            ],
+            MAX_STORED_CHAT_MESSAGES,
+        );
+        Ok(reply)
+    }
+
+    pub fn get_assistant_chat(
+        &self,
+        id: u64,
+        identity: Principal,
+    ) -> ServiceResult<Vec<AssistantChatMessage>> {
+        let draft = self
+            .draft_repository
+            .get(&id)
+            .ok_or(ServiceError::DraftNotFound)?;
+        validate_draft_author(draft.author, identity)?;
+        Ok(self.draft_chat_repository.get_messages(id))
+    }
+
+    pub fn clear_assistant_chat(&self, id: u64, identity: Principal) -> ServiceResult<()> {
+        let draft = self
+            .draft_repository
+            .get(&id)
+            .ok_or(ServiceError::DraftNotFound)?;
+        validate_draft_author(draft.author, identity)?;
+        self.draft_chat_repository.clear(id);
+        Ok(())
+    }
+
+    /// Runs the optional safety classification, skipped when the LLM cannot answer
+    async fn classify_safety(&self, content: &str) -> Option<SafetyClassification> {
+        if !self.llm_service.is_safety_check_enabled() {
+            return None;
+        }
+        match self.llm_service.classify_safety(content).await {
+            Ok(classification) => Some(classification),
+            Err(e) => {
+                ic_cdk::println!("safety classification skipped: {}", e);
+                None
+            }
+        }
     }
 
     pub fn get_draft(&self, id: &u64) -> ServiceResult<(Draft, StoryContent)> {
@@ -271,7 +520,10 @@ impl DraftService {
     }
 }
 
-fn validate_draft_author(author: Principal, identity: Principal) -> Result<(), ServiceError> {
+pub(super) fn validate_draft_author(
+    author: Principal,
+    identity: Principal,
+) -> Result<(), ServiceError> {
     if author != identity {
         return Err(ServiceError::IdentityUnauthorized {
             identity: identity.to_string(),
@@ -280,6 +532,25 @@ fn validate_draft_author(author: Principal, identity: Principal) -> Result<(), S
     Ok(())
 }
 
+/// Normalizes tags, dropping duplicates and keeping their order
+fn normalize_tags(tags: &[String]) -> ServiceResult<Vec<String>> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = normalize_tag(tag).ok_or_else(|| ServiceError::UnprocessableEntity {
+            reason: format!("Invalid tag: {}", tag),
+        })?;
+        if !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    if normalized.len() > MAX_STORY_TAGS {
+        return Err(ServiceError::UnprocessableEntity {
+            reason: format!("A story can have at most {} tags.", MAX_STORY_TAGS),
+        });
+    }
+    Ok(normalized)
+}
+
 fn validate_empty_save_args(args: &SaveDraftArgs, message: &str) -> Result<(), ServiceError> {
     if args.title.is_none() && args.content.is_none() && args.detail.is_none() {
         return Err(ServiceError::UnprocessableEntity {
@@ -288,3 +559,102 @@ fn validate_empty_save_args(args: &SaveDraftArgs, message: &str) -> Result<(), S
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use candid::Principal;
+    use icrc_ledger_types::icrc1::transfer::TransferArg;
+
+    use crate::{
+        repositories::{
+            draft::{DRAFT_CHAT_REPOSITORY, DRAFT_CONTENT_REPOSITORY, DRAFT_REPOSITORY},
+            llm::LLM_CONFIG_REPOSITORY,
+            story::{STORY_CONTENT_REPOSITORY, STORY_REPOSITORY},
+        },
+        services::{
+            llm::{LlmFuture, LlmProvider, LlmService},
+            user::USER_SERVICE,
+        },
+        structure::{AuditableRepository, Repository},
+        token::{api::create_token, CreateTokenArgs, StakeTokenArgs, LEDGER_SERVICE},
+        types::{LlmModel, SaveDraftArgs, ServiceError},
+        utils::mocks::{block_on, set_caller, timestamp},
+    };
+
+    use super::DraftService;
+
+    /// Deletes the draft while the assistant is answering
+    #[derive(Debug)]
+    struct DeletingLlmProvider(u64);
+
+    impl LlmProvider for DeletingLlmProvider {
+        fn chat(&self, _: LlmModel, _: Vec<ic_llm::ChatMessage>) -> LlmFuture<'_> {
+            DRAFT_CONTENT_REPOSITORY.delete(&self.0).unwrap();
+            DRAFT_CHAT_REPOSITORY.clear(self.0);
+            DRAFT_REPOSITORY.delete(&self.0).unwrap();
+            Box::pin(async { Ok("reply".to_string()) })
+        }
+    }
+
+    fn draft_service(provider: Arc<dyn LlmProvider>) -> DraftService {
+        DraftService::new(
+            DRAFT_REPOSITORY.clone(),
+            DRAFT_CONTENT_REPOSITORY.clone(),
+            STORY_REPOSITORY.clone(),
+            STORY_CONTENT_REPOSITORY.clone(),
+            DRAFT_CHAT_REPOSITORY.clone(),
+            Arc::new(LlmService::new(provider, LLM_CONFIG_REPOSITORY.clone())),
+            USER_SERVICE.clone(),
+        )
+    }
+
+    #[test]
+    fn test_chat_with_deleted_draft() {
+        create_token(Some(CreateTokenArgs {
+            token_name: "TestToken".to_string(),
+            token_symbol: "TT".to_string(),
+            token_logo: "logo".to_string(),
+            initial_supply: 1_000_000_000_usize.into(),
+            transfer_fee: 0_usize.into(),
+        }))
+        .unwrap();
+        let author = Principal::from_text("aaaaa-aa").unwrap();
+        LEDGER_SERVICE
+            .mint(TransferArg {
+                from_subaccount: None,
+                to: author.into(),
+                fee: None,
+                created_at_time: None,
+                memo: None,
+                amount: 1_000_000_usize.into(),
+            })
+            .unwrap();
+        set_caller(Some(&author.to_string()));
+        LEDGER_SERVICE
+            .stake(StakeTokenArgs {
+                from_subaccount: None,
+                amount: 1_000_000_usize.into(),
+            })
+            .unwrap();
+        set_caller(None);
+
+        let draft = block_on(
+            draft_service(Arc::new(DeletingLlmProvider(0))).create_draft(
+                SaveDraftArgs {
+                    content: Some("Once upon a time".to_string()),
+                    ..Default::default()
+                },
+                author,
+            ),
+        )
+        .unwrap();
+        let service = draft_service(Arc::new(DeletingLlmProvider(draft.id)));
+        let result = block_on(service.chat_with_assistant(draft.id, "Hi".to_string(), author));
+
+        assert!(matches!(result, Err(ServiceError::DraftNotFound)));
+        assert!(DRAFT_CHAT_REPOSITORY.get_messages(draft.id).is_empty());
+        assert_eq!(USER_SERVICE.get_ai_quota(&author, timestamp()).used, 0);
+    }
+}
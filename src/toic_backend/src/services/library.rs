@@ -0,0 +1,475 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+
+use candid::Principal;
+use lazy_static::lazy_static;
+
+use crate::{
+    repositories::{
+        library::{
+            BookmarkRepository, ReadingHistoryRepository, ReadingListRepository,
+            BOOKMARK_REPOSITORY, READING_HISTORY_REPOSITORY, READING_LIST_REPOSITORY,
+        },
+        story::{StoryRepository, STORY_REPOSITORY},
+    },
+    structure::{AuditableRepository, BinaryTreeRepository},
+    types::{
+        BookmarkCursor, ReadingHistoryCursor, ReadingHistoryEntry, ReadingList, ReadingListArgs,
+        ReadingListDetail, ReadingListItem, ReadingProgress, RepositoryError, ServiceError,
+        ServiceResult, Story,
+    },
+};
+
+use super::story::{StoryService, STORY_SERVICE};
+
+const MAX_BOOKMARKS: usize = 1_000;
+const MAX_READING_LISTS: usize = 50;
+const MAX_READING_LIST_STORIES: usize = 200;
+const MAX_READING_LIST_NAME_SIZE: usize = 50;
+const MAX_READING_LIST_DESCRIPTION_SIZE: usize = 280;
+/// Stories kept in the reading history of a user, least recently read are dropped
+const MAX_READING_HISTORY: usize = 100;
+
+lazy_static! {
+    pub static ref LIBRARY_SERVICE: Arc<LibraryService> = Arc::new(LibraryService::new(
+        BOOKMARK_REPOSITORY.clone(),
+        READING_HISTORY_REPOSITORY.clone(),
+        READING_LIST_REPOSITORY.clone(),
+        STORY_REPOSITORY.clone(),
+        STORY_SERVICE.clone(),
+    ));
+}
+
+#[derive(Debug)]
+pub struct LibraryService {
+    bookmark_repository: Arc<BookmarkRepository>,
+    reading_history_repository: Arc<ReadingHistoryRepository>,
+    reading_list_repository: Arc<ReadingListRepository>,
+    story_repository: Arc<StoryRepository>,
+    story_service: Arc<StoryService>,
+}
+
+impl LibraryService {
+    pub fn new(
+        bookmark_repository: Arc<BookmarkRepository>,
+        reading_history_repository: Arc<ReadingHistoryRepository>,
+        reading_list_repository: Arc<ReadingListRepository>,
+        story_repository: Arc<StoryRepository>,
+        story_service: Arc<StoryService>,
+    ) -> Self {
+        Self {
+            bookmark_repository,
+            reading_history_repository,
+            reading_list_repository,
+            story_repository,
+            story_service,
+        }
+    }
+
+    pub fn bookmark_story(&self, identity: Principal, id: u64, now: u64) -> ServiceResult<bool> {
+        if !self.story_repository.exists(&id) {
+            return Err(ServiceError::StoryNotFound);
+        }
+        if self.bookmark_repository.count(identity) >= MAX_BOOKMARKS {
+            return Err(ServiceError::UnprocessableEntity {
+                reason: format!("You can bookmark at most {} stories.", MAX_BOOKMARKS),
+            });
+        }
+        Ok(self.bookmark_repository.add(identity, id, now))
+    }
+
+    pub fn remove_bookmark(&self, identity: Principal, id: u64) -> ServiceResult<bool> {
+        Ok(self.bookmark_repository.remove(identity, id))
+    }
+
+    /// Gets the bookmarked stories of a user, latest bookmark first
+    pub fn get_bookmarks(
+        &self,
+        identity: Principal,
+        cursor: Option<BookmarkCursor>,
+        limit: usize,
+    ) -> ServiceResult<(Option<BookmarkCursor>, Vec<Story>)> {
+        let bookmarks = self
+            .bookmark_repository
+            .find_by_user(identity, cursor, limit);
+        let stories = bookmarks
+            .iter()
+            .filter_map(|(_, id)| self.story_repository.get(id))
+            .map(|s| self.story_service.add_author_name(s))
+            .collect();
+        let stories = self.story_service.gate_mature_content(stories, identity);
+        Ok((bookmarks.last().cloned(), stories))
+    }
+
+    pub fn save_reading_progress(
+        &self,
+        identity: Principal,
+        story_id: u64,
+        position: u64,
+        now: u64,
+    ) -> ServiceResult<ReadingProgress> {
+        if !self.story_repository.exists(&story_id) {
+            return Err(ServiceError::StoryNotFound);
+        }
+        let progress = ReadingProgress {
+            story_id,
+            position,
+            updated_at: now,
+        };
+        self.reading_history_repository
+            .save(identity, progress.clone(), MAX_READING_HISTORY);
+        Ok(progress)
+    }
+
+    pub fn get_reading_progress(
+        &self,
+        identity: Principal,
+        story_id: u64,
+    ) -> Option<ReadingProgress> {
+        self.reading_history_repository.get(identity, story_id)
+    }
+
+    /// Gets the stories read by a user with the position they stopped at, most recently read
+    /// first, so the history doubles as a continue reading feed
+    pub fn get_reading_history(
+        &self,
+        identity: Principal,
+        cursor: Option<ReadingHistoryCursor>,
+        limit: usize,
+    ) -> ServiceResult<(Option<ReadingHistoryCursor>, Vec<ReadingHistoryEntry>)> {
+        let history = self
+            .reading_history_repository
+            .find_by_reader(identity, cursor, limit);
+        let next = history.last().map(|p| (p.updated_at, p.story_id));
+        let stories = self.gated_stories(history.iter().map(|p| p.story_id), identity);
+        let entries = history
+            .into_iter()
+            .filter_map(|progress| {
+                let story = stories.get(&progress.story_id)?.clone();
+                Some(ReadingHistoryEntry { progress, story })
+            })
+            .collect();
+        Ok((next, entries))
+    }
+
+    pub fn create_reading_list(
+        &self,
+        identity: Principal,
+        args: ReadingListArgs,
+    ) -> ServiceResult<ReadingList> {
+        if self.reading_list_repository.count_by_owner(identity) >= MAX_READING_LISTS {
+            return Err(ServiceError::UnprocessableEntity {
+                reason: format!("You can have at most {} reading lists.", MAX_READING_LISTS),
+            });
+        }
+        let (name, description) = validate_reading_list_args(&args)?;
+        self.reading_list_repository
+            .insert(ReadingList {
+                id: 0,
+                owner: identity,
+                name,
+                description,
+                public: args.public,
+                story_ids: vec![],
+                created_at: 0,
+                updated_at: None,
+            })
+            .map_err(map_reading_list_err)
+    }
+
+    pub fn update_reading_list(
+        &self,
+        identity: Principal,
+        id: u64,
+        args: ReadingListArgs,
+    ) -> ServiceResult<ReadingList> {
+        let mut list = self.get_owned_reading_list(identity, id)?;
+        let (name, description) = validate_reading_list_args(&args)?;
+        list.name = name;
+        list.description = description;
+        list.public = args.public;
+        self.reading_list_repository
+            .update(list)
+            .map_err(map_reading_list_err)
+    }
+
+    pub fn delete_reading_list(&self, identity: Principal, id: u64) -> ServiceResult<u64> {
+        self.get_owned_reading_list(identity, id)?;
+        self.reading_list_repository
+            .delete(&id)
+            .map_err(map_reading_list_err)
+    }
+
+    /// Adds a story to a reading list at the given position, at the end when omitted.
+    /// A story already in the list is moved to the position
+    pub fn add_to_reading_list(
+        &self,
+        identity: Principal,
+        id: u64,
+        story_id: u64,
+        position: Option<usize>,
+    ) -> ServiceResult<ReadingList> {
+        let mut list = self.get_owned_reading_list(identity, id)?;
+        if !self.story_repository.exists(&story_id) {
+            return Err(ServiceError::StoryNotFound);
+        }
+        list.story_ids.retain(|s| *s != story_id);
+        if list.story_ids.len() >= MAX_READING_LIST_STORIES {
+            return Err(ServiceError::UnprocessableEntity {
+                reason: format!(
+                    "A reading list can have at most {} stories.",
+                    MAX_READING_LIST_STORIES
+                ),
+            });
+        }
+        let position = position
+            .unwrap_or(list.story_ids.len())
+            .min(list.story_ids.len());
+        list.story_ids.insert(position, story_id);
+        self.reading_list_repository
+            .update(list)
+            .map_err(map_reading_list_err)
+    }
+
+    pub fn remove_from_reading_list(
+        &self,
+        identity: Principal,
+        id: u64,
+        story_id: u64,
+    ) -> ServiceResult<ReadingList> {
+        let mut list = self.get_owned_reading_list(identity, id)?;
+        list.story_ids.retain(|s| *s != story_id);
+        self.reading_list_repository
+            .update(list)
+            .map_err(map_reading_list_err)
+    }
+
+    /// Reorders a reading list, the given ids must be the stories of the list in the new order
+    pub fn reorder_reading_list(
+        &self,
+        identity: Principal,
+        id: u64,
+        story_ids: Vec<u64>,
+    ) -> ServiceResult<ReadingList> {
+        let mut list = self.get_owned_reading_list(identity, id)?;
+        validate_reorder(&list.story_ids, &story_ids)?;
+        list.story_ids = story_ids;
+        self.reading_list_repository
+            .update(list)
+            .map_err(map_reading_list_err)
+    }
+
+    /// Gets a reading list with its stories, private lists are only visible to their owner.
+    /// Stories no longer available are kept in place without their content, mature stories are
+    /// gated by the preference of the caller like in the feeds
+    pub fn get_reading_list(
+        &self,
+        identity: Principal,
+        id: u64,
+    ) -> ServiceResult<ReadingListDetail> {
+        let list = self
+            .reading_list_repository
+            .get(&id)
+            .filter(|l| l.public || l.owner == identity)
+            .ok_or(reading_list_not_found())?;
+        let stories = self.gated_stories(list.story_ids.iter().copied(), identity);
+        let items = list
+            .story_ids
+            .iter()
+            .map(|story_id| ReadingListItem {
+                story_id: *story_id,
+                story: stories.get(story_id).cloned(),
+            })
+            .collect();
+        Ok(ReadingListDetail { list, items })
+    }
+
+    /// Gets the stories still available to the reader by id, mature content gated
+    fn gated_stories(
+        &self,
+        ids: impl Iterator<Item = u64>,
+        reader: Principal,
+    ) -> BTreeMap<u64, Story> {
+        let stories = ids
+            .filter_map(|id| self.story_repository.get(&id))
+            .map(|s| self.story_service.add_author_name(s))
+            .collect();
+        self.story_service
+            .gate_mature_content(stories, reader)
+            .into_iter()
+            .map(|s| (s.id, s))
+            .collect()
+    }
+
+    /// Gets the reading lists of an owner, only the public ones unless the caller is the owner
+    pub fn get_reading_lists(&self, identity: Principal, owner: Principal) -> Vec<ReadingList> {
+        self.reading_list_repository
+            .find_by_owner(owner)
+            .into_iter()
+            .filter(|l| l.public || l.owner == identity)
+            .collect()
+    }
+
+    fn get_owned_reading_list(&self, identity: Principal, id: u64) -> ServiceResult<ReadingList> {
+        let list = self
+            .reading_list_repository
+            .get(&id)
+            .ok_or(reading_list_not_found())?;
+        if list.owner != identity {
+            return Err(ServiceError::IdentityUnauthorized {
+                identity: identity.to_string(),
+            });
+        }
+        Ok(list)
+    }
+}
+
+fn validate_reading_list_args(args: &ReadingListArgs) -> ServiceResult<(String, Option<String>)> {
+    let name = args.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_READING_LIST_NAME_SIZE {
+        return Err(ServiceError::UnprocessableEntity {
+            reason: format!(
+                "Reading list name must be between 1 and {} characters.",
+                MAX_READING_LIST_NAME_SIZE
+            ),
+        });
+    }
+    let description = args
+        .description
+        .as_ref()
+        .map(|d| d.trim().to_string())
+        .filter(|d| !d.is_empty());
+    if description
+        .as_ref()
+        .is_some_and(|d| d.chars().count() > MAX_READING_LIST_DESCRIPTION_SIZE)
+    {
+        return Err(ServiceError::UnprocessableEntity {
+            reason: format!(
+                "Reading list description must be at most {} characters.",
+                MAX_READING_LIST_DESCRIPTION_SIZE
+            ),
+        });
+    }
+    Ok((name, description))
+}
+
+fn validate_reorder(current: &[u64], reordered: &[u64]) -> ServiceResult<()> {
+    let expected = current.iter().collect::<BTreeSet<_>>();
+    let given = reordered.iter().collect::<BTreeSet<_>>();
+    if reordered.len() != current.len() || given != expected {
+        return Err(ServiceError::UnprocessableEntity {
+            reason: "Reordered stories must match the stories of the reading list.".to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn reading_list_not_found() -> ServiceError {
+    ServiceError::UnprocessableEntity {
+        reason: "Reading list not found.".to_string(),
+    }
+}
+
+fn map_reading_list_err(e: RepositoryError) -> ServiceError {
+    match e {
+        RepositoryError::NotFound => reading_list_not_found(),
+        _ => ServiceError::InternalError {
+            reason: format!("{:?}", e),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use candid::Principal;
+
+    use crate::{
+        repositories::library::{BookmarkRepository, ReadingHistoryRepository},
+        types::{ReadingListArgs, ReadingProgress},
+    };
+
+    use super::{validate_reading_list_args, validate_reorder};
+
+    #[test]
+    fn test_validate_reading_list() {
+        let args = |name: &str, description: Option<&str>| ReadingListArgs {
+            name: name.to_string(),
+            description: description.map(|d| d.to_string()),
+            public: false,
+        };
+        assert_eq!(
+            validate_reading_list_args(&args(" Later ", Some(" "))).unwrap(),
+            ("Later".to_string(), None)
+        );
+        assert!(validate_reading_list_args(&args("  ", None)).is_err());
+        assert!(validate_reading_list_args(&args(&"a".repeat(51), None)).is_err());
+
+        assert!(validate_reorder(&[1, 2, 3], &[3, 1, 2]).is_ok());
+        assert!(validate_reorder(&[1, 2, 3], &[3, 1]).is_err());
+        assert!(validate_reorder(&[1, 2, 3], &[3, 1, 1]).is_err());
+        assert!(validate_reorder(&[1, 2, 3], &[3, 1, 4]).is_err());
+    }
+
+    #[test]
+    fn test_bookmarks() {
+        let repository = BookmarkRepository;
+        let reader = Principal::from_slice(&[1]);
+        let other = Principal::from_slice(&[2]);
+        assert!(repository.add(reader, 1, 10));
+        assert!(repository.add(reader, 2, 20));
+        assert!(repository.add(reader, 3, 20));
+        assert!(!repository.add(reader, 1, 30));
+        assert!(repository.add(other, 1, 40));
+
+        let page = repository.find_by_user(reader, None, 2);
+        assert_eq!(page, vec![(20, 2), (20, 3)]);
+        assert_eq!(
+            repository.find_by_user(reader, page.last().cloned(), 2),
+            vec![(10, 1)]
+        );
+
+        assert_eq!(repository.remove_story(1), 2);
+        assert_eq!(repository.count(reader), 2);
+        assert_eq!(repository.count(other), 0);
+        assert!(repository.remove(reader, 2));
+        assert!(!repository.remove(reader, 2));
+        assert_eq!(repository.find_by_user(reader, None, 10), vec![(20, 3)]);
+    }
+
+    #[test]
+    fn test_reading_history() {
+        let repository = ReadingHistoryRepository;
+        let reader = Principal::from_slice(&[3]);
+        let progress = |story_id, updated_at| ReadingProgress {
+            story_id,
+            position: story_id * 10,
+            updated_at,
+        };
+        repository.save(reader, progress(1, 10), 2);
+        repository.save(reader, progress(2, 20), 2);
+        repository.save(reader, progress(1, 30), 2);
+        assert_eq!(
+            repository.find_by_reader(reader, None, 10),
+            vec![progress(1, 30), progress(2, 20)]
+        );
+
+        repository.save(reader, progress(3, 40), 2);
+        assert_eq!(
+            repository.find_by_reader(reader, None, 10),
+            vec![progress(3, 40), progress(1, 30)]
+        );
+        assert!(repository.get(reader, 2).is_none());
+        assert_eq!(
+            repository.find_by_reader(reader, Some((40, 3)), 10),
+            vec![progress(1, 30)]
+        );
+
+        assert_eq!(repository.remove_story(1), 1);
+        assert_eq!(
+            repository.find_by_reader(reader, None, 10),
+            vec![progress(3, 40)]
+        );
+    }
+}
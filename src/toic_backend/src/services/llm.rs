@@ -1,4 +1,23 @@
-use ic_llm::{chat, ChatMessage, Model, Role};
+use std::{fmt::Debug, future::Future, pin::Pin, sync::Arc};
+
+use candid::{CandidType, Principal};
+use ic_llm::{ChatMessage, Model, Role};
+use lazy_static::lazy_static;
+use serde::Serialize;
+
+use crate::{
+    repositories::llm::{LlmConfigRepository, LLM_CONFIG_REPOSITORY},
+    types::{
+        AssistActionArgs, AssistActionKind, AssistantChatMessage, ChatRole, LlmModel,
+        LlmModelConfig, SafetyLabel, ServiceError, ServiceResult, Tone,
+    },
+    utils::chunk_text,
+};
+
+/// Canister and method called by `ic_llm` 0.4, used until the config sets others
+const DEFAULT_LLM_CANISTER: &str = "w36hm-eqaaa-aaaal-qr76a-cai";
+const DEFAULT_CHAT_METHOD: &str = "v0_chat";
+const FAILED_SENTINEL: &str = "::FAILED::";
 
 const EXPAND_SYSTEM_PROMPT: &str = r#"
 You are an AI writing assistant. Expand the user's paragraph by adding two to three meaningful sentences that match the original tone and topic.
@@ -12,36 +31,677 @@ Respond with the description only.
 If you cannot comply, respond with the exact keyword ::FAILED::.
 "#;
 
-pub async fn expand_paragraph(text: String) -> Result<String, String> {
-    let system = ChatMessage {
-        role: Role::System,
-        content: EXPAND_SYSTEM_PROMPT.to_string(),
-    };
-    let user = ChatMessage {
-        role: Role::User,
-        content: text,
-    };
-    let response = chat(Model::Llama3_1_8B, vec![system, user]).await;
-    if response == "::FAILED::" {
-        return Err("Failed to expand paragraph".to_string());
+const SUGGEST_TITLES_SYSTEM_PROMPT: &str = r#"
+You are an AI writing assistant. Suggest five catchy and fitting titles for the user's story.
+Respond with the titles only, one title per line, without numbering or quotes.
+If you cannot comply, respond with the exact keyword ::FAILED::.
+"#;
+
+const FIX_GRAMMAR_SYSTEM_PROMPT: &str = r#"
+You are an AI proofreader. Fix the spelling, grammar and punctuation mistakes of the user's text without changing its meaning, tone or formatting.
+Respond with the corrected text only.
+If you cannot comply, respond with the exact keyword ::FAILED::.
+"#;
+
+const CONTINUE_STORY_SYSTEM_PROMPT: &str = r#"
+You are an AI writing assistant. Continue the user's story with one to two new paragraphs that follow the plot, characters and style established so far.
+Respond with the new paragraphs only, without repeating the original text.
+If you cannot comply, respond with the exact keyword ::FAILED::.
+"#;
+
+const SUMMARIZE_SYSTEM_PROMPT: &str = r#"
+You are an AI writing assistant. Summarize the user's story in three to five sentences, keeping the key events and characters without adding anything new.
+Respond with the summary only.
+If you cannot comply, respond with the exact keyword ::FAILED::.
+"#;
+
+const CHANGE_TONE_SYSTEM_PROMPT: &str = r#"
+You are an AI writing assistant. Rewrite the user's text with a {tone} tone while keeping its meaning and plot intact.
+Respond with the rewritten text only.
+If you cannot comply, respond with the exact keyword ::FAILED::.
+"#;
+
+const CHAT_SYSTEM_PROMPT: &str = r#"
+You are an AI writing assistant helping an author with the draft of their story. Answer the author's questions and requests about the draft, staying concise and helpful.
+If you cannot comply, respond with the exact keyword ::FAILED::.
+The current draft is:
+"#;
+
+const SAFETY_SYSTEM_PROMPT: &str = r#"
+You are a content moderator. Classify the user's story text for the following labels: mature (sexual or explicit content), violence (graphic violence or gore), hate (hate speech or harassment).
+Respond with exactly three lines in the format `label: answer`, where answer is yes, no or unsure. For example:
+mature: no
+violence: unsure
+hate: no
+If you cannot comply, respond with the exact keyword ::FAILED::.
+"#;
+
+const MAX_SUGGESTED_TITLES: usize = 5;
+/// Chunks of a story sampled by the safety classification, bounding the calls per publish
+const MAX_SAFETY_CHUNKS: usize = 3;
+/// Characters of the draft given to the chat as context
+const MAX_CHAT_DRAFT_CHARS: usize = 4_000;
+/// Characters of the previous messages replayed to the chat
+const MAX_CHAT_HISTORY_CHARS: usize = 4_000;
+const MAX_CHAT_HISTORY_MESSAGES: usize = 20;
+/// Characters sent to the model in a single request, keeps the prompt within the context window
+pub const MAX_CHUNK_CHARS: usize = 6_000;
+/// Chunks a single assist request can be split into
+pub const MAX_ASSIST_CHUNKS: usize = 8;
+
+lazy_static! {
+    pub static ref LLM_SERVICE: Arc<LlmService> = Arc::new(LlmService::new(
+        Arc::new(IcLlmProvider::new(LLM_CONFIG_REPOSITORY.clone())),
+        LLM_CONFIG_REPOSITORY.clone()
+    ));
+}
+
+pub type LlmFuture<'a> = Pin<Box<dyn Future<Output = Result<String, String>> + 'a>>;
+
+/// Backend answering the chat completions of the writing assistant
+pub trait LlmProvider: Debug + Send + Sync {
+    fn chat(&self, model: LlmModel, messages: Vec<ChatMessage>) -> LlmFuture<'_>;
+}
+
+#[derive(CandidType, Serialize)]
+struct ChatRequest {
+    model: String,
+    messages: Vec<ChatMessage>,
+}
+
+/// Provider backed by the LLM canister, a rejected call is reported instead of trapping. The
+/// canister and its method are read from the config so they can follow the LLM canister
+/// without an upgrade
+#[derive(Debug)]
+pub struct IcLlmProvider {
+    config_repository: Arc<LlmConfigRepository>,
+}
+
+impl IcLlmProvider {
+    pub fn new(config_repository: Arc<LlmConfigRepository>) -> Self {
+        Self { config_repository }
+    }
+}
+
+impl LlmProvider for IcLlmProvider {
+    fn chat(&self, model: LlmModel, messages: Vec<ChatMessage>) -> LlmFuture<'_> {
+        let config = self.config_repository.get();
+        Box::pin(async move {
+            let canister = config.canister_id.unwrap_or_else(|| {
+                Principal::from_text(DEFAULT_LLM_CANISTER).expect("invalid canister id")
+            });
+            let method = config
+                .chat_method
+                .unwrap_or_else(|| DEFAULT_CHAT_METHOD.to_string());
+            let request = ChatRequest {
+                model: Model::from(model).to_string(),
+                messages,
+            };
+            ic_cdk::call::<_, (String,)>(canister, &method, (request,))
+                .await
+                .map(|(response,)| response)
+                .map_err(|(code, message)| format!("LLM unavailable ({:?}): {}", code, message))
+        })
+    }
+}
+
+/// Result of the safety classification of a story
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SafetyClassification {
+    pub labels: Vec<SafetyLabel>,
+    pub uncertain: Vec<SafetyLabel>,
+}
+
+/// Text surrounding a selection, given to the model so the result fits in
+#[derive(Debug, Clone, Default)]
+pub struct SelectionContext {
+    pub before: String,
+    pub after: String,
+}
+
+#[derive(Debug)]
+pub struct LlmService {
+    provider: Arc<dyn LlmProvider>,
+    config_repository: Arc<LlmConfigRepository>,
+}
+
+impl LlmService {
+    pub fn new(
+        provider: Arc<dyn LlmProvider>,
+        config_repository: Arc<LlmConfigRepository>,
+    ) -> Self {
+        Self {
+            provider,
+            config_repository,
+        }
+    }
+
+    pub fn get_model_config(&self) -> LlmModelConfig {
+        self.config_repository.get()
+    }
+
+    pub fn set_model_config(&self, config: LlmModelConfig) -> ServiceResult<LlmModelConfig> {
+        if config
+            .chat_method
+            .as_ref()
+            .is_some_and(|method| method.trim().is_empty())
+        {
+            return Err(ServiceError::UnprocessableEntity {
+                reason: "Chat method cannot be empty.".to_string(),
+            });
+        }
+        self.config_repository
+            .set(config)
+            .map_err(|e| ServiceError::InternalError {
+                reason: format!("{:?}", e),
+            })
+    }
+
+    /// Runs the assist action over text split into chunks. Rewriting actions are applied to
+    /// every chunk, continuing only looks at the last one, and the other actions work on the
+    /// summaries of the chunks when there is more than one.
+    pub async fn assist_chunks(
+        &self,
+        action: &AssistActionArgs,
+        mut chunks: Vec<String>,
+        context: Option<&SelectionContext>,
+    ) -> Result<String, String> {
+        if chunks.is_empty() {
+            return Err("Nothing to assist with".to_string());
+        }
+        match action {
+            AssistActionArgs::ExpandWriting(_)
+            | AssistActionArgs::FixGrammar(_)
+            | AssistActionArgs::ChangeTone(_, _) => {
+                let mut results = Vec::with_capacity(chunks.len());
+                for chunk in chunks {
+                    results.push(self.assist(action, chunk, context).await?);
+                }
+                Ok(results.join("\n\n"))
+            }
+            AssistActionArgs::ContinueStory(_) => {
+                let last = chunks.pop().unwrap_or_default();
+                self.assist(action, last, context).await
+            }
+            AssistActionArgs::GenerateDescription(_)
+            | AssistActionArgs::SuggestTitles(_)
+            | AssistActionArgs::Summarize(_) => {
+                if chunks.len() == 1 {
+                    return self.assist(action, chunks.remove(0), context).await;
+                }
+                let mut summaries = Vec::with_capacity(chunks.len());
+                for chunk in chunks {
+                    summaries.push(self.summarize(chunk).await?);
+                }
+                self.assist(action, summaries.join("\n\n"), None).await
+            }
+        }
+    }
+
+    /// Runs the assist action on the text, titles are returned one per line
+    pub async fn assist(
+        &self,
+        action: &AssistActionArgs,
+        text: String,
+        context: Option<&SelectionContext>,
+    ) -> Result<String, String> {
+        let text = match context {
+            Some(context) => frame_selection(text, context),
+            None => text,
+        };
+        match action {
+            AssistActionArgs::ExpandWriting(_) => self.expand_paragraph(text).await,
+            AssistActionArgs::GenerateDescription(_) => self.write_story_description(text).await,
+            AssistActionArgs::SuggestTitles(_) => {
+                self.suggest_titles(text).await.map(|t| t.join("\n"))
+            }
+            AssistActionArgs::FixGrammar(_) => self.fix_grammar(text).await,
+            AssistActionArgs::ContinueStory(_) => self.continue_story(text).await,
+            AssistActionArgs::Summarize(_) => self.summarize(text).await,
+            AssistActionArgs::ChangeTone(_, tone) => self.change_tone(text, *tone).await,
+        }
+    }
+
+    pub async fn expand_paragraph(&self, text: String) -> Result<String, String> {
+        self.complete(AssistActionKind::ExpandWriting, EXPAND_SYSTEM_PROMPT, text)
+            .await?
+            .ok_or("Failed to expand paragraph".to_string())
+    }
+
+    pub async fn write_story_description(&self, text: String) -> Result<String, String> {
+        self.complete(
+            AssistActionKind::GenerateDescription,
+            STORY_DETAIL_SYSTEM_PROMPT,
+            text,
+        )
+        .await?
+        .ok_or("Failed to write story description".to_string())
+    }
+
+    pub async fn suggest_titles(&self, text: String) -> Result<Vec<String>, String> {
+        let titles = self
+            .complete(
+                AssistActionKind::SuggestTitles,
+                SUGGEST_TITLES_SYSTEM_PROMPT,
+                text,
+            )
+            .await?
+            .map(|response| parse_titles(&response))
+            .unwrap_or_default();
+        if titles.is_empty() {
+            return Err("Failed to suggest titles".to_string());
+        }
+        Ok(titles)
+    }
+
+    pub async fn fix_grammar(&self, text: String) -> Result<String, String> {
+        self.complete(
+            AssistActionKind::FixGrammar,
+            FIX_GRAMMAR_SYSTEM_PROMPT,
+            text,
+        )
+        .await?
+        .ok_or("Failed to fix grammar".to_string())
     }
 
-    Ok(response.to_string())
+    pub async fn continue_story(&self, text: String) -> Result<String, String> {
+        self.complete(
+            AssistActionKind::ContinueStory,
+            CONTINUE_STORY_SYSTEM_PROMPT,
+            text,
+        )
+        .await?
+        .ok_or("Failed to continue story".to_string())
+    }
+
+    pub async fn summarize(&self, text: String) -> Result<String, String> {
+        self.complete(AssistActionKind::Summarize, SUMMARIZE_SYSTEM_PROMPT, text)
+            .await?
+            .ok_or("Failed to summarize story".to_string())
+    }
+
+    pub async fn change_tone(&self, text: String, tone: Tone) -> Result<String, String> {
+        let prompt = CHANGE_TONE_SYSTEM_PROMPT.replace("{tone}", tone_name(tone));
+        self.complete(AssistActionKind::ChangeTone, &prompt, text)
+            .await?
+            .ok_or("Failed to change tone".to_string())
+    }
+
+    pub fn is_safety_check_enabled(&self) -> bool {
+        self.config_repository.get().safety_check
+    }
+
+    /// Classifies a story over a few chunks sampled across its content
+    pub async fn classify_safety(&self, content: &str) -> Result<SafetyClassification, String> {
+        let model = self
+            .config_repository
+            .get()
+            .model_for(AssistActionKind::SafetyCheck);
+        let mut classification = SafetyClassification::default();
+        for chunk in sample_chunks(chunk_text(content, MAX_CHUNK_CHARS), MAX_SAFETY_CHUNKS) {
+            let messages = vec![
+                ChatMessage {
+                    role: Role::System,
+                    content: SAFETY_SYSTEM_PROMPT.to_string(),
+                },
+                ChatMessage {
+                    role: Role::User,
+                    content: chunk,
+                },
+            ];
+            let response = self.provider.chat(model, messages).await?;
+            if response.contains(FAILED_SENTINEL) {
+                return Err("Failed to classify story".to_string());
+            }
+            merge_classification(&mut classification, parse_safety_labels(&response));
+        }
+        classification
+            .uncertain
+            .retain(|label| !classification.labels.contains(label));
+        Ok(classification)
+    }
+
+    /// Answers the author's message, given the draft and the recent conversation
+    pub async fn chat(
+        &self,
+        draft: &str,
+        history: &[AssistantChatMessage],
+        message: String,
+    ) -> Result<String, String> {
+        let mut messages = vec![ChatMessage {
+            role: Role::System,
+            content: format!(
+                "{}{}",
+                CHAT_SYSTEM_PROMPT,
+                draft.chars().take(MAX_CHAT_DRAFT_CHARS).collect::<String>()
+            ),
+        }];
+        messages.extend(recent_history(history).iter().map(|m| ChatMessage {
+            role: match m.role {
+                ChatRole::User => Role::User,
+                ChatRole::Assistant => Role::Assistant,
+            },
+            content: m.content.clone(),
+        }));
+        messages.push(ChatMessage {
+            role: Role::User,
+            content: message,
+        });
+
+        let model = self
+            .config_repository
+            .get()
+            .model_for(AssistActionKind::Chat);
+        let response = self.provider.chat(model, messages).await?;
+        let response = response.trim();
+        if response.is_empty() || response.contains(FAILED_SENTINEL) {
+            return Err("Failed to answer the message".to_string());
+        }
+        Ok(response.to_string())
+    }
+
+    /// Sends the prompt with the model configured for the action, `None` when the model
+    /// answered with the failure sentinel
+    async fn complete(
+        &self,
+        action: AssistActionKind,
+        system_prompt: &str,
+        text: String,
+    ) -> Result<Option<String>, String> {
+        let system = ChatMessage {
+            role: Role::System,
+            content: system_prompt.to_string(),
+        };
+        let user = ChatMessage {
+            role: Role::User,
+            content: text,
+        };
+        let model = self.config_repository.get().model_for(action);
+        let response = self.provider.chat(model, vec![system, user]).await?;
+        let response = response.trim();
+        if response.is_empty() || response.contains(FAILED_SENTINEL) {
+            return Ok(None);
+        }
+        Ok(Some(response.to_string()))
+    }
+}
+
+/// Picks at most `max` chunks evenly spread over the text, always keeping the first and last
+fn sample_chunks(chunks: Vec<String>, max: usize) -> Vec<String> {
+    if chunks.len() <= max || max < 2 {
+        return chunks.into_iter().take(max.max(1)).collect();
+    }
+    let last = chunks.len() - 1;
+    let picks = (0..max).map(|i| i * last / (max - 1)).collect::<Vec<_>>();
+    chunks
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| picks.contains(i))
+        .map(|(_, chunk)| chunk)
+        .collect()
+}
+
+/// Parses the `label: answer` lines, a label without a clear answer is uncertain
+fn parse_safety_labels(response: &str) -> SafetyClassification {
+    let mut classification = SafetyClassification::default();
+    for (label, name) in [
+        (SafetyLabel::Mature, "mature"),
+        (SafetyLabel::Violence, "violence"),
+        (SafetyLabel::Hate, "hate"),
+    ] {
+        let answer = response.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            (key.trim().trim_matches('`').to_lowercase() == name)
+                .then(|| value.trim().trim_matches('`').to_lowercase())
+        });
+        match answer.as_deref() {
+            Some("yes") => classification.labels.push(label),
+            Some("no") => {}
+            _ => classification.uncertain.push(label),
+        }
+    }
+    classification
+}
+
+fn merge_classification(into: &mut SafetyClassification, other: SafetyClassification) {
+    for label in other.labels {
+        if !into.labels.contains(&label) {
+            into.labels.push(label);
+        }
+    }
+    for label in other.uncertain {
+        if !into.uncertain.contains(&label) {
+            into.uncertain.push(label);
+        }
+    }
+}
+
+/// Takes the latest messages that fit the history budget of the chat
+fn recent_history(history: &[AssistantChatMessage]) -> &[AssistantChatMessage] {
+    let mut size = 0;
+    let mut count = 0;
+    for message in history.iter().rev().take(MAX_CHAT_HISTORY_MESSAGES) {
+        size += message.content.chars().count();
+        if size > MAX_CHAT_HISTORY_CHARS {
+            break;
+        }
+        count += 1;
+    }
+    &history[history.len() - count..]
+}
+
+/// Wraps the selected text with its surroundings, marking what the model has to work on
+fn frame_selection(text: String, context: &SelectionContext) -> String {
+    format!(
+        "Apply the instructions only to the text inside the <selection> tags, the text around it is context and must not be repeated.\n\n{}<selection>{}</selection>{}",
+        context.before, text, context.after
+    )
+}
+
+fn tone_name(tone: Tone) -> &'static str {
+    match tone {
+        Tone::Formal => "formal",
+        Tone::Casual => "casual",
+        Tone::Humorous => "humorous",
+        Tone::Dramatic => "dramatic",
+        Tone::Suspenseful => "suspenseful",
+        Tone::Poetic => "poetic",
+    }
 }
 
-pub async fn write_story_description(text: String) -> Result<String, String> {
-    let system = ChatMessage {
-        role: Role::System,
-        content: STORY_DETAIL_SYSTEM_PROMPT.to_string(),
+/// Extracts the titles from the model response, dropping any list markers or quotes
+fn parse_titles(response: &str) -> Vec<String> {
+    response
+        .lines()
+        .map(|line| {
+            line.trim()
+                .trim_start_matches(|c: char| c.is_ascii_digit() || "-*.)".contains(c))
+                .trim()
+                .trim_matches('"')
+                .trim()
+                .to_string()
+        })
+        .filter(|title| !title.is_empty())
+        .take(MAX_SUGGESTED_TITLES)
+        .collect()
+}
+
+/// Deterministic provider answering with a fixed response, or echoing the last message
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct StubLlmProvider {
+    pub response: Option<String>,
+}
+
+#[cfg(test)]
+impl LlmProvider for StubLlmProvider {
+    fn chat(&self, model: LlmModel, messages: Vec<ChatMessage>) -> LlmFuture<'_> {
+        let response = self.response.clone().unwrap_or_else(|| {
+            let last = messages
+                .last()
+                .map(|m| m.content.clone())
+                .unwrap_or_default();
+            format!("{:?}: {}", model, last)
+        });
+        Box::pin(async move { Ok(response) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{
+        repositories::llm::LLM_CONFIG_REPOSITORY,
+        types::{
+            AssistActionArgs, AssistantChatMessage, ChatRole, LlmModelConfig, SafetyLabel, Tone,
+        },
+        utils::mocks::block_on,
     };
-    let user = ChatMessage {
-        role: Role::User,
-        content: text,
+
+    use super::{
+        parse_safety_labels, parse_titles, recent_history, sample_chunks, LlmService,
+        SafetyClassification, SelectionContext, StubLlmProvider, MAX_CHAT_HISTORY_MESSAGES,
     };
-    let response = chat(Model::Llama3_1_8B, vec![system, user]).await;
-    if response == "::FAILED::" {
-        return Err("Failed to write story description".to_string());
+
+    fn stub_service(response: Option<&str>) -> LlmService {
+        LlmService::new(
+            Arc::new(StubLlmProvider {
+                response: response.map(|r| r.to_string()),
+            }),
+            LLM_CONFIG_REPOSITORY.clone(),
+        )
+    }
+
+    #[test]
+    fn test_set_chat_method() {
+        let service = stub_service(None);
+        let config = |method: &str| LlmModelConfig {
+            chat_method: Some(method.to_string()),
+            ..Default::default()
+        };
+        assert!(service.set_model_config(config(" ")).is_err());
+        assert!(service.set_model_config(config("v1_chat")).is_ok());
+        assert_eq!(
+            service.get_model_config().chat_method,
+            Some("v1_chat".to_string())
+        );
+    }
+
+    #[test]
+    fn test_expand_paragraph_with_stub() {
+        let service = stub_service(None);
+        let result = block_on(service.expand_paragraph("Once upon a time".to_string()));
+        assert_eq!(result.unwrap(), "Llama3_1_8B: Once upon a time".to_string());
+    }
+
+    #[test]
+    fn test_failed_sentinel() {
+        let service = stub_service(Some("::FAILED::"));
+        let result = block_on(service.write_story_description("content".to_string()));
+        assert_eq!(
+            result.unwrap_err(),
+            "Failed to write story description".to_string()
+        );
     }
 
-    Ok(response.to_string())
+    #[test]
+    fn test_parse_titles() {
+        let response = "1. The Last Light\n2) \"Echoes of Dawn\"\n\n- A Quiet Storm";
+        assert_eq!(
+            parse_titles(response),
+            vec![
+                "The Last Light".to_string(),
+                "Echoes of Dawn".to_string(),
+                "A Quiet Storm".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_new_actions_fail_on_sentinel() {
+        let service = stub_service(Some(" ::FAILED::\n"));
+        assert!(block_on(service.suggest_titles("content".to_string())).is_err());
+        assert!(block_on(service.fix_grammar("content".to_string())).is_err());
+        assert!(block_on(service.continue_story("content".to_string())).is_err());
+        assert!(block_on(service.summarize("content".to_string())).is_err());
+        assert!(block_on(service.change_tone("content".to_string(), Tone::Formal)).is_err());
+    }
+
+    #[test]
+    fn test_assist_chunks() {
+        let service = stub_service(None);
+        let chunks = vec!["first".to_string(), "second".to_string()];
+
+        let result =
+            block_on(service.assist_chunks(&AssistActionArgs::FixGrammar(0), chunks.clone(), None));
+        assert_eq!(
+            result.unwrap(),
+            "Llama3_1_8B: first\n\nLlama3_1_8B: second".to_string()
+        );
+
+        let result =
+            block_on(service.assist_chunks(&AssistActionArgs::ContinueStory(0), chunks, None));
+        assert_eq!(result.unwrap(), "Llama3_1_8B: second".to_string());
+
+        let context = SelectionContext {
+            before: "A ".to_string(),
+            after: " C".to_string(),
+        };
+        let result = block_on(service.assist(
+            &AssistActionArgs::FixGrammar(0),
+            "B".to_string(),
+            Some(&context),
+        ))
+        .unwrap();
+        assert!(result.ends_with("A <selection>B</selection> C"));
+    }
+
+    #[test]
+    fn test_chat_history_is_truncated() {
+        let message = |content: String| AssistantChatMessage {
+            role: ChatRole::User,
+            content,
+            created_at: 0,
+        };
+        let history = (0..30).map(|i| message(i.to_string())).collect::<Vec<_>>();
+        let recent = recent_history(&history);
+        assert_eq!(recent.len(), MAX_CHAT_HISTORY_MESSAGES);
+        assert_eq!(recent.last().unwrap().content, "29".to_string());
+
+        let history = vec![message("a".repeat(3_000)), message("b".repeat(3_000))];
+        let recent = recent_history(&history);
+        assert_eq!(recent.len(), 1);
+        assert!(recent[0].content.starts_with('b'));
+
+        let service = stub_service(None);
+        let reply = block_on(service.chat("draft", &history, "hello".to_string()));
+        assert_eq!(reply.unwrap(), "Llama3_1_8B: hello".to_string());
+    }
+
+    #[test]
+    fn test_safety_classification() {
+        assert_eq!(
+            parse_safety_labels("Mature: yes\nviolence: unsure\nhate: no"),
+            SafetyClassification {
+                labels: vec![SafetyLabel::Mature],
+                uncertain: vec![SafetyLabel::Violence],
+            }
+        );
+        // missing answers cannot be trusted
+        assert_eq!(
+            parse_safety_labels("mature: no").uncertain,
+            vec![SafetyLabel::Violence, SafetyLabel::Hate]
+        );
+
+        let chunks = (0..10).map(|i| i.to_string()).collect::<Vec<_>>();
+        assert_eq!(sample_chunks(chunks, 3), vec!["0", "4", "9"]);
+
+        let service = stub_service(Some("mature: yes\nviolence: no\nhate: unsure"));
+        let classification = block_on(service.classify_safety("story")).unwrap();
+        assert_eq!(classification.labels, vec![SafetyLabel::Mature]);
+        assert_eq!(classification.uncertain, vec![SafetyLabel::Hate]);
+        let service = stub_service(Some("::FAILED::"));
+        assert!(block_on(service.classify_safety("story")).is_err());
+    }
 }
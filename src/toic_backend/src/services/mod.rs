@@ -1,4 +1,7 @@
 pub mod draft;
+pub mod library;
 pub mod llm;
+pub mod statistics;
 pub mod story;
+pub mod treasury;
 pub mod user;
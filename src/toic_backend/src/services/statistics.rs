@@ -0,0 +1,246 @@
+use std::{sync::Arc, time::Duration};
+
+use lazy_static::lazy_static;
+
+use crate::{
+    repositories::{
+        draft::{DraftRepository, DRAFT_REPOSITORY},
+        statistics::{StatisticsRepository, STATISTICS_REPOSITORY},
+        story::{StoryRepository, STORY_REPOSITORY},
+        user::{UserRepository, USER_REPOSITORY},
+    },
+    structure::BinaryTreeRepository,
+    token::{LedgerService, LEDGER_SERVICE},
+    types::{PlatformCounters, Statistics, StatisticsRebuild},
+};
+
+/// Entities counted by a single message of the recount
+const STATISTICS_REBUILD_BATCH_SIZE: usize = 500;
+
+lazy_static! {
+    pub static ref STATISTICS_SERVICE: Arc<StatisticsService> = Arc::new(StatisticsService::new(
+        STATISTICS_REPOSITORY.clone(),
+        USER_REPOSITORY.clone(),
+        DRAFT_REPOSITORY.clone(),
+        STORY_REPOSITORY.clone(),
+        LEDGER_SERVICE.clone(),
+    ));
+}
+
+#[derive(Debug)]
+pub struct StatisticsService {
+    statistics_repository: Arc<StatisticsRepository>,
+    user_repository: Arc<UserRepository>,
+    draft_repository: Arc<DraftRepository>,
+    story_repository: Arc<StoryRepository>,
+    ledger_service: Arc<LedgerService>,
+}
+
+impl StatisticsService {
+    pub fn new(
+        statistics_repository: Arc<StatisticsRepository>,
+        user_repository: Arc<UserRepository>,
+        draft_repository: Arc<DraftRepository>,
+        story_repository: Arc<StoryRepository>,
+        ledger_service: Arc<LedgerService>,
+    ) -> Self {
+        Self {
+            statistics_repository,
+            user_repository,
+            draft_repository,
+            story_repository,
+            ledger_service,
+        }
+    }
+
+    pub fn get_statistics(&self) -> Statistics {
+        let counters = self.statistics_repository.get();
+        Statistics {
+            rebuilding: !counters.is_ready(),
+            total_users: counters.total_users,
+            total_stories: counters.total_stories,
+            total_drafts: counters.total_drafts,
+            total_categories: self.story_repository.get_categories().len() as u32,
+            category_followers: counters
+                .category_followers
+                .into_iter()
+                .filter(|(_, followers)| *followers > 0)
+                .collect(),
+            category_stories: counters
+                .category_stories
+                .into_iter()
+                .filter(|(_, stories)| *stories > 0)
+                .collect(),
+            token: self.ledger_service.get_token_statistics(),
+        }
+    }
+
+    /// Starts the recount over, the totals are read from the stored maps right away and the
+    /// per-category counters are recounted by `rebuild_batch`. Returns false when a recount was
+    /// already running, its batches carry on from the start
+    pub fn begin_rebuild(&self) -> bool {
+        let running = self.statistics_repository.get().rebuild.is_some();
+        self.statistics_repository.set(PlatformCounters {
+            initialized: false,
+            total_users: self.user_repository.count() as u32,
+            total_drafts: self.draft_repository.count() as u32,
+            total_stories: self.story_repository.count() as u32,
+            rebuild: Some(StatisticsRebuild::default()),
+            ..Default::default()
+        });
+        !running
+    }
+
+    /// Keeps the recount interrupted by an upgrade, or begins one when the counters were never
+    /// computed. Returns whether a recount has batches to run
+    pub fn resume_rebuild(&self) -> bool {
+        let counters = self.statistics_repository.get();
+        if counters.rebuild.is_none() && !counters.initialized {
+            self.begin_rebuild();
+            return true;
+        }
+        counters.rebuild.is_some()
+    }
+
+    /// Counts the next `limit` users, or stories once every user is counted. Returns true when
+    /// the recount is done
+    pub fn rebuild_batch(&self, limit: usize) -> bool {
+        let mut counters = self.statistics_repository.get();
+        let Some(mut rebuild) = counters.rebuild.take() else {
+            return true;
+        };
+        if !rebuild.users_counted {
+            let users = self
+                .user_repository
+                .get_users_after(rebuild.last_user, limit);
+            for user in &users {
+                for category in &user.followed_categories {
+                    *counters.category_followers.entry(*category).or_default() += 1;
+                }
+            }
+            rebuild.users_counted = users.len() < limit;
+            rebuild.last_user = users.last().map(|u| u.id).or(rebuild.last_user);
+        } else {
+            let stories = self
+                .story_repository
+                .get_stories_from(rebuild.next_story, limit);
+            for story in &stories {
+                *counters
+                    .category_stories
+                    .entry(story.detail.category)
+                    .or_default() += 1;
+            }
+            if stories.len() < limit {
+                counters.initialized = true;
+                self.statistics_repository.set(counters);
+                return true;
+            }
+            rebuild.next_story = stories.last().map_or(rebuild.next_story, |s| s.id + 1);
+        }
+        counters.rebuild = Some(rebuild);
+        self.statistics_repository.set(counters);
+        false
+    }
+}
+
+/// Runs the recount interrupted by an upgrade, or the first one when the counters were never
+/// computed, one batch per message
+pub fn resume_statistics_rebuild() {
+    if STATISTICS_SERVICE.resume_rebuild() {
+        schedule_rebuild_batch();
+    }
+}
+
+/// Recounts every counter from the stored entities, one batch per message
+pub fn restart_statistics_rebuild() {
+    if STATISTICS_SERVICE.begin_rebuild() {
+        schedule_rebuild_batch();
+    }
+}
+
+fn schedule_rebuild_batch() {
+    ic_cdk_timers::set_timer(Duration::ZERO, || {
+        if !STATISTICS_SERVICE.rebuild_batch(STATISTICS_REBUILD_BATCH_SIZE) {
+            schedule_rebuild_batch();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use candid::Principal;
+
+    use crate::{
+        repositories::{
+            statistics::STATISTICS_REPOSITORY, story::STORY_REPOSITORY, user::USER_REPOSITORY,
+        },
+        structure::{AuditableRepository, Repository},
+        types::{Category, Draft, Story, StoryDetail, User},
+    };
+
+    use super::STATISTICS_SERVICE;
+
+    #[test]
+    fn test_statistics_counters() {
+        let user = Principal::anonymous();
+        STATISTICS_REPOSITORY.add_user();
+        STATISTICS_REPOSITORY.add_category_followers(user, &[Category::SciFi, Category::Horror]);
+        STATISTICS_REPOSITORY.add_draft();
+        STATISTICS_REPOSITORY.add_story(1, Category::Horror);
+        STATISTICS_REPOSITORY.add_story(2, Category::Crime);
+        STATISTICS_REPOSITORY.remove_story(2, Category::Crime);
+        STATISTICS_REPOSITORY.remove_draft();
+        STATISTICS_REPOSITORY.remove_draft();
+
+        let stats = STATISTICS_SERVICE.get_statistics();
+        assert_eq!(stats.total_users, 1);
+        assert_eq!(stats.total_drafts, 0);
+        assert_eq!(stats.total_stories, 1);
+        assert_eq!(stats.total_categories, 10);
+        assert_eq!(stats.category_stories, vec![(Category::Horror, 1)]);
+        assert_eq!(
+            stats.category_followers,
+            vec![(Category::SciFi, 1), (Category::Horror, 1)]
+        );
+        assert_eq!(stats.token.holders, 0);
+    }
+
+    #[test]
+    fn test_rebuild_in_batches() {
+        let users = ["aaaaa-aa", "rdmx6-jaaaa-aaaaa-aaadq-cai", "2vxsx-fae"]
+            .map(|text| Principal::from_text(text).unwrap());
+        for user in users {
+            let mut user = User::new(user, 0);
+            user.followed_categories = vec![Category::Horror];
+            USER_REPOSITORY.insert(user).unwrap();
+        }
+        let mut stories = Vec::new();
+        for category in [Category::Horror, Category::Crime, Category::Crime] {
+            let draft = Draft::new("Title".to_string(), None, users[0]);
+            let detail = StoryDetail::new("Description".to_string(), false, category);
+            stories.push(STORY_REPOSITORY.insert(Story::new(draft, detail)).unwrap());
+        }
+
+        assert!(STATISTICS_SERVICE.resume_rebuild());
+        assert!(STATISTICS_SERVICE.get_statistics().rebuilding);
+        assert!(!STATISTICS_SERVICE.rebuild_batch(2));
+        // a change to a user not counted yet is left to the recount
+        let uncounted = users.iter().max().unwrap();
+        STATISTICS_REPOSITORY.remove_category_followers(*uncounted, &[Category::Horror]);
+        STATISTICS_REPOSITORY.add_category_followers(*uncounted, &[Category::Horror]);
+        assert!(!STATISTICS_SERVICE.rebuild_batch(2));
+        assert!(!STATISTICS_SERVICE.rebuild_batch(2));
+        // a story removed after being counted is taken out again
+        let story = STORY_REPOSITORY.delete(&stories[0].id).unwrap();
+        assert_eq!(story, stories[0].id);
+        assert!(STATISTICS_SERVICE.rebuild_batch(2));
+
+        let stats = STATISTICS_SERVICE.get_statistics();
+        assert!(!stats.rebuilding);
+        assert_eq!(stats.total_users, 3);
+        assert_eq!(stats.total_stories, 2);
+        assert_eq!(stats.category_followers, vec![(Category::Horror, 3)]);
+        assert_eq!(stats.category_stories, vec![(Category::Crime, 2)]);
+        assert!(!STATISTICS_SERVICE.resume_rebuild());
+    }
+}
@@ -1,6 +1,11 @@
-use std::sync::Arc;
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+    time::Duration,
+};
 
-use candid::{Nat, Principal};
+use candid::Principal;
 use icrc_ledger_types::icrc1::transfer::{TransferArg, TransferError};
 use itertools::Itertools;
 use lazy_static::lazy_static;
@@ -16,21 +21,50 @@ use crate::{
             StoryContentRepository, StoryRepository, STORY_CONTENT_REPOSITORY, STORY_REPOSITORY,
         },
     },
-    services::user::USER_SERVICE,
+    services::{
+        treasury::{TreasuryService, TIP_POINT_UNIT, TREASURY_SERVICE},
+        user::USER_SERVICE,
+    },
     structure::{AuditableRepository, BinaryTreeRepository},
-    token::{LedgerService, LEDGER_SERVICE},
+    token::{LedgerService, Tokens, LEDGER_SERVICE},
     types::{
-        Category, RepositoryError, Score, ServiceError, ServiceResult, SortOrder, Story,
-        StoryContent, StoryInteractionArgs, SupportSize, User, UserOutline,
+        AssistActionArgs, AssistReplacement, AssistSelection, AuthorAnalytics, AuthorStanding,
+        Category, CategoryDefinition, CategoryInfo, DailyStoryActivity, Engagement,
+        EngagementTotal, LeaderboardWindow, ModerationEntry, NotificationKind, RankingConfig,
+        RepositoryError, SafetyLabel, Score, SelectionRange, ServiceError, ServiceResult,
+        SortOrder, Story, StoryAnalytics, StoryContent, StoryInteractionArgs, StoryTerms,
+        SupportSize, SupporterStanding, UpdateCategoryArgs, User, UserOutline, UserProfile,
+    },
+    utils::{
+        chunk_text, day_index, detect_language, extract_terms, normalize_language, normalize_tag,
+        paragraph_ranges, primary_language, UNDETERMINED_LANGUAGE,
     },
 };
 
 use super::{
-    llm::{expand_paragraph, write_story_description},
+    draft::validate_draft_author,
+    llm::{LlmService, SelectionContext, LLM_SERVICE, MAX_ASSIST_CHUNKS, MAX_CHUNK_CHARS},
     user::{self, UserService},
 };
 
 pub const MAX_STORY_SUPPORT_GIVEN: SupportSize = 10;
+/// Terms kept in the vector of a story
+pub const MAX_STORY_TERMS: usize = 32;
+const RELATED_CANDIDATES_PER_TERM: usize = 100;
+const SAME_CATEGORY_BONUS: f64 = 0.1;
+const DEFAULT_ASSIST_CONTEXT_CHARS: usize = 500;
+const MAX_ASSIST_CONTEXT_CHARS: usize = 2_000;
+/// Fixed point precision of the stored rank
+const RANK_PRECISION: f64 = 1_000_000.0;
+const FOLLOWED_CATEGORY_BONUS: Score = RANK_PRECISION as Score;
+const MIN_RANK_HALF_LIFE_SECS: u64 = 60 * 60;
+const RANK_REFRESH_INTERVAL_SECS: u64 = 10 * 60;
+const RANK_REFRESH_BATCH_SIZE: usize = 500;
+const ENGAGEMENT_PRUNE_INTERVAL_SECS: u64 = 60 * 60;
+const MAX_ANALYTICS_DAYS: u64 = 366;
+const MAX_TOP_SUPPORTERS: usize = 10;
+const MAX_CATEGORY_NAME_SIZE: usize = 30;
+const MAX_STORY_TRANSLATIONS: usize = 50;
 
 lazy_static! {
     pub static ref STORY_SERVICE: Arc<StoryService> = Arc::new(StoryService::new(
@@ -39,6 +73,8 @@ lazy_static! {
         DRAFT_CONTENT_REPOSITORY.clone(),
         LEDGER_SERVICE.clone(),
         USER_SERVICE.clone(),
+        TREASURY_SERVICE.clone(),
+        LLM_SERVICE.clone(),
     ));
 }
 
@@ -49,6 +85,8 @@ pub struct StoryService {
     draft_content_repository: Arc<DraftContentRepository>,
     ledger_service: Arc<LedgerService>,
     user_service: Arc<UserService>,
+    treasury_service: Arc<TreasuryService>,
+    llm_service: Arc<LlmService>,
 }
 
 impl StoryService {
@@ -59,6 +97,8 @@ impl StoryService {
         draft_repository: Arc<DraftContentRepository>,
         ledger_service: Arc<LedgerService>,
         user_service: Arc<UserService>,
+        treasury_service: Arc<TreasuryService>,
+        llm_service: Arc<LlmService>,
     ) -> Self {
         Self {
             story_repository,
@@ -66,19 +106,28 @@ impl StoryService {
             draft_content_repository: draft_repository,
             ledger_service,
             user_service,
+            treasury_service,
+            llm_service,
         }
     }
 
-    pub fn get_story(&self, id: &u64) -> ServiceResult<(Story, StoryContent)> {
+    /// Gets a story with its content, a mature story the reader has not opted into comes
+    /// masked and without content
+    pub fn get_story(&self, id: &u64, reader: Principal) -> ServiceResult<(Story, StoryContent)> {
         let story = self
             .story_repository
             .get(id)
             .map(|s| self.add_author_name(s))
             .ok_or(ServiceError::StoryNotFound)?;
-        let content = self
+        let mut content = self
             .story_content_repository
             .get(id)
             .ok_or(ServiceError::StoryNotFound)?;
+        let access = self.mature_content_access(reader);
+        if story.author != reader && story.detail.mature_content && access != MatureAccess::Show {
+            content.content = String::new();
+            return Ok((mask_story(story), content));
+        }
         Ok((story, content))
     }
 
@@ -97,25 +146,27 @@ impl StoryService {
             .get(&args.id)
             .ok_or(ServiceError::StoryNotFound)?;
         validate_supporter(story.author, identity)?;
-        let (mut support_given, mut tip_given) = self
+        self.user_service
+            .ensure_not_blocked(story.author, identity)?;
+        let given = self
             .story_repository
             .get_story_supporter_size(args.id, identity)
-            .map_err(map_story_err)?
-            .unwrap_or_default();
+            .map_err(map_story_err)?;
+        let is_new_supporter = given.is_none();
+        let (mut support_given, mut tip_given) = given.unwrap_or_default();
         if support_given >= MAX_STORY_SUPPORT_GIVEN {
             return Ok(false);
         }
-        let user_supporter = self.user_service.get_user(&identity)?;
-        let category_scoring = calculate_category_matching_score(
-            &story.detail.category,
-            &user_supporter.followed_categories,
-        );
+        // supporter must be registered
+        self.user_service.get_user(&identity)?;
+        let mut engagement = Engagement::default();
         if let Some(mut new_support) = args.support {
             if new_support + support_given > MAX_STORY_SUPPORT_GIVEN {
                 new_support = MAX_STORY_SUPPORT_GIVEN - support_given;
             }
             story.total_support += new_support;
             support_given += new_support;
+            engagement.support = new_support as u64;
         }
         if let Some(new_tip) = args.tip {
             self.ledger_service
@@ -129,10 +180,13 @@ impl StoryService {
                 })
                 .map_err(map_transfer_err)?;
             story.total_tip_support += new_tip.clone();
+            engagement.tips = new_tip.clone();
             tip_given += new_tip;
             // tip given is calculated before fee
         }
-        story.score = calculate_basic_scoring(support_given, tip_given.clone(), category_scoring);
+        // ranked from the story totals, so every supporter counts and no reader preference leaks in
+        let config = self.story_repository.get_ranking_config();
+        story.score = calculate_rank(&story, &config);
 
         self.story_repository
             .update(story.clone())
@@ -141,59 +195,571 @@ impl StoryService {
                     .support_story(args.id, identity, support_given, tip_given)
             })
             .map_err(map_story_err)?;
+        self.index_all_time_engagement(&story, &config);
+        self.story_repository
+            .record_engagement(
+                day_index(timestamp()),
+                story.id,
+                story.author,
+                &engagement,
+                is_new_supporter,
+            )
+            .map_err(map_story_err)?;
+        if engagement.support > 0 {
+            self.user_service.notify(
+                story.author,
+                NotificationKind::Support {
+                    story_id: story.id,
+                    by: identity,
+                    support: engagement.support as SupportSize,
+                },
+                timestamp(),
+            );
+        }
+        if engagement.tips > 0u64 {
+            self.user_service.notify(
+                story.author,
+                NotificationKind::Tip {
+                    story_id: story.id,
+                    by: identity,
+                    amount: engagement.tips.clone(),
+                },
+                timestamp(),
+            );
+        }
+        self.treasury_service
+            .record_engagement(story.author, engagement);
         Ok(true)
     }
 
+    /// Counts a view of a story once per viewer and day, the author reading their own story is
+    /// not counted
+    pub fn record_view(&self, id: u64, identity: Principal) -> ServiceResult<bool> {
+        let story = self
+            .story_repository
+            .get(&id)
+            .ok_or(ServiceError::StoryNotFound)?;
+        if story.author == identity {
+            return Ok(false);
+        }
+        let day = day_index(timestamp());
+        if !self.story_repository.record_story_view(day, id, identity) {
+            return Ok(false);
+        }
+
+        let story = self
+            .story_repository
+            .increment_story_views(id)
+            .map_err(map_story_err)?;
+        let config = self.story_repository.get_ranking_config();
+        let rank = calculate_rank(&story, &config);
+        self.story_repository
+            .set_story_score(id, rank)
+            .map_err(map_story_err)?;
+        self.index_all_time_engagement(&story, &config);
+        let engagement = Engagement::new(0, 1, 0_usize.into());
+        self.story_repository
+            .record_engagement(day, id, story.author, &engagement, false)
+            .map_err(map_story_err)?;
+        self.treasury_service
+            .record_engagement(story.author, engagement);
+        Ok(true)
+    }
+
+    /// Gets the stories with the most engagement received within the window
+    pub fn get_trending_stories(
+        &self,
+        window: LeaderboardWindow,
+        limit: usize,
+        reader: Principal,
+    ) -> ServiceResult<Vec<Story>> {
+        let config = self.story_repository.get_ranking_config();
+        // the all-time index is read further until the gated page is full
+        let mut fetch = limit;
+        loop {
+            let scored = match window.days() {
+                Some(days) => self
+                    .story_repository
+                    .get_story_engagement_since(window_start_day(timestamp(), days))
+                    .into_iter()
+                    .filter_map(|(id, engagement)| {
+                        let story = self.story_repository.get(&id)?;
+                        Some((calculate_points(&engagement, &config), story))
+                    })
+                    .collect_vec(),
+                None => self
+                    .story_repository
+                    .get_top_story_totals(fetch)
+                    .into_iter()
+                    .filter_map(|(id, total)| Some((total.points, self.story_repository.get(&id)?)))
+                    .collect_vec(),
+            };
+            let exhausted = window.days().is_some() || scored.len() < fetch;
+            let stories = scored
+                .into_iter()
+                .filter(|(points, _)| *points > 0)
+                .sorted_by_key(|(points, story)| Reverse((*points, story.id)))
+                .map(|(_, story)| story)
+                .collect_vec();
+            let stories = self.gate_mature_content(stories, reader);
+            if exhausted || stories.len() >= limit {
+                return Ok(stories
+                    .into_iter()
+                    .take(limit)
+                    .map(|story| self.add_author_name(story))
+                    .collect());
+            }
+            fetch = fetch.saturating_mul(2);
+        }
+    }
+
+    /// Gets the authors with the most engagement received within the window
+    pub fn get_top_authors(
+        &self,
+        window: LeaderboardWindow,
+        limit: usize,
+    ) -> ServiceResult<Vec<AuthorStanding>> {
+        let config = self.story_repository.get_ranking_config();
+        let totals = match window.days() {
+            Some(days) => self
+                .story_repository
+                .get_author_engagement_since(window_start_day(timestamp(), days)),
+            None => self
+                .story_repository
+                .get_top_author_totals(limit)
+                .into_iter()
+                .map(|(author, total)| (author, total.engagement))
+                .collect(),
+        };
+        let standings = totals
+            .into_iter()
+            .map(|(author, engagement)| {
+                (calculate_points(&engagement, &config), author, engagement)
+            })
+            .filter(|(points, _, _)| *points > 0)
+            .sorted_by_key(|(points, author, _)| Reverse((*points, *author)))
+            .take(limit)
+            .map(|(points, author, engagement)| {
+                let user = self.user_service.get_user(&author).ok();
+                AuthorStanding {
+                    author: UserOutline {
+                        id: author,
+                        name: user.as_ref().and_then(|u| u.name.clone()),
+                        bio: user.and_then(|u| u.bio),
+                    },
+                    engagement,
+                    points,
+                }
+            })
+            .collect_vec();
+        Ok(standings)
+    }
+
+    /// Gets the public profile of a user with the totals received by their stories
+    pub fn get_user_profile(&self, id: Principal) -> ServiceResult<UserProfile> {
+        let user = self.user_service.get_user(&id)?;
+        let stories = self
+            .story_repository
+            .get_stories_by_author(id, None, usize::MAX)
+            .map_err(map_story_err)?;
+        Ok(UserProfile {
+            handle: user.handle.clone(),
+            avatar: self.user_service.get_avatar(&id),
+            followers: user.follower,
+            following: user.followed_authors.len() as u32,
+            followed_categories: user.followed_categories.clone(),
+            story_count: stories.len() as u64,
+            total_support_received: stories.iter().map(|s| s.total_support as u64).sum(),
+            total_tips_received: stories.iter().fold(Tokens::default(), |total, s| {
+                total + s.total_tip_support.clone()
+            }),
+            joined_at: user.created_at,
+            outline: UserOutline {
+                id: user.id,
+                name: user.name,
+                bio: user.bio,
+            },
+        })
+    }
+
+    /// Gets the performance of the stories of an author between two timestamps in nanoseconds
+    pub fn get_author_analytics(
+        &self,
+        author: Principal,
+        from: u64,
+        to: u64,
+    ) -> ServiceResult<AuthorAnalytics> {
+        let (from_day, to_day) = (day_index(from), day_index(to));
+        if from_day > to_day || to_day - from_day >= MAX_ANALYTICS_DAYS {
+            return Err(ServiceError::UnprocessableEntity {
+                reason: format!(
+                    "Date range must be ordered and cover at most {} days.",
+                    MAX_ANALYTICS_DAYS
+                ),
+            });
+        }
+        let user = self.user_service.get_user(&author)?;
+
+        let config = self.story_repository.get_ranking_config();
+        let mut supporters = BTreeMap::<Principal, Engagement>::new();
+        let mut stories = Vec::new();
+        for story in self
+            .story_repository
+            .get_stories_by_author(author, None, usize::MAX)
+            .map_err(map_story_err)?
+        {
+            let story_supporters = self
+                .story_repository
+                .get_story_supporters(story.id)
+                .map_err(map_story_err)?;
+            for (supporter, support, tips) in &story_supporters {
+                supporters
+                    .entry(*supporter)
+                    .or_default()
+                    .merge(&Engagement::new(*support as u64, 0, tips.clone()));
+            }
+            let daily = self
+                .story_repository
+                .get_story_activity(story.id, from_day, to_day)
+                .into_iter()
+                .map(|(day, activity)| DailyStoryActivity { day, activity })
+                .collect_vec();
+            stories.push(StoryAnalytics {
+                story_id: story.id,
+                title: story.title,
+                total_views: story.total_views,
+                total_support: story.total_support,
+                total_tips: story.total_tip_support,
+                supporter_count: story_supporters.len() as u32,
+                daily,
+            });
+        }
+
+        let top_supporters = supporters
+            .into_iter()
+            .sorted_by_key(|(supporter, engagement)| {
+                Reverse((calculate_points(engagement, &config), *supporter))
+            })
+            .take(MAX_TOP_SUPPORTERS)
+            .map(|(supporter, engagement)| {
+                let user = self.user_service.get_user(&supporter).ok();
+                SupporterStanding {
+                    supporter: UserOutline {
+                        id: supporter,
+                        name: user.as_ref().and_then(|u| u.name.clone()),
+                        bio: user.and_then(|u| u.bio),
+                    },
+                    support: engagement.support,
+                    tips: engagement.tips,
+                }
+            })
+            .collect_vec();
+
+        Ok(AuthorAnalytics {
+            from_day,
+            to_day,
+            followers: user.follower,
+            follower_growth: self
+                .user_service
+                .get_follower_growth(author, from_day, to_day),
+            stories,
+            top_supporters,
+        })
+    }
+
+    /// Removes the daily engagement buckets no window covers anymore, and the recorded views
+    /// of the days before today
+    pub fn prune_engagement(&self, now: u64) -> usize {
+        let days = LeaderboardWindow::Month.days().unwrap_or_default();
+        self.story_repository
+            .prune_engagement_before(window_start_day(now, days))
+            + self
+                .story_repository
+                .prune_story_views_before(day_index(now))
+    }
+
     pub fn get_stories_by_author(
         &self,
         author: Principal,
         cursor: Option<u64>,
         limit: usize,
+        reader: Principal,
     ) -> ServiceResult<(Option<u64>, Vec<Story>)> {
         let stories = self
             .story_repository
             .get_stories_by_author(author, cursor, limit)
             .map_err(map_story_err)?;
-        Ok((stories.last().map(|s| s.id), stories))
+        let next_cursor = stories.last().map(|s| s.id);
+        let stories = gate_mature_stories(stories, self.mature_content_access(reader), reader);
+        Ok((next_cursor, stories))
     }
 
+    /// Pages the stories of a category, the stories of authors muted by the reader are left out
+    /// so a page can hold fewer stories than the limit. Only the stories in `language`, or else
+    /// in the primary languages the reader prefers, are listed when either is set. Stories of an
+    /// undetected language are kept in the feed filtered by preference
     pub fn get_stories_by_category(
         &self,
         category: Category,
+        language: Option<String>,
         cursor: Option<u64>,
         limit: usize,
+        reader: Principal,
     ) -> ServiceResult<(Option<u64>, Vec<Story>)> {
+        let languages =
+            match language {
+                Some(language) => vec![normalize_language(&language).ok_or(
+                    ServiceError::UnprocessableEntity {
+                        reason: "Invalid language.".to_string(),
+                    },
+                )?],
+                None => {
+                    let mut languages = self
+                        .user_service
+                        .get_user(&reader)
+                        .map(|u| u.languages)
+                        .unwrap_or_default()
+                        .iter()
+                        .map(|l| primary_language(l).to_string())
+                        .unique()
+                        .collect_vec();
+                    if !languages.is_empty() {
+                        languages.push(UNDETERMINED_LANGUAGE.to_string());
+                    }
+                    languages
+                }
+            };
+        let stories = if languages.is_empty() {
+            self.story_repository.get_stories_by_categories(
+                vec![category],
+                SortOrder::default(),
+                vec![cursor],
+                limit,
+            )
+        } else {
+            self.story_repository
+                .get_stories_by_languages(&languages, category, cursor, limit)
+        }
+        .map_err(map_story_err)?;
+        let next_cursor = stories.last().map(|s| s.id);
+        let muted = self.get_muted_authors(reader);
+        let stories = stories
+            .into_iter()
+            .filter(|s| !muted.contains(&s.author))
+            .map(|s| self.add_author_name(s))
+            .collect_vec();
+        let stories = gate_mature_stories(stories, self.mature_content_access(reader), reader);
+        Ok((next_cursor, stories))
+    }
+
+    /// Pages the stories with a tag, latest first, applying the mutes and the mature content
+    /// preference of the reader like the category feed
+    pub fn get_stories_by_tag(
+        &self,
+        tag: String,
+        cursor: Option<u64>,
+        limit: usize,
+        reader: Principal,
+    ) -> ServiceResult<(Option<u64>, Vec<Story>)> {
+        let tag = normalize_tag(&tag).ok_or(ServiceError::UnprocessableEntity {
+            reason: "Invalid tag.".to_string(),
+        })?;
         let stories = self
             .story_repository
-            .get_stories_by_categories(vec![category], SortOrder::default(), vec![cursor], limit)
+            .get_stories_by_tag(tag, cursor, limit)
             .map_err(map_story_err)?;
+        let next_cursor = stories.last().map(|s| s.id);
+        let muted = self.get_muted_authors(reader);
         let stories = stories
             .into_iter()
+            .filter(|s| !muted.contains(&s.author))
+            .map(|s| self.add_author_name(s))
+            .collect_vec();
+        let stories = gate_mature_stories(stories, self.mature_content_access(reader), reader);
+        Ok((next_cursor, stories))
+    }
+
+    /// Gets the original of a story and all of its translations, the story itself included
+    pub fn get_story_translations(&self, id: u64, reader: Principal) -> ServiceResult<Vec<Story>> {
+        let story = self
+            .story_repository
+            .get(&id)
+            .ok_or(ServiceError::StoryNotFound)?;
+        let original = story.detail.translation_of.unwrap_or(story.id);
+        let stories = std::iter::once(original)
+            .chain(
+                self.story_repository
+                    .get_translation_ids(original, MAX_STORY_TRANSLATIONS),
+            )
+            .filter_map(|id| self.story_repository.get(&id))
             .map(|s| self.add_author_name(s))
             .collect_vec();
-        Ok((stories.last().map(|s| s.id), stories))
+        Ok(gate_mature_stories(
+            stories,
+            self.mature_content_access(reader),
+            reader,
+        ))
+    }
+
+    pub fn get_categories(&self) -> Vec<CategoryInfo> {
+        self.story_repository.get_categories()
+    }
+
+    pub fn add_category(&self, name: String) -> ServiceResult<CategoryDefinition> {
+        let name = validate_category_name(&name)?;
+        self.story_repository
+            .add_category(name, timestamp())
+            .map_err(map_category_err)
+    }
+
+    pub fn update_category(
+        &self,
+        id: u16,
+        args: UpdateCategoryArgs,
+    ) -> ServiceResult<CategoryDefinition> {
+        let mut definition = self.story_repository.get_category_definition(id).ok_or(
+            ServiceError::UnprocessableEntity {
+                reason: "Category not found.".to_string(),
+            },
+        )?;
+        if let Some(name) = args.name {
+            definition.name = validate_category_name(&name)?;
+        }
+        if let Some(active) = args.active {
+            definition.active = active;
+        }
+        self.story_repository
+            .update_category(definition)
+            .map_err(map_category_err)
     }
 
+    /// Pages the stories by their rank personalized for the reader, the stories of the followed
+    /// categories are boosted across every page. The rank keeps its order as time passes so the
+    /// cursor, the (personalized rank, id) of the last story, stays valid between pages
     pub fn get_recommended_stories(
         &self,
         cursor: Option<(Score, u64)>,
         limit: usize,
+        reader: Principal,
     ) -> ServiceResult<(Option<(Score, u64)>, Vec<Story>)> {
-        let now = timestamp();
-        let stories = self
-            .story_repository
-            .get_stories_by_score(cursor, limit)
-            .map_err(map_story_err)?;
-        let next_cursor = stories.last().map(|s| (s.score, s.id));
-        let stories = stories
+        let followed_categories = self
+            .user_service
+            .get_user(&reader)
+            .map(|u| u.followed_categories)
+            .unwrap_or_default();
+        let ranked = self.get_stories_by_personalized_rank(&followed_categories, cursor, limit)?;
+        let next_cursor = ranked.last().map(|(rank, s)| (*rank, s.id));
+        let muted = self.get_muted_authors(reader);
+        let stories = ranked
             .into_iter()
-            .sorted_by_cached_key(|s| calculate_complete_scoring(s.score, s.created_at, now))
+            .map(|(_, s)| s)
+            .filter(|s| !muted.contains(&s.author))
             .map(|s| self.add_author_name(s))
-            .rev()
             .collect_vec();
+        let stories = gate_mature_stories(stories, self.mature_content_access(reader), reader);
         Ok((next_cursor, stories))
     }
 
+    /// Merges the boosted stories of the followed categories with the highest scored stories of
+    /// the other categories, both taken from the cursor on and ordered by personalized rank
+    fn get_stories_by_personalized_rank(
+        &self,
+        followed_categories: &[Category],
+        cursor: Option<(Score, u64)>,
+        limit: usize,
+    ) -> ServiceResult<Vec<(Score, Story)>> {
+        let boosted = match cursor {
+            None => Some(None),
+            Some((rank, id)) => rank
+                .checked_sub(FOLLOWED_CATEGORY_BONUS)
+                .map(|score| Some((score, id))),
+        };
+        let mut ranked = match boosted {
+            Some(boosted_cursor) if !followed_categories.is_empty() => self
+                .story_repository
+                .get_stories_by_category_score(followed_categories, boosted_cursor, limit),
+            _ => vec![],
+        };
+
+        // the followed stories met in the score index are already ranked above
+        let mut others = Vec::new();
+        let mut score_cursor = cursor;
+        loop {
+            let stories = self
+                .story_repository
+                .get_stories_by_score(score_cursor, limit)
+                .map_err(map_story_err)?;
+            let exhausted = stories.len() < limit;
+            score_cursor = stories.last().map(|s| (s.score, s.id));
+            others.extend(
+                stories
+                    .into_iter()
+                    .filter(|s| !followed_categories.contains(&s.detail.category)),
+            );
+            if exhausted || others.len() >= limit {
+                break;
+            }
+        }
+        ranked.append(&mut others);
+
+        Ok(ranked
+            .into_iter()
+            .map(|s| (calculate_personalized_rank(&s, followed_categories), s))
+            .sorted_unstable_by_key(|(rank, s)| Reverse((*rank, s.id)))
+            .take(limit)
+            .collect())
+    }
+
+    pub fn get_ranking_config(&self) -> RankingConfig {
+        self.story_repository.get_ranking_config()
+    }
+
+    /// Saves new ranking weights, the stories are re-ranked gradually by the refresh timer
+    pub fn set_ranking_config(&self, config: RankingConfig) -> ServiceResult<RankingConfig> {
+        if config.half_life_secs < MIN_RANK_HALF_LIFE_SECS {
+            return Err(ServiceError::UnprocessableEntity {
+                reason: format!(
+                    "Half-life must be at least {} seconds.",
+                    MIN_RANK_HALF_LIFE_SECS
+                ),
+            });
+        }
+        let config = self
+            .story_repository
+            .set_ranking_config(config)
+            .map_err(map_story_err)?;
+        self.story_repository.set_rank_refresh_cursor(1);
+        Ok(config)
+    }
+
+    /// Recomputes the rank of the next `limit` stories, wrapping around after the last story.
+    /// Returns the number of ranks that changed
+    pub fn refresh_ranks(&self, limit: usize) -> ServiceResult<usize> {
+        let config = self.story_repository.get_ranking_config();
+        let cursor = self.story_repository.get_rank_refresh_cursor();
+        let stories = self.story_repository.get_stories_from(cursor, limit);
+        let next_cursor = match stories.last() {
+            Some(last) if stories.len() == limit => last.id + 1,
+            _ => 1,
+        };
+        let mut changed = 0;
+        for story in stories {
+            // also backfills the all-time and category score indexes, and follows changes of the
+            // weights
+            self.index_all_time_engagement(&story, &config);
+            self.story_repository.index_category_score(&story);
+            let rank = calculate_rank(&story, &config);
+            if rank != story.score {
+                self.story_repository
+                    .set_story_score(story.id, rank)
+                    .map_err(map_story_err)?;
+                changed += 1;
+            }
+        }
+        self.story_repository.set_rank_refresh_cursor(next_cursor);
+        Ok(changed)
+    }
+
     pub fn get_story_supporter(&self, id: u64) -> ServiceResult<Vec<UserOutline>> {
         let supporters = self
             .story_repository
@@ -211,48 +777,367 @@ impl StoryService {
         Ok(supporters)
     }
 
-    pub async fn assist_expand_writing(
+    /// Runs an assist action on the whole content of a draft
+    pub async fn assist(
         &self,
-        id: &u64,
+        action: &AssistActionArgs,
         identity: &Principal,
     ) -> ServiceResult<String> {
-        self.user_service.ensure_ai_enabled(&identity)?;
         let content = self
             .draft_content_repository
-            .get(id)
-            .ok_or(ServiceError::StoryNotFound)?;
-        let expansion = expand_paragraph(content.content)
-            .await
-            .map_err(|e| ServiceError::AiModelError(e))?;
-        Ok(expansion)
+            .get(&action.draft_id())
+            .ok_or(ServiceError::DraftNotFound)?;
+        validate_draft_author(content.author, *identity)?;
+        let chunks = chunk_text(&content.content, MAX_CHUNK_CHARS);
+        self.run_assist(action, chunks, None, identity).await
     }
 
-    pub async fn assist_story_description(
+    /// Runs an assist action on a selected range of a draft, answering with the range the
+    /// result has to replace
+    pub async fn assist_selection(
         &self,
-        id: &u64,
+        action: &AssistActionArgs,
+        selection: &AssistSelection,
         identity: &Principal,
-    ) -> ServiceResult<String> {
-        self.user_service.ensure_ai_enabled(&identity)?;
+    ) -> ServiceResult<AssistReplacement> {
         let content = self
             .draft_content_repository
-            .get(id)
-            .ok_or(ServiceError::StoryNotFound)?;
-        let description = write_story_description(content.content)
+            .get(&action.draft_id())
+            .ok_or(ServiceError::DraftNotFound)?;
+        validate_draft_author(content.author, *identity)?;
+        let chars = content.content.chars().collect_vec();
+        let (start, end) = resolve_selection(&content.content, chars.len(), &selection.range)?;
+
+        let context_size = selection
+            .context_size
+            .map_or(DEFAULT_ASSIST_CONTEXT_CHARS, |c| c as usize)
+            .min(MAX_ASSIST_CONTEXT_CHARS);
+        let context = SelectionContext {
+            before: chars[start.saturating_sub(context_size)..start]
+                .iter()
+                .collect(),
+            after: chars[end..(end + context_size).min(chars.len())]
+                .iter()
+                .collect(),
+        };
+        let selected = chars[start..end].iter().collect::<String>();
+        let chunks = chunk_text(&selected, MAX_CHUNK_CHARS);
+        let text = self
+            .run_assist(action, chunks, Some(&context), identity)
+            .await?;
+
+        // continuing the story inserts the text right after the selection
+        let start = match action {
+            AssistActionArgs::ContinueStory(_) => end,
+            _ => start,
+        };
+        Ok(AssistReplacement {
+            text,
+            start: start as u64,
+            end: end as u64,
+        })
+    }
+
+    /// Consumes the AI quota for the request, refunded when the model fails
+    async fn run_assist(
+        &self,
+        action: &AssistActionArgs,
+        chunks: Vec<String>,
+        context: Option<&SelectionContext>,
+        identity: &Principal,
+    ) -> ServiceResult<String> {
+        if chunks.is_empty() {
+            return Err(ServiceError::UnprocessableEntity {
+                reason: "There is no text to assist with.".to_string(),
+            });
+        }
+        if chunks.len() > MAX_ASSIST_CHUNKS {
+            return Err(ServiceError::UnprocessableEntity {
+                reason: "The text is too long to assist with, select a shorter range.".to_string(),
+            });
+        }
+        let now = timestamp();
+        self.user_service.consume_ai_quota(identity, now)?;
+        let result = self
+            .llm_service
+            .assist_chunks(action, chunks, context)
             .await
-            .map_err(|e| ServiceError::AiModelError(e))?;
-        Ok(description)
+            .map_err(|e| {
+                self.user_service.refund_ai_quota(identity, now);
+                ServiceError::AiModelError(e)
+            })?;
+        Ok(result)
+    }
+
+    /// Finds the stories closest to a story, comparing the TF-IDF vectors of their terms
+    pub fn get_related_stories(
+        &self,
+        id: u64,
+        limit: usize,
+        reader: Principal,
+    ) -> ServiceResult<Vec<Story>> {
+        let Some(target) = self.story_repository.get_story_terms(id) else {
+            if !self.story_repository.exists(&id) {
+                return Err(ServiceError::StoryNotFound);
+            }
+            return Ok(vec![]);
+        };
+
+        let total = self.story_repository.count_stories_with_terms();
+        let mut idf_cache = BTreeMap::new();
+        let mut weigh = |terms: &StoryTerms| {
+            terms
+                .terms
+                .iter()
+                .map(|(term, occurrences)| {
+                    let idf = *idf_cache.entry(term.clone()).or_insert_with(|| {
+                        let frequency = self.story_repository.get_term_frequency(term);
+                        ((total as f64 + 1.0) / (frequency as f64 + 1.0)).ln() + 1.0
+                    });
+                    (term.clone(), *occurrences as f64 * idf)
+                })
+                .collect::<BTreeMap<_, _>>()
+        };
+        let target_vector = weigh(&target);
+
+        let candidates = target
+            .terms
+            .iter()
+            .flat_map(|(term, _)| {
+                self.story_repository
+                    .find_stories_by_term(term, RELATED_CANDIDATES_PER_TERM)
+            })
+            .filter(|candidate| *candidate != id)
+            .collect::<BTreeSet<_>>();
+        let mut scored = candidates
+            .into_iter()
+            .filter_map(|candidate| {
+                let terms = self.story_repository.get_story_terms(candidate)?;
+                let mut score = cosine_similarity(&target_vector, &weigh(&terms));
+                if terms.category == target.category {
+                    score += SAME_CATEGORY_BONUS;
+                }
+                Some((score, candidate))
+            })
+            .collect_vec();
+        scored.sort_by(|(a, a_id), (b, b_id)| b.total_cmp(a).then(b_id.cmp(a_id)));
+
+        let stories = scored
+            .into_iter()
+            .take(limit)
+            .filter_map(|(_, candidate)| self.story_repository.get(&candidate))
+            .map(|s| self.add_author_name(s))
+            .collect_vec();
+        Ok(self.gate_mature_content(stories, reader))
     }
 
-    fn add_author_name(&self, mut s: Story) -> Story {
-        s.author_name = self
+    /// Recomputes the term vectors of a batch of stories, returns the cursor of the next batch
+    pub fn reindex_story_terms(
+        &self,
+        cursor: Option<u64>,
+        limit: usize,
+    ) -> ServiceResult<Option<u64>> {
+        let stories = self
+            .story_repository
+            .get_stories_from(cursor.unwrap_or_default(), limit);
+        for story in &stories {
+            let content = self
+                .story_content_repository
+                .get(&story.id)
+                .map(|c| c.content)
+                .unwrap_or_default();
+            self.story_repository
+                .save_story_terms(story.id, build_story_terms(story, &content))
+                .map_err(map_story_err)?;
+        }
+        if stories.len() < limit {
+            return Ok(None);
+        }
+        Ok(stories.last().map(|s| s.id + 1))
+    }
+
+    /// Detects the language of a batch of stories published without one and indexes every story
+    /// of the batch under its language tags, returns the cursor of the next batch
+    pub fn detect_story_languages(
+        &self,
+        cursor: Option<u64>,
+        limit: usize,
+    ) -> ServiceResult<Option<u64>> {
+        let stories = self
+            .story_repository
+            .get_stories_from(cursor.unwrap_or_default(), limit);
+        for story in stories.iter() {
+            self.story_repository.index_language(story);
+            if story.detail.language.is_some() {
+                continue;
+            }
+            let content = self
+                .story_content_repository
+                .get(&story.id)
+                .map(|c| c.content)
+                .unwrap_or_default();
+            let text = format!("{}\n{}\n{}", story.title, story.detail.description, content);
+            if let Some(language) = detect_language(&text) {
+                self.story_repository
+                    .set_story_language(story.id, language)
+                    .map_err(map_story_err)?;
+            }
+        }
+        if stories.len() < limit {
+            return Ok(None);
+        }
+        Ok(stories.last().map(|s| s.id + 1))
+    }
+
+    pub fn get_moderation_queue(&self) -> Vec<ModerationEntry> {
+        self.story_repository.get_moderation_queue()
+    }
+
+    /// Applies the labels decided by a moderator and takes the story out of the queue
+    pub fn resolve_moderation(&self, id: u64, labels: Vec<SafetyLabel>) -> ServiceResult<Story> {
+        let mut story = self
+            .story_repository
+            .get(&id)
+            .ok_or(ServiceError::StoryNotFound)?;
+        self.story_repository.remove_moderation(id).map_err(|_| {
+            ServiceError::UnprocessableEntity {
+                reason: "Story is not waiting for moderation.".to_string(),
+            }
+        })?;
+        if !labels.is_empty() {
+            story.detail.mature_content = true;
+        }
+        story.safety_labels = labels;
+        let story = self.story_repository.update(story).map_err(map_story_err)?;
+        Ok(self.add_author_name(story))
+    }
+
+    /// Indexes the all-time points of a story and of its author from the story totals
+    fn index_all_time_engagement(&self, story: &Story, config: &RankingConfig) {
+        let engagement = story_engagement(story);
+        let total = EngagementTotal {
+            points: calculate_points(&engagement, config),
+            engagement,
+        };
+        let author_engagement =
+            self.story_repository
+                .set_story_all_time_engagement(story.id, story.author, total);
+        self.story_repository
+            .set_author_all_time_points(story.author, calculate_points(&author_engagement, config));
+    }
+
+    /// Applies the mature content preference of the reader to stories, for every path that
+    /// hands out stories outside of this service
+    pub fn gate_mature_content(&self, stories: Vec<Story>, reader: Principal) -> Vec<Story> {
+        gate_mature_stories(stories, self.mature_content_access(reader), reader)
+    }
+
+    fn mature_content_access(&self, reader: Principal) -> MatureAccess {
+        match self.user_service.get_user(&reader) {
+            Ok(user) if user.show_mature_content => MatureAccess::Show,
+            Ok(_) => MatureAccess::Hide,
+            Err(_) => MatureAccess::Mask,
+        }
+    }
+
+    fn get_muted_authors(&self, reader: Principal) -> BTreeSet<Principal> {
+        self.user_service
+            .get_muted_users(reader)
+            .into_iter()
+            .collect()
+    }
+
+    pub fn add_author_name(&self, mut s: Story) -> Story {
+        let author = self
             .user_service
             .get_user(&s.author)
-            .unwrap_or(User::new(Principal::anonymous(), 0))
-            .name;
+            .unwrap_or(User::new(Principal::anonymous(), 0));
+        s.author_name = author.name;
+        s.author_handle = author.handle;
         s
     }
 }
 
+/// How mature stories are served to a reader
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MatureAccess {
+    /// The reader opted into mature content
+    Show,
+    /// Registered readers who have not opted in do not get mature stories in feeds
+    Hide,
+    /// Anonymous readers get mature stories as a masked outline
+    Mask,
+}
+
+/// Applies the mature content access of a reader to a feed, the own stories of the reader are
+/// always served as is
+fn gate_mature_stories(stories: Vec<Story>, access: MatureAccess, reader: Principal) -> Vec<Story> {
+    stories
+        .into_iter()
+        .filter_map(|s| {
+            if !s.detail.mature_content || s.author == reader {
+                return Some(s);
+            }
+            match access {
+                MatureAccess::Show => Some(s),
+                MatureAccess::Hide => None,
+                MatureAccess::Mask => Some(mask_story(s)),
+            }
+        })
+        .collect()
+}
+
+/// Keeps the outline of a mature story without its description
+fn mask_story(mut story: Story) -> Story {
+    story.detail.description = String::new();
+    story
+}
+
+/// Builds the term vector of a story from its title, description and content
+pub fn build_story_terms(story: &Story, content: &str) -> StoryTerms {
+    let text = format!("{}\n{}\n{}", story.title, story.detail.description, content);
+    StoryTerms {
+        category: story.detail.category,
+        terms: extract_terms(&text, MAX_STORY_TERMS),
+    }
+}
+
+fn cosine_similarity(a: &BTreeMap<String, f64>, b: &BTreeMap<String, f64>) -> f64 {
+    let dot = a
+        .iter()
+        .filter_map(|(term, weight)| b.get(term).map(|other| weight * other))
+        .sum::<f64>();
+    let norm = |v: &BTreeMap<String, f64>| v.values().map(|w| w * w).sum::<f64>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        return 0.0;
+    }
+    dot / norms
+}
+
+/// Resolves a selection into character offsets of the content
+fn resolve_selection(
+    content: &str,
+    content_len: usize,
+    range: &SelectionRange,
+) -> ServiceResult<(usize, usize)> {
+    let (start, end) = match range {
+        SelectionRange::Offsets { start, end } => (*start as usize, *end as usize),
+        SelectionRange::Paragraph(index) => paragraph_ranges(content)
+            .get(*index as usize)
+            .copied()
+            .ok_or(ServiceError::UnprocessableEntity {
+                reason: "Paragraph not found.".to_string(),
+            })?,
+    };
+    if start >= end || end > content_len {
+        return Err(ServiceError::UnprocessableEntity {
+            reason: "Invalid selection range.".to_string(),
+        });
+    }
+    Ok((start, end))
+}
+
 fn validate_supporter(author: Principal, supporter: Principal) -> ServiceResult<()> {
     if author == supporter {
         return Err(ServiceError::UnprocessableEntity {
@@ -262,6 +1147,39 @@ fn validate_supporter(author: Principal, supporter: Principal) -> ServiceResult<
     Ok(())
 }
 
+fn validate_category_name(name: &str) -> ServiceResult<String> {
+    let name = name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_CATEGORY_NAME_SIZE {
+        return Err(ServiceError::UnprocessableEntity {
+            reason: format!(
+                "Category name must be between 1 and {} characters.",
+                MAX_CATEGORY_NAME_SIZE
+            ),
+        });
+    }
+    if Category::BUILT_IN
+        .iter()
+        .any(|c| format!("{:?}", c).eq_ignore_ascii_case(&name))
+    {
+        return Err(ServiceError::Conflict {
+            entity: "Category".to_string(),
+        });
+    }
+    Ok(name)
+}
+
+fn map_category_err(e: RepositoryError) -> ServiceError {
+    match e {
+        RepositoryError::Conflict => ServiceError::Conflict {
+            entity: "Category".to_string(),
+        },
+        RepositoryError::IllegalArgument { reason } => ServiceError::UnprocessableEntity { reason },
+        _ => ServiceError::InternalError {
+            reason: format!("{:?}", e),
+        },
+    }
+}
+
 fn map_story_err(e: RepositoryError) -> ServiceError {
     match e {
         RepositoryError::NotFound => ServiceError::StoryNotFound,
@@ -280,29 +1198,426 @@ fn map_transfer_err(e: TransferError) -> ServiceError {
     }
 }
 
-fn calculate_time_bonus_scoring(created_at: u64, now: u64) -> u64 {
-    let ages_seconds = (now - created_at) / 1_000_000_000;
-    let time_bonus = (3660 - (ages_seconds / (3 * 60 * 60 * 24)) as i64 * 10).max(0) as u64;
-    time_bonus
+/// Ranks a story by `log2(1 + engagement) + created_at / half_life`, the logarithm of the
+/// decayed engagement `engagement * 2^(-age / half_life)` without the `now` term shared by
+/// every story. The order of the ranks therefore never changes while time passes, and a new
+/// story starts above older ones with the same engagement.
+pub fn calculate_rank(story: &Story, config: &RankingConfig) -> Score {
+    let engagement = calculate_engagement_points(story, config);
+    let created_secs = story.created_at / 1_000_000_000;
+    let rank = (engagement as f64 + 1.0).log2()
+        + created_secs as f64 / config.half_life_secs.max(1) as f64;
+    (rank * RANK_PRECISION) as Score
+}
+
+/// Rank of a story for a reader, a followed category weighs as much as doubling the engagement
+fn calculate_personalized_rank(story: &Story, followed_categories: &[Category]) -> Score {
+    if followed_categories.contains(&story.detail.category) {
+        story.score.saturating_add(FOLLOWED_CATEGORY_BONUS)
+    } else {
+        story.score
+    }
+}
+
+fn calculate_engagement_points(story: &Story, config: &RankingConfig) -> u64 {
+    calculate_points(&story_engagement(story), config)
 }
 
-fn calculate_basic_scoring(support: u32, tips: Nat, category_matching_score: u64) -> u64 {
-    let support_score = support as u64 * 10;
-    let tip_score = (tips.0 * 100_usize).try_into().unwrap_or(u64::MAX);
+fn calculate_points(engagement: &Engagement, config: &RankingConfig) -> u64 {
+    let tip_units: u64 = (engagement.tips.0.clone() / TIP_POINT_UNIT)
+        .try_into()
+        .unwrap_or(u64::MAX);
 
-    support_score
-        .saturating_add(tip_score)
-        .saturating_add(category_matching_score)
+    engagement
+        .support
+        .saturating_mul(config.support_weight)
+        .saturating_add(engagement.views.saturating_mul(config.view_weight))
+        .saturating_add(tip_units.saturating_mul(config.tip_weight))
 }
 
-fn calculate_complete_scoring(basic_scoring: u64, created_at: u64, now: u64) -> u64 {
-    basic_scoring.saturating_add(calculate_time_bonus_scoring(created_at, now))
+fn story_engagement(story: &Story) -> Engagement {
+    Engagement::new(
+        story.total_support as u64,
+        story.total_views as u64,
+        story.total_tip_support.clone(),
+    )
 }
 
-fn calculate_category_matching_score(category: &Category, categories: &[Category]) -> u64 {
-    if categories.contains(category) {
-        100
-    } else {
-        0
+/// First daily bucket of a window of `days` ending today
+fn window_start_day(now: u64, days: u64) -> u64 {
+    (day_index(now) + 1).saturating_sub(days)
+}
+
+pub fn start_engagement_prune_timer() {
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(ENGAGEMENT_PRUNE_INTERVAL_SECS), || {
+        STORY_SERVICE.prune_engagement(timestamp());
+    });
+}
+
+pub fn start_rank_refresh_timer() {
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(RANK_REFRESH_INTERVAL_SECS), || {
+        if let Err(e) = STORY_SERVICE.refresh_ranks(RANK_REFRESH_BATCH_SIZE) {
+            ic_cdk::println!("rank refresh failed: {}", e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cmp::Reverse, collections::BTreeMap};
+
+    use candid::Principal;
+    use ic_stable_structures::Storable;
+
+    use crate::{
+        repositories::{
+            category::CategoryRepository,
+            story::{StoryLanguageIndexRepository, StoryRepository},
+            user::UserRepository,
+        },
+        structure::{AuditableRepository, IndexRepository, Repository},
+        types::{
+            Category, CategoryDefinition, Draft, Engagement, EngagementTotal, LeaderboardWindow,
+            RankingConfig, SelectionRange, ServiceError, StorableTerm, Story, StoryDetail, User,
+        },
+        utils::DAY_NANOS,
+    };
+
+    use super::{
+        calculate_personalized_rank, calculate_points, calculate_rank, cosine_similarity,
+        gate_mature_stories, resolve_selection, validate_category_name, window_start_day,
+        MatureAccess, STORY_SERVICE,
+    };
+
+    fn story_at(created_secs: u64, total_support: u32) -> Story {
+        let draft = Draft::new("Title".to_string(), None, Principal::anonymous());
+        let detail = StoryDetail::new("Description".to_string(), false, Category::Fantasy);
+        let mut story = Story::new(draft, detail);
+        story.created_at = created_secs * 1_000_000_000;
+        story.total_support = total_support;
+        story
+    }
+
+    #[test]
+    fn test_rank_decay() {
+        let config = RankingConfig {
+            support_weight: 1,
+            ..Default::default()
+        };
+        let half_life = config.half_life_secs;
+        let now = 6_500 * half_life;
+
+        // a new story without engagement is not buried under older engaged stories
+        assert!(
+            calculate_rank(&story_at(now, 0), &config)
+                > calculate_rank(&story_at(now - 3 * half_life, 5), &config)
+        );
+        // one half-life older needs twice the engagement, (1 + 1) vs (1 + 3)
+        assert_eq!(
+            calculate_rank(&story_at(now, 1), &config),
+            calculate_rank(&story_at(now - half_life, 3), &config)
+        );
+        assert!(
+            calculate_rank(&story_at(now, 10), &config)
+                > calculate_rank(&story_at(now, 9), &config)
+        );
+    }
+
+    #[test]
+    fn test_leaderboard_window() {
+        let now = 10 * DAY_NANOS + 5;
+        assert_eq!(
+            window_start_day(now, LeaderboardWindow::Day.days().unwrap()),
+            10
+        );
+        assert_eq!(
+            window_start_day(now, LeaderboardWindow::Week.days().unwrap()),
+            4
+        );
+        assert_eq!(
+            window_start_day(now, LeaderboardWindow::Month.days().unwrap()),
+            0
+        );
+        assert_eq!(LeaderboardWindow::AllTime.days(), None);
+
+        let config = RankingConfig::default();
+        let engagement = Engagement::new(2, 3, 2_500_000_usize.into());
+        // 2 * 10 + 3 * 1 + 2 tip units * 100
+        assert_eq!(calculate_points(&engagement, &config), 223);
+    }
+
+    #[test]
+    fn test_author_analytics_range() {
+        let author = Principal::anonymous();
+        assert!(STORY_SERVICE
+            .get_author_analytics(author, 10 * DAY_NANOS, 9 * DAY_NANOS)
+            .is_err());
+        assert!(STORY_SERVICE
+            .get_author_analytics(author, 0, 366 * DAY_NANOS)
+            .is_err());
+        // valid range, but the author is not registered
+        assert!(matches!(
+            STORY_SERVICE.get_author_analytics(author, 0, 365 * DAY_NANOS),
+            Err(ServiceError::IdentityNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_personalized_rank() {
+        let config = RankingConfig {
+            support_weight: 1,
+            ..Default::default()
+        };
+        let now = 6_500 * config.half_life_secs;
+        let mut story = story_at(now, 1);
+        story.score = calculate_rank(&story, &config);
+
+        assert_eq!(calculate_personalized_rank(&story, &[]), story.score);
+        assert_eq!(
+            calculate_personalized_rank(&story, &[Category::Fantasy]),
+            calculate_rank(&story_at(now, 3), &config)
+        );
+        assert_eq!(
+            calculate_personalized_rank(&story, &[Category::Horror]),
+            story.score
+        );
+    }
+
+    #[test]
+    fn test_recommended_stories_boost_followed_categories() {
+        let key = (Category::Custom(u16::MAX), (42_u64, 7_u64));
+        assert_eq!(<(Category, (u64, u64))>::from_bytes(key.to_bytes()), key);
+
+        let repository = StoryRepository::default();
+        let insert = |category, score| {
+            let draft = Draft::new("Title".to_string(), None, Principal::anonymous());
+            let detail = StoryDetail::new("Description".to_string(), false, category);
+            let story = repository.insert(Story::new(draft, detail)).unwrap();
+            repository.set_story_score(story.id, score).unwrap().id
+        };
+        let horror = [
+            insert(Category::Horror, 100),
+            insert(Category::Horror, 90),
+            insert(Category::Horror, 80),
+        ];
+        let fantasy = [insert(Category::Fantasy, 10), insert(Category::Fantasy, 5)];
+
+        let page = |cursor| {
+            let ranked = STORY_SERVICE
+                .get_stories_by_personalized_rank(&[Category::Fantasy], cursor, 2)
+                .unwrap();
+            let next = ranked.last().map(|(rank, s)| (*rank, s.id));
+            (
+                next,
+                ranked.into_iter().map(|(_, s)| s.id).collect::<Vec<_>>(),
+            )
+        };
+        // the followed stories lead even when they are past the first page of the score index
+        let (cursor, ids) = page(None);
+        assert_eq!(ids, fantasy.to_vec());
+        let (cursor, ids) = page(cursor);
+        assert_eq!(ids, horror[..2].to_vec());
+        let (cursor, ids) = page(cursor);
+        assert_eq!(ids, horror[2..].to_vec());
+        assert!(page(cursor).1.is_empty());
+    }
+
+    #[test]
+    fn test_trending_stories_fill_gated_page() {
+        let repository = StoryRepository::default();
+        let author = Principal::from_text("aaaaa-aa").unwrap();
+        let reader = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+        // registered readers hide mature content unless they opt in
+        UserRepository::default()
+            .insert(User::new(reader, 0))
+            .unwrap();
+        let insert = |mature, points| {
+            let draft = Draft::new("Title".to_string(), None, author);
+            let detail = StoryDetail::new("Description".to_string(), mature, Category::Horror);
+            let story = repository.insert(Story::new(draft, detail)).unwrap();
+            let total = EngagementTotal {
+                engagement: Engagement::new(1, 0, 0_usize.into()),
+                points,
+            };
+            repository.set_story_all_time_engagement(story.id, author, total);
+            story.id
+        };
+        insert(true, 300);
+        insert(true, 200);
+        let safe = insert(false, 100);
+
+        let stories = STORY_SERVICE
+            .get_trending_stories(LeaderboardWindow::AllTime, 1, reader)
+            .unwrap();
+        assert_eq!(stories.iter().map(|s| s.id).collect::<Vec<_>>(), vec![safe]);
+    }
+
+    #[test]
+    fn test_resolve_selection() {
+        let content = "First paragraph.\n\nSecond one.";
+        let len = content.chars().count();
+
+        let range = resolve_selection(content, len, &SelectionRange::Paragraph(1)).unwrap();
+        assert_eq!(range, (18, 29));
+        let range =
+            resolve_selection(content, len, &SelectionRange::Offsets { start: 0, end: 5 }).unwrap();
+        assert_eq!(range, (0, 5));
+
+        assert!(resolve_selection(content, len, &SelectionRange::Paragraph(2)).is_err());
+        assert!(
+            resolve_selection(content, len, &SelectionRange::Offsets { start: 5, end: 5 }).is_err()
+        );
+        assert!(
+            resolve_selection(content, len, &SelectionRange::Offsets { start: 0, end: 99 })
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_cosine_similarity() {
+        let vector = |terms: &[(&str, f64)]| {
+            terms
+                .iter()
+                .map(|(t, w)| (t.to_string(), *w))
+                .collect::<BTreeMap<_, _>>()
+        };
+        let a = vector(&[("dragon", 2.0), ("cave", 1.0)]);
+        let b = vector(&[("dragon", 4.0), ("cave", 2.0)]);
+        let c = vector(&[("spaceship", 1.0)]);
+        assert!((cosine_similarity(&a, &b) - 1.0).abs() < 1e-9);
+        assert_eq!(cosine_similarity(&a, &c), 0.0);
+        assert_eq!(cosine_similarity(&a, &BTreeMap::new()), 0.0);
+    }
+
+    #[test]
+    fn test_gate_mature_stories() {
+        let reader = Principal::from_slice(&[1]);
+        let mut mature = story_at(1, 0);
+        mature.id = 1;
+        mature.author = Principal::from_slice(&[2]);
+        mature.detail.mature_content = true;
+        let mut own = mature.clone();
+        own.id = 2;
+        own.author = reader;
+        let mut safe = story_at(1, 0);
+        safe.id = 3;
+        let stories = vec![mature, own, safe];
+        let ids = |stories: &[Story]| stories.iter().map(|s| s.id).collect::<Vec<_>>();
+
+        let shown = gate_mature_stories(stories.clone(), MatureAccess::Show, reader);
+        assert_eq!(ids(&shown), vec![1, 2, 3]);
+        assert_eq!(shown[0].detail.description, "Description");
+
+        let hidden = gate_mature_stories(stories.clone(), MatureAccess::Hide, reader);
+        assert_eq!(ids(&hidden), vec![2, 3]);
+
+        let masked = gate_mature_stories(stories, MatureAccess::Mask, Principal::anonymous());
+        assert_eq!(ids(&masked), vec![1, 2, 3]);
+        assert!(masked[0].detail.description.is_empty());
+        assert!(masked[1].detail.description.is_empty());
+        assert_eq!(masked[2].detail.description, "Description");
+    }
+
+    #[test]
+    fn test_custom_category() {
+        let key = (Category::Custom(u16::MAX), Reverse(42_u64));
+        assert_eq!(<(Category, Reverse<u64>)>::from_bytes(key.to_bytes()), key);
+
+        let repository = CategoryRepository;
+        let poetry = repository.insert("Poetry".to_string(), 0).unwrap();
+        assert!(repository.insert("poetry".to_string(), 0).is_err());
+        let essay = repository.insert("Essay".to_string(), 0).unwrap();
+        assert_eq!(essay.id, poetry.id + 1);
+        assert!(repository.is_available(&Category::Custom(essay.id)));
+        assert!(!repository.is_available(&Category::Custom(essay.id + 1)));
+        repository
+            .update(CategoryDefinition {
+                active: false,
+                ..essay.clone()
+            })
+            .unwrap();
+        assert!(!repository.is_available(&Category::Custom(essay.id)));
+        assert!(repository.is_available(&Category::Horror));
+
+        assert_eq!(validate_category_name(" Poetry ").unwrap(), "Poetry");
+        assert!(validate_category_name("scifi").is_err());
+        assert!(validate_category_name(" ").is_err());
+    }
+
+    #[test]
+    fn test_language_index() {
+        let key = (
+            StorableTerm("zh-Hant-TW".to_string()),
+            (Category::Custom(u16::MAX), Reverse(42_u64)),
+        );
+        assert_eq!(
+            <(StorableTerm, (Category, Reverse<u64>))>::from_bytes(key.to_bytes()),
+            key
+        );
+
+        let index = StoryLanguageIndexRepository;
+        let key = |language: &str, category, id| {
+            (StorableTerm(language.to_string()), (category, Reverse(id)))
+        };
+        index.insert(key("en", Category::Fantasy, 1));
+        index.insert(key("en", Category::Fantasy, 3));
+        index.insert(key("en", Category::Horror, 4));
+        index.insert(key("en-GB", Category::Fantasy, 5));
+        index.insert(key("id", Category::Fantasy, 2));
+
+        let find = |language: &str, cursor| {
+            index.find((language.to_string(), Category::Fantasy), None, cursor, 10)
+        };
+        assert_eq!(find("en", None), vec![3, 1]);
+        assert_eq!(find("en", Some(3)), vec![1]);
+        assert_eq!(find("en-GB", None), vec![5]);
+        assert_eq!(find("id", None), vec![2]);
+        assert!(find("fr", None).is_empty());
+
+        index.remove(&key("en", Category::Fantasy, 3));
+        assert_eq!(find("en", None), vec![1]);
+    }
+
+    #[test]
+    fn test_story_view_counted_once_per_day() {
+        let repository = StoryRepository::default();
+        let viewer = Principal::from_text("aaaaa-aa").unwrap();
+        assert!(repository.record_story_view(10, 1, viewer));
+        assert!(!repository.record_story_view(10, 1, viewer));
+        assert!(repository.record_story_view(10, 2, viewer));
+        assert!(repository.record_story_view(11, 1, viewer));
+
+        assert_eq!(repository.prune_story_views_before(11), 2);
+        assert!(!repository.record_story_view(11, 1, viewer));
+    }
+
+    #[test]
+    fn test_all_time_engagement_index() {
+        let repository = StoryRepository::default();
+        let author = Principal::from_text("aaaaa-aa").unwrap();
+        let total = |support, points| EngagementTotal {
+            engagement: Engagement::new(support, 0, 0_usize.into()),
+            points,
+        };
+
+        repository.set_story_all_time_engagement(1, author, total(2, 200));
+        let author_engagement = repository.set_story_all_time_engagement(2, author, total(3, 300));
+        assert_eq!(author_engagement.support, 5);
+        // a story indexed again only carries its difference over to the author
+        let author_engagement = repository.set_story_all_time_engagement(1, author, total(4, 400));
+        assert_eq!(author_engagement.support, 7);
+        repository.set_author_all_time_points(author, 700);
+        repository.set_story_all_time_engagement(3, author, total(0, 0));
+
+        let top = repository.get_top_story_totals(10);
+        assert_eq!(
+            top.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(top[0].1.points, 400);
+        let authors = repository.get_top_author_totals(10);
+        assert_eq!(authors.len(), 1);
+        assert_eq!(authors[0].1.points, 700);
+        assert_eq!(authors[0].1.engagement.support, 7);
     }
 }
@@ -0,0 +1,480 @@
+use std::{sync::Arc, time::Duration};
+
+use candid::Principal;
+use icrc_ledger_types::icrc1::transfer::TransferArg;
+use itertools::Itertools;
+use lazy_static::lazy_static;
+
+#[cfg(all(test, not(rust_analyzer)))]
+use crate::utils::mocks::timestamp;
+#[cfg(any(not(test), rust_analyzer))]
+use crate::utils::timestamp;
+use crate::{
+    repositories::treasury::{
+        PendingEngagementRepository, RewardRoundRepository, TreasuryStateRepository,
+        PENDING_ENGAGEMENT_REPOSITORY, REWARD_ROUND_REPOSITORY, TREASURY_STATE_REPOSITORY,
+    },
+    structure::{AuditableRepository, BinaryTreeRepository},
+    token::{LedgerService, Tokens, LEDGER_SERVICE},
+    types::{
+        EmissionSchedule, Engagement, RepositoryError, RewardAllocation, RewardRound, ServiceError,
+        ServiceResult, TreasuryState,
+    },
+};
+
+/// How often the emission timer checks whether a reward round is due
+pub const EMISSION_CHECK_INTERVAL_SECS: u64 = 60 * 60;
+/// Amount of tokens tipped that is worth a single `tip_weight`
+pub const TIP_POINT_UNIT: usize = 1_000_000;
+
+const MIN_ROUND_INTERVAL_SECS: u64 = 60 * 60;
+const MAX_ROUND_INTERVAL_SECS: u64 = 366 * 24 * 60 * 60;
+const MAX_DECAY_BPS: u16 = 10_000;
+
+lazy_static! {
+    pub static ref TREASURY_SERVICE: Arc<TreasuryService> = Arc::new(TreasuryService::new(
+        TREASURY_STATE_REPOSITORY.clone(),
+        REWARD_ROUND_REPOSITORY.clone(),
+        PENDING_ENGAGEMENT_REPOSITORY.clone(),
+        LEDGER_SERVICE.clone(),
+    ));
+}
+
+#[derive(Debug)]
+pub struct TreasuryService {
+    state_repository: Arc<TreasuryStateRepository>,
+    round_repository: Arc<RewardRoundRepository>,
+    engagement_repository: Arc<PendingEngagementRepository>,
+    ledger_service: Arc<LedgerService>,
+}
+
+impl TreasuryService {
+    pub fn new(
+        state_repository: Arc<TreasuryStateRepository>,
+        round_repository: Arc<RewardRoundRepository>,
+        engagement_repository: Arc<PendingEngagementRepository>,
+        ledger_service: Arc<LedgerService>,
+    ) -> Self {
+        Self {
+            state_repository,
+            round_repository,
+            engagement_repository,
+            ledger_service,
+        }
+    }
+
+    pub fn get_state(&self) -> TreasuryState {
+        self.state_repository.get()
+    }
+
+    pub fn update_schedule(&self, schedule: EmissionSchedule) -> ServiceResult<TreasuryState> {
+        validate_schedule(&schedule)?;
+
+        let mut state = self.get_state();
+        state.current_emission = schedule.emission_per_round.clone();
+        state.schedule = schedule;
+        self.state_repository.set(state).map_err(map_treasury_err)
+    }
+
+    pub fn set_paused(&self, paused: bool) -> ServiceResult<TreasuryState> {
+        let mut state = self.get_state();
+        state.paused = paused;
+        self.state_repository.set(state).map_err(map_treasury_err)
+    }
+
+    /// Records engagement received by an author, to be rewarded on the next round
+    pub fn record_engagement(&self, author: Principal, engagement: Engagement) {
+        self.engagement_repository.record(author, &engagement);
+    }
+
+    /// Called by the emission timer, distributes the rewards once the round interval elapsed
+    pub fn tick(&self, now: u64) -> ServiceResult<Option<RewardRound>> {
+        let mut state = self.get_state();
+        if state.paused || !self.ledger_service.is_token_created() {
+            return Ok(None);
+        }
+        if state.last_round_at == 0 {
+            // first tick opens the first reward period
+            state.last_round_at = now;
+            self.state_repository.set(state).map_err(map_treasury_err)?;
+            return Ok(None);
+        }
+        let interval = state
+            .schedule
+            .round_interval_secs
+            .saturating_mul(1_000_000_000);
+        if now < state.last_round_at.saturating_add(interval) {
+            return Ok(None);
+        }
+        self.run_round(now).map(Some)
+    }
+
+    /// Closes the current reward period and mints the emission to the engaged authors
+    pub fn run_round(&self, now: u64) -> ServiceResult<RewardRound> {
+        let mut state = self.get_state();
+        if state.paused {
+            return Err(ServiceError::UnprocessableEntity {
+                reason: "Emission is paused.".to_string(),
+            });
+        }
+        if !self.ledger_service.is_token_created() {
+            return Err(ServiceError::UnprocessableEntity {
+                reason: "Token has not been created.".to_string(),
+            });
+        }
+
+        let pending = self.engagement_repository.drain();
+        let points = pending
+            .iter()
+            .map(|(author, engagement)| (*author, engagement_points(&state.schedule, engagement)))
+            .filter(|(_, p)| *p > 0)
+            .collect_vec();
+        let total_points = points
+            .iter()
+            .map(|(_, p)| *p)
+            .fold(0_u64, u64::saturating_add);
+
+        let round = RewardRound {
+            id: 0,
+            period_start: state.last_round_at,
+            period_end: now,
+            emission: state.current_emission.clone(),
+            distributed: 0_usize.into(),
+            total_points,
+            recipients: 0,
+            created_at: 0,
+            updated_at: None,
+        };
+        let mut round = self
+            .round_repository
+            .insert(round)
+            .map_err(map_treasury_err)?;
+
+        for (recipient, points, amount) in allocate_rewards(&round.emission, &points) {
+            let mut allocation = RewardAllocation {
+                round_id: round.id,
+                recipient,
+                points,
+                amount: amount.clone(),
+                block_index: None,
+                error: None,
+            };
+            if amount > 0_usize {
+                match self.mint_reward(recipient, &amount) {
+                    Ok(block) => {
+                        allocation.block_index = Some(block);
+                        round.distributed += amount;
+                        round.recipients += 1;
+                    }
+                    Err(e) => allocation.error = Some(e),
+                }
+            }
+            self.round_repository
+                .save_allocation(allocation)
+                .map_err(map_treasury_err)?;
+        }
+
+        let round = self
+            .round_repository
+            .update(round)
+            .map_err(map_treasury_err)?;
+
+        state.total_emitted += round.distributed.clone();
+        state.current_emission = decay_emission(&state.schedule, &state.current_emission);
+        state.last_round_at = now;
+        self.state_repository.set(state).map_err(map_treasury_err)?;
+        Ok(round)
+    }
+
+    /// Mints again the allocations of a round whose mint failed, the engagement they were paid
+    /// for is already drained so they are only ever paid from here
+    pub fn retry_failed_allocations(&self, round_id: u64) -> ServiceResult<RewardRound> {
+        if !self.ledger_service.is_token_created() {
+            return Err(ServiceError::UnprocessableEntity {
+                reason: "Token has not been created.".to_string(),
+            });
+        }
+        let (mut round, allocations) = self.get_round(round_id)?;
+        let mut state = self.get_state();
+        for mut allocation in allocations.into_iter().filter(|a| a.error.is_some()) {
+            match self.mint_reward(allocation.recipient, &allocation.amount) {
+                Ok(block) => {
+                    allocation.block_index = Some(block);
+                    allocation.error = None;
+                    round.distributed += allocation.amount.clone();
+                    round.recipients += 1;
+                    state.total_emitted += allocation.amount.clone();
+                }
+                Err(e) => allocation.error = Some(e),
+            }
+            self.round_repository
+                .save_allocation(allocation)
+                .map_err(map_treasury_err)?;
+        }
+
+        let round = self
+            .round_repository
+            .update(round)
+            .map_err(map_treasury_err)?;
+        self.state_repository.set(state).map_err(map_treasury_err)?;
+        Ok(round)
+    }
+
+    fn mint_reward(&self, recipient: Principal, amount: &Tokens) -> Result<Tokens, String> {
+        self.ledger_service
+            .mint(TransferArg {
+                from_subaccount: None,
+                to: recipient.into(),
+                fee: None,
+                created_at_time: None,
+                memo: None,
+                amount: amount.clone(),
+            })
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    pub fn get_rounds(
+        &self,
+        cursor: Option<u64>,
+        limit: usize,
+    ) -> ServiceResult<(Option<u64>, Vec<RewardRound>)> {
+        let rounds = self.round_repository.get_rounds(cursor, limit);
+        Ok((rounds.last().map(|r| r.id), rounds))
+    }
+
+    pub fn get_round(&self, id: u64) -> ServiceResult<(RewardRound, Vec<RewardAllocation>)> {
+        let round = self
+            .round_repository
+            .get(&id)
+            .ok_or(ServiceError::UnprocessableEntity {
+                reason: "Reward round not found.".to_string(),
+            })?;
+        let allocations = self
+            .round_repository
+            .get_allocations(id)
+            .map_err(map_treasury_err)?;
+        Ok((round, allocations))
+    }
+}
+
+/// Starts the recurring timer driving the emission schedule
+pub fn start_emission_timer() {
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(EMISSION_CHECK_INTERVAL_SECS), || {
+        if let Err(e) = TREASURY_SERVICE.tick(timestamp()) {
+            ic_cdk::println!("reward round failed: {}", e);
+        }
+    });
+}
+
+fn validate_schedule(schedule: &EmissionSchedule) -> ServiceResult<()> {
+    if schedule.round_interval_secs < MIN_ROUND_INTERVAL_SECS {
+        return Err(ServiceError::UnprocessableEntity {
+            reason: format!(
+                "Round interval must be at least {} seconds.",
+                MIN_ROUND_INTERVAL_SECS
+            ),
+        });
+    }
+    if schedule.round_interval_secs > MAX_ROUND_INTERVAL_SECS {
+        return Err(ServiceError::UnprocessableEntity {
+            reason: format!(
+                "Round interval must be at most {} seconds.",
+                MAX_ROUND_INTERVAL_SECS
+            ),
+        });
+    }
+    if schedule.decay_bps > MAX_DECAY_BPS {
+        return Err(ServiceError::UnprocessableEntity {
+            reason: "Decay cannot exceed 10000 basis points.".to_string(),
+        });
+    }
+    if schedule.min_emission > schedule.emission_per_round {
+        return Err(ServiceError::UnprocessableEntity {
+            reason: "Minimum emission cannot exceed the emission per round.".to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn engagement_points(schedule: &EmissionSchedule, engagement: &Engagement) -> u64 {
+    let tip_units: u64 = (engagement.tips.0.clone() / TIP_POINT_UNIT)
+        .try_into()
+        .unwrap_or(u64::MAX);
+
+    engagement
+        .support
+        .saturating_mul(schedule.support_weight)
+        .saturating_add(engagement.views.saturating_mul(schedule.view_weight))
+        .saturating_add(tip_units.saturating_mul(schedule.tip_weight))
+}
+
+/// Splits the emission proportionally to the points, rounding down every share
+fn allocate_rewards(
+    emission: &Tokens,
+    points: &[(Principal, u64)],
+) -> Vec<(Principal, u64, Tokens)> {
+    let total = points
+        .iter()
+        .map(|(_, p)| *p)
+        .fold(0_u64, u64::saturating_add);
+    if total == 0 {
+        return vec![];
+    }
+    points
+        .iter()
+        .map(|(recipient, p)| {
+            let share = emission.clone() * Tokens::from(*p) / Tokens::from(total);
+            (*recipient, *p, share)
+        })
+        .collect()
+}
+
+fn decay_emission(schedule: &EmissionSchedule, current: &Tokens) -> Tokens {
+    let decayed = current.clone() * Tokens::from(MAX_DECAY_BPS - schedule.decay_bps)
+        / Tokens::from(MAX_DECAY_BPS);
+    if decayed < schedule.min_emission {
+        schedule.min_emission.clone()
+    } else {
+        decayed
+    }
+}
+
+fn map_treasury_err(e: RepositoryError) -> ServiceError {
+    match e {
+        RepositoryError::NotFound => ServiceError::UnprocessableEntity {
+            reason: "Reward round not found.".to_string(),
+        },
+        _ => ServiceError::InternalError {
+            reason: format!("{:?}", e),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use candid::Principal;
+    use icrc_ledger_types::icrc1::account::Account;
+
+    use crate::{
+        repositories::treasury::{
+            PendingEngagementRepository, RewardRoundRepository, TreasuryStateRepository,
+        },
+        token::{
+            api::{create_token, update_token_config},
+            CreateTokenArgs, Tokens, UpdateTokenConfigArgs, LEDGER_SERVICE,
+        },
+        types::{EmissionSchedule, Engagement},
+        utils::mocks::caller,
+    };
+
+    use super::{
+        allocate_rewards, decay_emission, engagement_points, validate_schedule, TreasuryService,
+        MAX_ROUND_INTERVAL_SECS,
+    };
+
+    #[test]
+    fn test_engagement_points() {
+        let schedule = EmissionSchedule::default();
+        let engagement = Engagement::new(2, 30, 5_000_000_usize.into());
+        // 2 * 100 + 30 * 1 + 5 * 10
+        assert_eq!(engagement_points(&schedule, &engagement), 280);
+    }
+
+    #[test]
+    fn test_allocate_rewards_proportionally() {
+        let a = Principal::from_text("aaaaa-aa").unwrap();
+        let b = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+        let emission: Tokens = 1_000_usize.into();
+
+        let allocations = allocate_rewards(&emission, &[(a, 1), (b, 3)]);
+        assert_eq!(allocations.len(), 2);
+        assert_eq!(allocations[0].2, Tokens::from(250_usize));
+        assert_eq!(allocations[1].2, Tokens::from(750_usize));
+
+        assert!(allocate_rewards(&emission, &[(a, 0)]).is_empty());
+    }
+
+    #[test]
+    fn test_decay_emission() {
+        let schedule = EmissionSchedule {
+            emission_per_round: 1_000_usize.into(),
+            min_emission: 950_usize.into(),
+            decay_bps: 300,
+            ..Default::default()
+        };
+        let next = decay_emission(&schedule, &schedule.emission_per_round);
+        assert_eq!(next, Tokens::from(970_usize));
+        let next = decay_emission(&schedule, &next);
+        assert_eq!(next, Tokens::from(950_usize));
+    }
+
+    #[test]
+    fn test_validate_round_interval() {
+        let schedule = |round_interval_secs| EmissionSchedule {
+            round_interval_secs,
+            ..Default::default()
+        };
+        assert!(validate_schedule(&schedule(MAX_ROUND_INTERVAL_SECS)).is_ok());
+        assert!(validate_schedule(&schedule(MAX_ROUND_INTERVAL_SECS + 1)).is_err());
+        assert!(validate_schedule(&schedule(u64::MAX)).is_err());
+    }
+
+    #[test]
+    fn test_retry_failed_allocations() {
+        create_token(Some(CreateTokenArgs {
+            token_name: "TestToken".to_string(),
+            token_symbol: "TT".to_string(),
+            token_logo: "logo".to_string(),
+            initial_supply: 1_000_000_000_usize.into(),
+            transfer_fee: 0_usize.into(),
+        }))
+        .unwrap();
+        let service = TreasuryService::new(
+            TreasuryStateRepository::default().into(),
+            RewardRoundRepository::default().into(),
+            PendingEngagementRepository::default().into(),
+            LEDGER_SERVICE.clone(),
+        );
+        // minting to the minting account fails as a transfer to the same account
+        let minter = caller();
+        let author = Principal::from_text("aaaaa-aa").unwrap();
+        service.record_engagement(minter, Engagement::new(0, 1, 0_usize.into()));
+        service.record_engagement(author, Engagement::new(0, 3, 0_usize.into()));
+
+        let round = service.run_round(1_000).unwrap();
+        let emission = round.emission.clone();
+        assert_eq!(round.recipients, 1);
+        assert_eq!(round.distributed, emission.clone() * 3_usize / 4_usize);
+        let (_, allocations) = service.get_round(round.id).unwrap();
+        assert!(allocations
+            .iter()
+            .any(|a| a.recipient == minter && a.error.is_some()));
+
+        // still failing, nothing is paid twice
+        let round = service.retry_failed_allocations(round.id).unwrap();
+        assert_eq!(round.recipients, 1);
+        assert_eq!(service.get_state().total_emitted, round.distributed);
+
+        update_token_config(UpdateTokenConfigArgs {
+            minting_account: Some(Account {
+                owner: minter,
+                subaccount: Some([1; 32]),
+            }),
+            ..Default::default()
+        })
+        .unwrap();
+        let round = service.retry_failed_allocations(round.id).unwrap();
+        assert_eq!(round.recipients, 2);
+        assert_eq!(round.distributed, emission);
+        assert_eq!(service.get_state().total_emitted, emission);
+        assert_eq!(
+            LEDGER_SERVICE.balance_of(minter.into()),
+            emission.clone() / 4_usize
+        );
+        let (_, allocations) = service.get_round(round.id).unwrap();
+        assert!(allocations.iter().all(|a| a.error.is_none()));
+
+        let round = service.retry_failed_allocations(round.id).unwrap();
+        assert_eq!(round.distributed, emission);
+    }
+}
@@ -1,18 +1,48 @@
-use std::sync::Arc;
+use std::{collections::BTreeSet, sync::Arc};
 
 use candid::Principal;
 use lazy_static::lazy_static;
 
 use crate::{
-    repositories::user::{UserRepository, USER_REPOSITORY},
+    repositories::{
+        ai_quota::{AiQuotaRepository, AI_QUOTA_REPOSITORY},
+        category::{CategoryRepository, CATEGORY_REPOSITORY},
+        notification::{NotificationRepository, NOTIFICATION_REPOSITORY},
+        statistics::{StatisticsRepository, STATISTICS_REPOSITORY},
+        user::{UserRepository, USER_REPOSITORY},
+    },
     structure::{BinaryTreeRepository, Repository},
-    token::{LedgerService, LEDGER_SERVICE},
-    types::{Category, OnboardingArgs, RepositoryError, ServiceError, ServiceResult, User},
+    token::{LedgerService, Tokens, LEDGER_SERVICE},
+    types::{
+        AiQuota, Category, DailyFollowerChange, Notification, NotificationKind, OnboardingArgs,
+        RepositoryError, ServiceError, ServiceResult, StakingTier, StakingTiers, UpdateProfileArgs,
+        User, MAX_HANDLE_SIZE,
+    },
+    utils::{day_index, normalize_handle, normalize_language, DAY_NANOS, MIN_HANDLE_SIZE},
 };
 
+const MAX_FOLLOWED_AUTHORS: usize = 500;
+const MAX_MUTED_USERS: usize = 500;
+const MAX_BLOCKED_USERS: usize = 1_000;
+/// Users notified at most for the mentions of a single story
+pub const MAX_MENTIONS: usize = 20;
+/// Notifications kept per user, older ones are dropped
+const MAX_NOTIFICATIONS: usize = 200;
+/// Followers notified at most when an author publishes a story
+const MAX_NEW_STORY_RECIPIENTS: usize = 1_000;
+const MAX_NAME_SIZE: usize = 50;
+const MAX_BIO_SIZE: usize = 280;
+const MAX_AVATAR_URL_SIZE: usize = 512;
+const MAX_AVATAR_SIZE: usize = 64 * 1024;
+const MAX_PREFERRED_LANGUAGES: usize = 5;
+
 lazy_static! {
     pub static ref USER_SERVICE: Arc<UserService> = Arc::new(UserService::new(
         USER_REPOSITORY.clone(),
+        AI_QUOTA_REPOSITORY.clone(),
+        STATISTICS_REPOSITORY.clone(),
+        NOTIFICATION_REPOSITORY.clone(),
+        CATEGORY_REPOSITORY.clone(),
         LEDGER_SERVICE.clone()
     ));
 }
@@ -20,13 +50,28 @@ lazy_static! {
 #[derive(Debug)]
 pub struct UserService {
     user_repository: Arc<UserRepository>,
+    ai_quota_repository: Arc<AiQuotaRepository>,
+    statistics_repository: Arc<StatisticsRepository>,
+    notification_repository: Arc<NotificationRepository>,
+    category_repository: Arc<CategoryRepository>,
     ledger_service: Arc<LedgerService>,
 }
 
 impl UserService {
-    pub fn new(user_repository: Arc<UserRepository>, ledger_service: Arc<LedgerService>) -> Self {
+    pub fn new(
+        user_repository: Arc<UserRepository>,
+        ai_quota_repository: Arc<AiQuotaRepository>,
+        statistics_repository: Arc<StatisticsRepository>,
+        notification_repository: Arc<NotificationRepository>,
+        category_repository: Arc<CategoryRepository>,
+        ledger_service: Arc<LedgerService>,
+    ) -> Self {
         Self {
             user_repository,
+            ai_quota_repository,
+            statistics_repository,
+            notification_repository,
+            category_repository,
             ledger_service,
         }
     }
@@ -39,6 +84,7 @@ impl UserService {
         }
         let user = User::new(identity, created_at);
         let user = self.user_repository.insert(user).map_err(map_user_err)?;
+        self.statistics_repository.add_user();
         Ok(user)
     }
 
@@ -55,11 +101,13 @@ impl UserService {
         identity: Principal,
         args: OnboardingArgs,
     ) -> ServiceResult<()> {
-        if args.categories.len() != 3 {
-            return Err(ServiceError::UnprocessableEntity {
-                reason: "You must select exactly 3 categories.".to_string(),
-            });
-        }
+        self.validate_categories(&args.categories)?;
+        let handle = args
+            .handle
+            .as_deref()
+            .map(parse_handle)
+            .transpose()?
+            .flatten();
 
         let mut user =
             self.user_repository
@@ -76,22 +124,564 @@ impl UserService {
         user.bio = args.bio;
         user.followed_categories = args.categories;
         user.onboarded = true;
-        self.user_repository.update(user).map_err(map_user_err)?;
+        let old_handle = std::mem::replace(&mut user.handle, handle);
+        let user = self.save_with_handle(user, old_handle)?;
+        self.statistics_repository
+            .add_category_followers(identity, &user.followed_categories);
         Ok(())
     }
 
-    pub fn ensure_ai_enabled(&self, identity: &Principal) -> ServiceResult<()> {
-        let staked = self
-            .ledger_service
-            .locked_balance_of(identity.clone().into());
-        if staked < 1_000_000_usize {
+    pub fn update_profile(
+        &self,
+        identity: Principal,
+        args: UpdateProfileArgs,
+    ) -> ServiceResult<User> {
+        let mut user = self.get_user(&identity)?;
+        if !user.onboarded {
             return Err(ServiceError::UnprocessableEntity {
-                reason: "You need to stake at least 1 million of your token to unlock this feature"
-                    .to_string(),
+                reason: "Complete onboarding before updating your profile.".to_string(),
+            });
+        }
+
+        if let Some(name) = args.name {
+            user.name = validate_profile_text("Name", name, MAX_NAME_SIZE)?;
+        }
+        if let Some(bio) = args.bio {
+            user.bio = validate_profile_text("Bio", bio, MAX_BIO_SIZE)?;
+        }
+        let old_handle = user.handle.clone();
+        if let Some(handle) = args.handle {
+            user.handle = parse_handle(&handle)?;
+        }
+        let avatar = args.avatar.map(validate_avatar).transpose()?;
+        let old_categories = user.followed_categories.clone();
+        if let Some(categories) = args.categories {
+            self.validate_categories(&categories)?;
+            user.followed_categories = categories;
+        }
+        if let Some(show_mature_content) = args.show_mature_content {
+            user.show_mature_content = show_mature_content;
+        }
+        if let Some(languages) = args.languages {
+            user.languages = normalize_languages(&languages)?;
+        }
+
+        let user = self.save_with_handle(user, old_handle)?;
+        if let Some(avatar) = avatar {
+            self.user_repository.set_avatar(identity, avatar);
+        }
+        if user.followed_categories != old_categories {
+            self.statistics_repository
+                .remove_category_followers(identity, &old_categories);
+            self.statistics_repository
+                .add_category_followers(identity, &user.followed_categories);
+        }
+        Ok(user)
+    }
+
+    pub fn resolve_handle(&self, handle: &str) -> ServiceResult<User> {
+        normalize_handle(handle)
+            .and_then(|h| self.user_repository.find_by_handle(&h))
+            .and_then(|id| self.user_repository.get(&id))
+            .ok_or(ServiceError::IdentityNotFound {
+                identity: handle.to_string(),
+            })
+    }
+
+    /// Notifies the users mentioned in a story by their normalized handle, the author excluded.
+    /// Returns the number of users notified
+    pub fn notify_mentions(
+        &self,
+        author: Principal,
+        story_id: u64,
+        handles: &[String],
+        now: u64,
+    ) -> usize {
+        let recipients = handles
+            .iter()
+            .take(MAX_MENTIONS)
+            .filter_map(|handle| self.user_repository.find_by_handle(handle))
+            .filter(|recipient| *recipient != author && !self.is_blocked(*recipient, author))
+            .collect::<BTreeSet<_>>();
+        for recipient in &recipients {
+            self.notify(
+                *recipient,
+                NotificationKind::Mention {
+                    story_id,
+                    by: author,
+                },
+                now,
+            );
+        }
+        recipients.len()
+    }
+
+    /// Notifies the followers of an author about a newly published story.
+    /// Returns the number of users notified
+    pub fn notify_followers(&self, author: Principal, story_id: u64, now: u64) -> usize {
+        let followers = self
+            .user_repository
+            .find_followers(author, MAX_NEW_STORY_RECIPIENTS);
+        for follower in &followers {
+            self.notify(
+                *follower,
+                NotificationKind::NewStory { story_id, author },
+                now,
+            );
+        }
+        followers.len()
+    }
+
+    pub fn notify(&self, recipient: Principal, kind: NotificationKind, now: u64) -> Notification {
+        self.notification_repository.insert(
+            Notification {
+                id: 0,
+                recipient,
+                kind,
+                created_at: now,
+                read: false,
+            },
+            MAX_NOTIFICATIONS,
+        )
+    }
+
+    pub fn get_notifications(
+        &self,
+        identity: Principal,
+        cursor: Option<u64>,
+        limit: usize,
+    ) -> ServiceResult<(Option<u64>, Vec<Notification>)> {
+        let notifications = self
+            .notification_repository
+            .find_by_recipient(identity, cursor, limit);
+        Ok((notifications.last().map(|n| n.id), notifications))
+    }
+
+    /// Marks the given notifications as read, or every notification when no ids are given
+    pub fn mark_notifications_read(
+        &self,
+        identity: Principal,
+        ids: Option<Vec<u64>>,
+    ) -> ServiceResult<usize> {
+        Ok(self
+            .notification_repository
+            .mark_read(identity, ids.as_deref()))
+    }
+
+    pub fn get_unread_notification_count(&self, identity: Principal) -> u64 {
+        self.notification_repository.count_unread(identity)
+    }
+
+    /// Claims the new handle of the user before saving it, a taken handle fails before
+    /// anything is written and the old handle is given back when the update fails
+    fn save_with_handle(&self, user: User, old_handle: Option<String>) -> ServiceResult<User> {
+        if user.handle == old_handle {
+            return self.user_repository.update(user).map_err(map_user_err);
+        }
+        let identity = user.id;
+        let new_handle = user.handle.clone();
+        self.claim_handle(identity, old_handle.as_deref(), new_handle.as_deref())?;
+        self.user_repository.update(user).map_err(|e| {
+            let _ = self.user_repository.set_handle(
+                identity,
+                new_handle.as_deref(),
+                old_handle.as_deref(),
+            );
+            map_user_err(e)
+        })
+    }
+
+    fn claim_handle(
+        &self,
+        identity: Principal,
+        old: Option<&str>,
+        new: Option<&str>,
+    ) -> ServiceResult<()> {
+        self.user_repository
+            .set_handle(identity, old, new)
+            .map_err(|e| match e {
+                RepositoryError::Conflict => ServiceError::Conflict {
+                    entity: "Handle".to_string(),
+                },
+                e => map_user_err(e),
+            })
+    }
+
+    /// Validates followed categories, custom ones must be active
+    fn validate_categories(&self, categories: &[Category]) -> ServiceResult<()> {
+        validate_followed_categories(categories)?;
+        if !categories
+            .iter()
+            .all(|category| self.category_repository.is_available(category))
+        {
+            return Err(ServiceError::UnprocessableEntity {
+                reason: "Category is not available.".to_string(),
             });
         }
         Ok(())
     }
+
+    pub fn get_avatar(&self, identity: &Principal) -> Option<String> {
+        self.user_repository.get_avatar(identity)
+    }
+
+    pub fn follow_author(
+        &self,
+        identity: Principal,
+        author: Principal,
+        now: u64,
+    ) -> ServiceResult<bool> {
+        if identity == author {
+            return Err(ServiceError::UnprocessableEntity {
+                reason: "You cannot follow yourself.".to_string(),
+            });
+        }
+        self.ensure_not_blocked(author, identity)?;
+        let mut user = self.get_user(&identity)?;
+        let mut followed = self.get_user(&author)?;
+        if user.followed_authors.contains(&author) {
+            return Ok(false);
+        }
+        if user.followed_authors.len() >= MAX_FOLLOWED_AUTHORS {
+            return Err(ServiceError::UnprocessableEntity {
+                reason: format!("You can follow at most {} authors.", MAX_FOLLOWED_AUTHORS),
+            });
+        }
+
+        user.followed_authors.push(author);
+        followed.follower = followed.follower.saturating_add(1);
+        self.user_repository.update(user).map_err(map_user_err)?;
+        self.user_repository
+            .update(followed)
+            .map_err(map_user_err)?;
+        self.user_repository.add_follower(author, identity);
+        self.user_repository
+            .record_follower_change(author, day_index(now), true);
+        self.notify(author, NotificationKind::NewFollower { by: identity }, now);
+        Ok(true)
+    }
+
+    pub fn unfollow_author(
+        &self,
+        identity: Principal,
+        author: Principal,
+        now: u64,
+    ) -> ServiceResult<bool> {
+        let mut user = self.get_user(&identity)?;
+        if !user.followed_authors.contains(&author) {
+            return Ok(false);
+        }
+
+        user.followed_authors.retain(|a| a != &author);
+        self.user_repository.update(user).map_err(map_user_err)?;
+        if let Ok(mut followed) = self.get_user(&author) {
+            followed.follower = followed.follower.saturating_sub(1);
+            self.user_repository
+                .update(followed)
+                .map_err(map_user_err)?;
+        }
+        self.user_repository.remove_follower(author, identity);
+        self.user_repository
+            .record_follower_change(author, day_index(now), false);
+        Ok(true)
+    }
+
+    /// Mutes a user, their stories are left out of the feeds of the muting user
+    pub fn mute_user(&self, identity: Principal, user: Principal) -> ServiceResult<bool> {
+        validate_relation_target(identity, user, "mute")?;
+        self.get_user(&user)?;
+        if self
+            .user_repository
+            .find_muted(identity, MAX_MUTED_USERS)
+            .len()
+            >= MAX_MUTED_USERS
+        {
+            return Err(ServiceError::UnprocessableEntity {
+                reason: format!("You can mute at most {} users.", MAX_MUTED_USERS),
+            });
+        }
+        Ok(self.user_repository.mute(identity, user))
+    }
+
+    pub fn unmute_user(&self, identity: Principal, user: Principal) -> ServiceResult<bool> {
+        Ok(self.user_repository.unmute(identity, user))
+    }
+
+    pub fn get_muted_users(&self, identity: Principal) -> Vec<Principal> {
+        self.user_repository.find_muted(identity, MAX_MUTED_USERS)
+    }
+
+    /// Blocks a user from interacting with the blocking user, a follow of the blocked user
+    /// is removed
+    pub fn block_user(
+        &self,
+        identity: Principal,
+        user: Principal,
+        now: u64,
+    ) -> ServiceResult<bool> {
+        validate_relation_target(identity, user, "block")?;
+        self.get_user(&user)?;
+        if self
+            .user_repository
+            .find_blocked(identity, MAX_BLOCKED_USERS)
+            .len()
+            >= MAX_BLOCKED_USERS
+        {
+            return Err(ServiceError::UnprocessableEntity {
+                reason: format!("You can block at most {} users.", MAX_BLOCKED_USERS),
+            });
+        }
+        if !self.user_repository.block(identity, user) {
+            return Ok(false);
+        }
+        self.unfollow_author(user, identity, now)?;
+        Ok(true)
+    }
+
+    pub fn unblock_user(&self, identity: Principal, user: Principal) -> ServiceResult<bool> {
+        Ok(self.user_repository.unblock(identity, user))
+    }
+
+    pub fn get_blocked_users(&self, identity: Principal) -> Vec<Principal> {
+        self.user_repository
+            .find_blocked(identity, MAX_BLOCKED_USERS)
+    }
+
+    pub fn is_blocked(&self, identity: Principal, user: Principal) -> bool {
+        self.user_repository.is_blocked(identity, user)
+    }
+
+    /// Fails when `user` is blocked by `identity`
+    pub fn ensure_not_blocked(&self, identity: Principal, user: Principal) -> ServiceResult<()> {
+        if self.is_blocked(identity, user) {
+            return Err(ServiceError::IdentityUnauthorized {
+                identity: user.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Gets the followers gained and lost by an author per day, both day indexes inclusive
+    pub fn get_follower_growth(
+        &self,
+        author: Principal,
+        from_day: u64,
+        to_day: u64,
+    ) -> Vec<DailyFollowerChange> {
+        self.user_repository
+            .get_follower_changes(author, from_day, to_day)
+            .into_iter()
+            .map(|(day, change)| DailyFollowerChange { day, change })
+            .collect()
+    }
+
+    pub fn get_staking_tiers(&self) -> StakingTiers {
+        self.ai_quota_repository.get_tiers()
+    }
+
+    pub fn set_staking_tiers(&self, tiers: Vec<StakingTier>) -> ServiceResult<StakingTiers> {
+        let tiers = validate_staking_tiers(tiers)?;
+        self.ai_quota_repository
+            .set_tiers(tiers)
+            .map_err(map_user_err)
+    }
+
+    pub fn get_ai_quota(&self, identity: &Principal, now: u64) -> AiQuota {
+        let staked = self.ledger_service.locked_balance_of((*identity).into());
+        let tiers = self.ai_quota_repository.get_tiers();
+        let tier = resolve_staking_tier(&tiers, &staked);
+        let day = day_index(now);
+        let used = self.ai_quota_repository.get_usage(*identity, day);
+        let daily_quota = tier.map_or(0, |t| t.daily_quota);
+        AiQuota {
+            tier: tier.map(|t| t.name.clone()),
+            staked,
+            daily_quota,
+            used,
+            remaining: daily_quota.saturating_sub(used),
+            resets_at: (day + 1) * DAY_NANOS,
+        }
+    }
+
+    /// Takes one AI assist request out of the daily quota granted by the staking tier
+    pub fn consume_ai_quota(&self, identity: &Principal, now: u64) -> ServiceResult<AiQuota> {
+        let quota = self.get_ai_quota(identity, now);
+        if quota.tier.is_none() {
+            let min_stake = self
+                .ai_quota_repository
+                .get_tiers()
+                .0
+                .first()
+                .map(|t| t.min_stake.clone())
+                .unwrap_or_default();
+            return Err(ServiceError::UnprocessableEntity {
+                reason: format!(
+                    "You need to stake at least {} of your token to unlock this feature",
+                    min_stake
+                ),
+            });
+        }
+        if quota.remaining == 0 {
+            return Err(ServiceError::AiQuotaExceeded {
+                quota: quota.daily_quota,
+                resets_at: quota.resets_at,
+            });
+        }
+        let used = self
+            .ai_quota_repository
+            .increment_usage(*identity, day_index(now));
+        Ok(AiQuota {
+            used,
+            remaining: quota.daily_quota.saturating_sub(used),
+            ..quota
+        })
+    }
+
+    /// Gives back a request taken by `consume_ai_quota`, when the assist action did not complete
+    pub fn refund_ai_quota(&self, identity: &Principal, now: u64) {
+        self.ai_quota_repository
+            .decrement_usage(*identity, day_index(now));
+    }
+}
+
+/// Picks the highest tier reached by the staked amount, tiers are sorted by `min_stake`
+fn resolve_staking_tier<'a>(tiers: &'a StakingTiers, staked: &Tokens) -> Option<&'a StakingTier> {
+    tiers.0.iter().rev().find(|t| &t.min_stake <= staked)
+}
+
+fn validate_staking_tiers(mut tiers: Vec<StakingTier>) -> ServiceResult<StakingTiers> {
+    if tiers.is_empty() {
+        return Err(ServiceError::UnprocessableEntity {
+            reason: "At least one staking tier is required.".to_string(),
+        });
+    }
+    tiers.sort_by(|a, b| a.min_stake.cmp(&b.min_stake));
+    let mut names = BTreeSet::new();
+    for (i, tier) in tiers.iter().enumerate() {
+        if tier.name.trim().is_empty() {
+            return Err(ServiceError::UnprocessableEntity {
+                reason: "Staking tier name cannot be empty.".to_string(),
+            });
+        }
+        if tier.daily_quota == 0 || tier.min_stake == 0_usize {
+            return Err(ServiceError::UnprocessableEntity {
+                reason: format!("Staking tier {} must have a stake and a quota.", tier.name),
+            });
+        }
+        if let Some(prev) = i.checked_sub(1).map(|p| &tiers[p]) {
+            if prev.min_stake == tier.min_stake {
+                return Err(ServiceError::UnprocessableEntity {
+                    reason: format!("Staking tier {} is duplicated.", tier.name),
+                });
+            }
+        }
+        if !names.insert(tier.name.as_str()) {
+            return Err(ServiceError::UnprocessableEntity {
+                reason: format!("Staking tier {} is duplicated.", tier.name),
+            });
+        }
+    }
+    Ok(StakingTiers(tiers))
+}
+
+/// Normalizes a handle, an empty handle removes it
+fn parse_handle(handle: &str) -> ServiceResult<Option<String>> {
+    if handle.trim().is_empty() {
+        return Ok(None);
+    }
+    normalize_handle(handle)
+        .map(Some)
+        .ok_or_else(|| ServiceError::UnprocessableEntity {
+            reason: format!(
+                "Handle must be {} to {} letters, digits or underscores.",
+                MIN_HANDLE_SIZE, MAX_HANDLE_SIZE
+            ),
+        })
+}
+
+fn validate_followed_categories(categories: &[Category]) -> ServiceResult<()> {
+    if categories.len() != 3 || categories.iter().collect::<BTreeSet<_>>().len() != categories.len()
+    {
+        return Err(ServiceError::UnprocessableEntity {
+            reason: "You must select exactly 3 categories.".to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn validate_relation_target(
+    identity: Principal,
+    user: Principal,
+    action: &str,
+) -> ServiceResult<()> {
+    if identity == user {
+        return Err(ServiceError::UnprocessableEntity {
+            reason: format!("You cannot {} yourself.", action),
+        });
+    }
+    Ok(())
+}
+
+/// Normalizes preferred languages, dropping duplicates and keeping their order
+fn normalize_languages(languages: &[String]) -> ServiceResult<Vec<String>> {
+    let mut normalized: Vec<String> = Vec::new();
+    for language in languages {
+        let language =
+            normalize_language(language).ok_or_else(|| ServiceError::UnprocessableEntity {
+                reason: format!("Invalid language: {}", language),
+            })?;
+        if !normalized.contains(&language) {
+            normalized.push(language);
+        }
+    }
+    if normalized.len() > MAX_PREFERRED_LANGUAGES {
+        return Err(ServiceError::UnprocessableEntity {
+            reason: format!(
+                "At most {} languages can be preferred.",
+                MAX_PREFERRED_LANGUAGES
+            ),
+        });
+    }
+    Ok(normalized)
+}
+
+/// Trims a profile text, an empty text clears the field
+fn validate_profile_text(
+    field: &str,
+    text: String,
+    max_size: usize,
+) -> ServiceResult<Option<String>> {
+    let text = text.trim();
+    if text.chars().count() > max_size {
+        return Err(ServiceError::UnprocessableEntity {
+            reason: format!("{} cannot exceed {} characters.", field, max_size),
+        });
+    }
+    Ok((!text.is_empty()).then(|| text.to_string()))
+}
+
+/// Accepts an `https` URL or a base64 image data URI, an empty avatar removes it
+fn validate_avatar(avatar: String) -> ServiceResult<Option<String>> {
+    let avatar = avatar.trim();
+    if avatar.is_empty() {
+        return Ok(None);
+    }
+    let valid = if avatar.starts_with("https://") {
+        avatar.len() <= MAX_AVATAR_URL_SIZE
+    } else {
+        avatar.starts_with("data:image/")
+            && avatar.contains(";base64,")
+            && avatar.len() <= MAX_AVATAR_SIZE
+    };
+    if !valid {
+        return Err(ServiceError::UnprocessableEntity {
+            reason: format!(
+                "Avatar must be an https URL or a base64 image of at most {} bytes.",
+                MAX_AVATAR_SIZE
+            ),
+        });
+    }
+    Ok(Some(avatar.to_string()))
 }
 
 fn map_user_err(e: RepositoryError) -> ServiceError {
@@ -110,3 +700,167 @@ fn map_user_err(e: RepositoryError) -> ServiceError {
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use candid::Principal;
+
+    use crate::{
+        repositories::{notification::NotificationRepository, user::UserRepository},
+        types::{Category, Notification, NotificationKind, StakingTier, StakingTiers, User},
+    };
+
+    use super::{
+        resolve_staking_tier, validate_avatar, validate_followed_categories, validate_profile_text,
+        validate_staking_tiers, USER_SERVICE,
+    };
+
+    fn tier(name: &str, min_stake: usize, daily_quota: u32) -> StakingTier {
+        StakingTier {
+            name: name.to_string(),
+            min_stake: min_stake.into(),
+            daily_quota,
+        }
+    }
+
+    #[test]
+    fn test_resolve_staking_tier() {
+        let tiers = StakingTiers::default();
+        assert!(resolve_staking_tier(&tiers, &999_999_usize.into()).is_none());
+        assert_eq!(
+            resolve_staking_tier(&tiers, &1_000_000_usize.into()).map(|t| t.name.as_str()),
+            Some("Bronze")
+        );
+        assert_eq!(
+            resolve_staking_tier(&tiers, &50_000_000_usize.into()).map(|t| t.name.as_str()),
+            Some("Silver")
+        );
+    }
+
+    #[test]
+    fn test_validate_staking_tiers() {
+        let tiers = validate_staking_tiers(vec![tier("B", 20, 5), tier("A", 10, 1)]).unwrap();
+        assert_eq!(tiers.0[0].name, "A");
+
+        assert!(validate_staking_tiers(vec![]).is_err());
+        assert!(validate_staking_tiers(vec![tier("A", 10, 0)]).is_err());
+        assert!(validate_staking_tiers(vec![tier("A", 10, 1), tier("B", 10, 2)]).is_err());
+        assert!(
+            validate_staking_tiers(vec![tier("A", 10, 1), tier("B", 20, 2), tier("A", 30, 3)])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_validate_profile() {
+        assert_eq!(
+            validate_profile_text("Name", "  Jane ".to_string(), 50).unwrap(),
+            Some("Jane".to_string())
+        );
+        assert_eq!(
+            validate_profile_text("Bio", " ".to_string(), 5).unwrap(),
+            None
+        );
+        assert!(validate_profile_text("Bio", "abcdef".to_string(), 5).is_err());
+
+        assert_eq!(validate_avatar("".to_string()).unwrap(), None);
+        assert!(validate_avatar("https://example.com/a.png".to_string()).is_ok());
+        assert!(validate_avatar("data:image/png;base64,AAAA".to_string()).is_ok());
+        assert!(validate_avatar("http://example.com/a.png".to_string()).is_err());
+        assert!(validate_avatar("data:text/html;base64,AAAA".to_string()).is_err());
+
+        assert!(validate_followed_categories(&[
+            Category::SciFi,
+            Category::Horror,
+            Category::Crime
+        ])
+        .is_ok());
+        assert!(
+            validate_followed_categories(&[Category::SciFi, Category::SciFi, Category::Crime])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_notification_inbox() {
+        let repository = NotificationRepository;
+        let recipient = Principal::from_slice(&[1]);
+        let by = Principal::from_slice(&[2]);
+        let notification = |created_at| Notification {
+            id: 0,
+            recipient,
+            kind: NotificationKind::NewFollower { by },
+            created_at,
+            read: false,
+        };
+        for created_at in 0..5 {
+            repository.insert(notification(created_at), 3);
+        }
+        repository.insert(
+            Notification {
+                recipient: by,
+                ..notification(5)
+            },
+            3,
+        );
+
+        let inbox = repository.find_by_recipient(recipient, None, 10);
+        assert_eq!(
+            inbox.iter().map(|n| n.created_at).collect::<Vec<_>>(),
+            vec![4, 3, 2]
+        );
+        let page = repository.find_by_recipient(recipient, Some(inbox[0].id), 1);
+        assert_eq!(page[0].id, inbox[1].id);
+
+        assert_eq!(repository.count_unread(recipient), 3);
+        assert_eq!(repository.mark_read(recipient, Some(&[inbox[0].id])), 1);
+        assert_eq!(repository.count_unread(recipient), 2);
+        assert_eq!(repository.mark_read(recipient, None), 2);
+        assert_eq!(repository.count_unread(recipient), 0);
+        assert_eq!(repository.count_unread(by), 1);
+    }
+
+    #[test]
+    fn test_release_handle_of_failed_update() {
+        let user = User {
+            id: Principal::from_slice(&[4]),
+            name: None,
+            bio: None,
+            follower: 0,
+            created_at: 0,
+            followed_categories: vec![],
+            followed_authors: vec![],
+            onboarded: true,
+            handle: Some("ghost".to_string()),
+            show_mature_content: false,
+            languages: vec![],
+        };
+
+        // the user was never stored, so the update fails after the handle is claimed
+        assert!(USER_SERVICE.save_with_handle(user, None).is_err());
+        assert_eq!(UserRepository.find_by_handle("ghost"), None);
+    }
+
+    #[test]
+    fn test_mute_and_block() {
+        let repository = UserRepository;
+        let user = Principal::from_slice(&[1]);
+        let other = Principal::from_slice(&[2]);
+        let third = Principal::from_slice(&[3]);
+
+        assert!(repository.mute(user, other));
+        assert!(!repository.mute(user, other));
+        assert!(repository.mute(user, third));
+        assert!(repository.mute(other, user));
+        assert_eq!(repository.find_muted(user, 10), vec![other, third]);
+        assert!(repository.unmute(user, other));
+        assert_eq!(repository.find_muted(user, 10), vec![third]);
+
+        assert!(repository.block(user, other));
+        assert!(repository.is_blocked(user, other));
+        assert!(!repository.is_blocked(other, user));
+        assert!(repository.unblock(user, other));
+        assert!(!repository.is_blocked(user, other));
+        assert!(repository.find_blocked(user, 10).is_empty());
+    }
+}
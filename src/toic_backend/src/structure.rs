@@ -2,10 +2,11 @@ use std::cell::RefCell;
 
 use ic_stable_structures::{BTreeMap, Cell, Memory, Storable};
 
-use crate::{
-    types::{AuditableEntity, RepositoryError, RepositoryResult, SortOrder},
-    utils::timestamp,
-};
+use crate::types::{AuditableEntity, RepositoryError, RepositoryResult, SortOrder};
+#[cfg(all(test, not(rust_analyzer)))]
+use crate::utils::mocks::timestamp;
+#[cfg(any(not(test), rust_analyzer))]
+use crate::utils::timestamp;
 
 pub trait SerialIdRepository<M>
 where
@@ -1,8 +1,8 @@
 // https://github.com/dfinity/examples/blob/master/rust/tokenmania/backend/lib.rs
 
-use std::{cell::RefCell, str::FromStr, sync::Arc};
+use std::{cell::RefCell, collections::BTreeSet, str::FromStr, sync::Arc};
 
-use candid::Nat;
+use candid::{Nat, Principal};
 #[cfg(any(not(test), rust_analyzer))]
 use ic_cdk::api::{caller, id, is_controller};
 use ic_cdk::{query, update};
@@ -24,16 +24,19 @@ use lazy_static::lazy_static;
 
 use crate::memory::{
     MEMORY_MANAGER, TOKEN_ACCOUNT_BALANCE_MEM_ID, TOKEN_ACCOUNT_STAKING_MEM_ID,
-    TOKEN_CONFIG_MEM_ID, TOKEN_TX_LOG_MEM_ID,
+    TOKEN_CONFIG_AUDIT_MEM_ID, TOKEN_CONFIG_MEM_ID, TOKEN_TX_LOG_MEM_ID,
 };
 #[cfg(any(not(test), rust_analyzer))]
 use crate::utils::timestamp;
 
 use super::{
-    constant::TOKEN_DATA_IMAGE, to_approve_error, to_transfer_from_error, AccountBalanceRefCell,
-    AccountOwnerBalanceRefCell, ConfigRefCell, Configuration, CreateTokenArgs, StakeTokenArgs,
-    StorableToken, StorableTransaction, SupportedStandard, Tokens, TransactionLog,
-    TransactionLogRefCell, TxInfo,
+    constant::TOKEN_DATA_IMAGE,
+    staking::{clear_staking, record_stake},
+    to_approve_error, to_transfer_from_error, AccountBalanceRefCell, AccountOwnerBalanceRefCell,
+    ConfigAuditLogRefCell, ConfigFieldChange, ConfigRefCell, Configuration, CreateTokenArgs,
+    DeleteTokenArgs, StakeTokenArgs, StorableToken, StorableTransaction, SupportedStandard,
+    TokenConfigChange, TokenStatistics, Tokens, TransactionLog, TransactionLogRefCell, TxInfo,
+    UpdateTokenConfigArgs,
 };
 
 #[cfg(all(test, not(rust_analyzer)))]
@@ -42,6 +45,10 @@ use crate::utils::mocks::{caller, id, is_controller, timestamp};
 const MAX_MEMO_SIZE: usize = 32;
 const PERMITTED_DRIFT_NANOS: u64 = 60_000_000_000;
 const TRANSACTION_WINDOW_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+const MAX_TOKEN_NAME_SIZE: usize = 32;
+const MAX_TOKEN_LOGO_SIZE: usize = 256 * 1024;
+const MAX_TRANSFER_FEE: u64 = 1_000_000_000;
+const DELETE_CONFIRMATION_WINDOW_NANOS: u64 = 5 * 60 * 1_000_000_000;
 
 // Error codes
 const MEMO_TOO_LONG_ERROR_CODE: usize = 0;
@@ -73,6 +80,16 @@ thread_local! {
             MEMORY_MANAGER.with_borrow(|m| m.get(TOKEN_ACCOUNT_STAKING_MEM_ID))
         )
     );
+
+    static CONFIG_AUDIT_LOG: ConfigAuditLogRefCell = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(TOKEN_CONFIG_AUDIT_MEM_ID))
+        )
+    );
+
+    // (requested by, confirmation code, expires at), kept on the heap on purpose so an
+    // upgrade cancels any pending deletion
+    static PENDING_DELETION: RefCell<Option<(Principal, u64, u64)>> = const { RefCell::new(None) };
 }
 
 lazy_static! {
@@ -99,6 +116,14 @@ impl LedgerService {
         get_locked_balance(account)
     }
 
+    pub fn is_token_created(&self) -> bool {
+        token_created()
+    }
+
+    pub fn get_token_statistics(&self) -> TokenStatistics {
+        token_statistics()
+    }
+
     pub fn mint(&self, arg: TransferArg) -> Result<BlockIndex, TransferError> {
         let tx = TxInfo {
             from: icrc1_minting_account().expect("Bug: failed to get minting account"),
@@ -154,7 +179,7 @@ fn get_cached_balance(account: Account) -> Tokens {
 }
 
 /// Retrieves the locked/staked balance of an account from the cache.
-fn get_locked_balance(account: Account) -> Tokens {
+pub(super) fn get_locked_balance(account: Account) -> Tokens {
     STAKED.with_borrow(|balances| {
         balances
             .get(&account.owner)
@@ -163,6 +188,17 @@ fn get_locked_balance(account: Account) -> Tokens {
     })
 }
 
+/// Lists the owners holding a locked/staked balance.
+pub(super) fn get_staked_owners() -> Vec<Principal> {
+    STAKED.with_borrow(|staked| {
+        staked
+            .iter()
+            .filter(|(_, s)| s.0 > 0_usize)
+            .map(|(owner, _)| owner)
+            .collect()
+    })
+}
+
 /// Updates the balance of an account in the cache.
 /// This function is called after a transaction is successfully applied.
 fn update_balance(tx: &Transaction) -> Result<(), TransferError> {
@@ -496,7 +532,7 @@ fn map_tx(tx: TxInfo, now: u64) -> Result<StorableTransaction, TransferError> {
 }
 
 /// Runs validity checks and records the transaction followed by updating balance cahches if it is valid
-fn apply_tx(tx: TxInfo) -> Result<BlockIndex, TransferError> {
+pub(super) fn apply_tx(tx: TxInfo) -> Result<BlockIndex, TransferError> {
     validate_account(tx.from, tx.to)?;
     validate_memo(tx.memo.as_ref())?;
     let now = timestamp();
@@ -521,6 +557,8 @@ fn stake_token(from: Account, amount: Tokens) -> Result<BlockIndex, TransferErro
         is_approval: false,
     };
     let block = apply_tx(tx)?;
+    // position has to be recorded before the locked balance is increased
+    record_stake(from.owner, amount.clone(), timestamp());
     STAKED.with_borrow_mut(|m| {
         let prev = m.get(&from.owner).map(|s| s.0).unwrap_or_default();
         m.insert(from.owner, StorableToken(prev + amount));
@@ -541,7 +579,7 @@ fn log_trx() -> Vec<Transaction> {
 }
 
 #[update]
-fn create_token(args: Option<CreateTokenArgs>) -> Result<String, String> {
+pub(crate) fn create_token(args: Option<CreateTokenArgs>) -> Result<String, String> {
     let caller = caller();
     if !is_controller(&caller) {
         return Err("Unauthorized operation".to_string());
@@ -594,12 +632,58 @@ fn create_token(args: Option<CreateTokenArgs>) -> Result<String, String> {
 }
 
 #[query]
-fn token_created() -> bool {
+pub(super) fn token_created() -> bool {
     CONFIG.with_borrow(|config| config.get().token_created)
 }
 
+/// Deleting the token on a shared network has to be confirmed with a second call. A build
+/// without `DFX_NETWORK` may be deployed anywhere, so only an explicit local build skips it
+fn is_local_network() -> bool {
+    option_env!("DFX_NETWORK") == Some("local")
+}
+
+/// Whether any account other than the minting account still holds or stakes tokens
+fn has_user_balances() -> bool {
+    let minting_account = icrc1_minting_account();
+    let has_balance = BALANCES.with_borrow(|balances| {
+        balances
+            .iter()
+            .any(|(account, balance)| Some(account) != minting_account && balance.0 > 0_usize)
+    });
+    has_balance || STAKED.with_borrow(|staked| staked.iter().any(|(_, s)| s.0 > 0_usize))
+}
+
+fn token_statistics() -> TokenStatistics {
+    let minting_account = icrc1_minting_account();
+    let mut holders = BALANCES.with_borrow(|balances| {
+        balances
+            .iter()
+            .filter(|(account, balance)| Some(*account) != minting_account && balance.0 > 0_usize)
+            .map(|(account, _)| account.owner)
+            .collect::<BTreeSet<_>>()
+    });
+    let total_staked = STAKED.with_borrow(|staked| {
+        staked
+            .iter()
+            .filter(|(_, s)| s.0 > 0_usize)
+            .fold(Tokens::default(), |total, (owner, s)| {
+                holders.insert(owner);
+                total + s.0
+            })
+    });
+    TokenStatistics {
+        holders: holders.len() as u64,
+        total_staked,
+        total_supply: total_supply(),
+    }
+}
+
 #[update]
-fn delete_token() -> Result<String, String> {
+fn delete_token(args: Option<DeleteTokenArgs>) -> Result<String, String> {
+    delete_token_on_network(args.unwrap_or_default(), is_local_network())
+}
+
+fn delete_token_on_network(args: DeleteTokenArgs, is_local: bool) -> Result<String, String> {
     let caller = caller();
     if !is_controller(&caller) {
         return Err("Unauthorized operation".to_string());
@@ -613,6 +697,29 @@ fn delete_token() -> Result<String, String> {
         return Err("Caller is not the token creator".to_string());
     };
 
+    if !args.force && has_user_balances() {
+        return Err("Users still hold token balances, use force to delete anyway".to_string());
+    }
+
+    if !is_local {
+        let now = timestamp();
+        let pending = PENDING_DELETION.with_borrow(|p| *p);
+        match (pending, args.confirmation_code) {
+            (Some((requester, code, expires_at)), Some(confirmation))
+                if requester == caller && code == confirmation && now <= expires_at => {}
+            _ => {
+                let code = now;
+                PENDING_DELETION.with_borrow_mut(|p| {
+                    *p = Some((caller, code, now + DELETE_CONFIRMATION_WINDOW_NANOS))
+                });
+                return Err(format!(
+                    "Confirm the deletion by calling delete_token with confirmation code {} within 5 minutes",
+                    code
+                ));
+            }
+        }
+    }
+
     // Reset stable memory
     CONFIG.with_borrow_mut(|config| {
         config.set(Configuration::default()).unwrap();
@@ -621,9 +728,211 @@ fn delete_token() -> Result<String, String> {
         let memory = MEMORY_MANAGER.with_borrow_mut(|mm| mm.get(TOKEN_TX_LOG_MEM_ID));
         *cell = TransactionLog::new(memory).unwrap();
     });
+    BALANCES.with_borrow_mut(|balances| balances.clear_new());
+    STAKED.with_borrow_mut(|staked| staked.clear_new());
+    CONFIG_AUDIT_LOG.with_borrow_mut(|log| log.clear_new());
+    clear_staking();
+    PENDING_DELETION.with_borrow_mut(|p| *p = None);
     Ok("Token deleted".to_string())
 }
 
+/// Validates the requested config update and lists the fields that are actually changing
+fn diff_token_config(
+    args: &UpdateTokenConfigArgs,
+    config: &Configuration,
+) -> Result<Vec<ConfigFieldChange>, String> {
+    let mut changes = Vec::new();
+    if let Some(name) = &args.token_name {
+        let name = name.trim();
+        if name.is_empty() || name.len() > MAX_TOKEN_NAME_SIZE {
+            return Err(format!(
+                "Token name must be between 1 and {} characters",
+                MAX_TOKEN_NAME_SIZE
+            ));
+        }
+        if name != config.token_name {
+            changes.push(ConfigFieldChange {
+                field: "token_name".to_string(),
+                old_value: config.token_name.clone(),
+                new_value: name.to_string(),
+            });
+        }
+    }
+    if let Some(logo) = &args.token_logo {
+        if !(logo.starts_with("data:image/") || logo.starts_with("https://")) {
+            return Err("Token logo must be a data:image URI or an https URL".to_string());
+        }
+        if logo.len() > MAX_TOKEN_LOGO_SIZE {
+            return Err(format!(
+                "Token logo cannot exceed {} bytes",
+                MAX_TOKEN_LOGO_SIZE
+            ));
+        }
+        if logo != &config.token_logo {
+            // logos can be large data URIs, only their size is audited
+            changes.push(ConfigFieldChange {
+                field: "token_logo".to_string(),
+                old_value: format!("{} bytes", config.token_logo.len()),
+                new_value: format!("{} bytes", logo.len()),
+            });
+        }
+    }
+    if let Some(fee) = &args.transfer_fee {
+        if fee > &MAX_TRANSFER_FEE {
+            return Err(format!("Transfer fee cannot exceed {}", MAX_TRANSFER_FEE));
+        }
+        if fee != &config.transfer_fee {
+            changes.push(ConfigFieldChange {
+                field: "transfer_fee".to_string(),
+                old_value: config.transfer_fee.to_string(),
+                new_value: fee.to_string(),
+            });
+        }
+    }
+    if let Some(account) = &args.minting_account {
+        if account == &stake_account_address() {
+            return Err("Staking account cannot be the minting account".to_string());
+        }
+        if !is_controller(&account.owner) {
+            return Err("Minting account must be owned by a controller".to_string());
+        }
+        if Some(*account) != config.minting_account {
+            changes.push(ConfigFieldChange {
+                field: "minting_account".to_string(),
+                old_value: config
+                    .minting_account
+                    .map(|a| a.to_string())
+                    .unwrap_or_default(),
+                new_value: account.to_string(),
+            });
+        }
+    }
+    Ok(changes)
+}
+
+#[update]
+pub(crate) fn update_token_config(args: UpdateTokenConfigArgs) -> Result<String, String> {
+    let caller = caller();
+    if !is_controller(&caller) {
+        return Err("Unauthorized operation".to_string());
+    }
+
+    if !token_created() {
+        return Err("Token not created".to_string());
+    };
+
+    let changes = CONFIG.with_borrow(|config| diff_token_config(&args, config.get()))?;
+    if changes.is_empty() {
+        return Err("Nothing to update".to_string());
+    }
+    let old_minting_account = icrc1_minting_account();
+
+    CONFIG.with_borrow_mut(|config| {
+        let current = config.get();
+        config
+            .set(Configuration {
+                token_name: args
+                    .token_name
+                    .map(|n| n.trim().to_string())
+                    .unwrap_or_else(|| current.token_name.clone()),
+                token_symbol: current.token_symbol.clone(),
+                token_logo: args
+                    .token_logo
+                    .unwrap_or_else(|| current.token_logo.clone()),
+                transfer_fee: args
+                    .transfer_fee
+                    .unwrap_or_else(|| current.transfer_fee.clone()),
+                decimals: current.decimals,
+                minting_account: args.minting_account.or(current.minting_account),
+                token_created: current.token_created,
+            })
+            .map_err(|_| "Failed to update config".to_string())
+    })?;
+    if let (Some(old), Some(new)) = (old_minting_account, args.minting_account) {
+        if old != new {
+            migrate_minting_balance(old, new);
+        }
+    }
+    CONFIG_AUDIT_LOG.with_borrow_mut(|log| {
+        let idx = log.last_key_value().map_or(0, |(k, _)| k + 1);
+        log.insert(
+            idx,
+            TokenConfigChange {
+                changed_by: caller,
+                timestamp: timestamp(),
+                changes,
+            },
+        );
+    });
+    Ok("Token config updated".to_string())
+}
+
+/// Balance of an account according to the transaction log, which the cache can lag behind for
+/// the minting account as mints from it are never debited
+fn logged_balance(account: Account) -> Tokens {
+    TRANSACTION_LOG.with_borrow(|log| {
+        log.iter().fold(Tokens::default(), |balance, tx_wrapper| {
+            let tx = tx_wrapper.0;
+            let mut balance = balance;
+            if let Some(mint) = tx.mint.filter(|m| m.to == account) {
+                balance += mint.amount;
+            }
+            if let Some(burn) = tx.burn.filter(|b| b.from == account) {
+                balance -= burn.amount;
+            }
+            if let Some(transfer) = tx.transfer {
+                if transfer.from == account {
+                    balance -= transfer.amount.clone() + transfer.fee.unwrap_or_default();
+                }
+                if transfer.to == account {
+                    balance += transfer.amount;
+                }
+            }
+            balance
+        })
+    })
+}
+
+/// Moves the supply held by the previous minting account to the new one, as a burn followed by
+/// a mint so the transaction log, the balances cache and the total supply stay consistent
+fn migrate_minting_balance(old: Account, new: Account) {
+    let amount = logged_balance(old);
+    if amount == 0_usize {
+        return;
+    }
+    let now = timestamp();
+    record_valid_transaction(&StorableTransaction(Transaction::burn(
+        Burn {
+            amount: amount.clone(),
+            from: old,
+            spender: None,
+            memo: None,
+            created_at_time: None,
+        },
+        now,
+    )));
+    record_valid_transaction(&StorableTransaction(Transaction::mint(
+        Mint {
+            amount,
+            to: new,
+            memo: None,
+            created_at_time: None,
+        },
+        now,
+    )));
+    BALANCES.with_borrow_mut(|balances| {
+        if let Some(cached) = balances.remove(&old) {
+            let prev = balances.get(&new).map(|b| b.0).unwrap_or_default();
+            balances.insert(new, StorableToken(prev + cached.0));
+        }
+    });
+}
+
+#[query]
+fn get_token_config_history() -> Vec<TokenConfigChange> {
+    CONFIG_AUDIT_LOG.with_borrow(|log| log.values().collect())
+}
+
 const STAKE_SUBACCOUNT: [u8; 32] = [137; 32];
 
 fn stake_account_address() -> Account {
@@ -639,7 +948,7 @@ fn staked_balance_of(account: Account) -> Tokens {
 }
 
 #[update]
-fn stake(arg: StakeTokenArgs) -> Result<BlockIndex, TransferError> {
+pub(super) fn stake(arg: StakeTokenArgs) -> Result<BlockIndex, TransferError> {
     let from = Account {
         owner: caller(),
         subaccount: arg.from_subaccount,
@@ -648,7 +957,7 @@ fn stake(arg: StakeTokenArgs) -> Result<BlockIndex, TransferError> {
 }
 
 #[update]
-fn icrc1_transfer(arg: TransferArg) -> Result<BlockIndex, TransferError> {
+pub(super) fn icrc1_transfer(arg: TransferArg) -> Result<BlockIndex, TransferError> {
     let from = Account {
         owner: caller(),
         subaccount: arg.from_subaccount,
@@ -669,7 +978,7 @@ fn icrc1_transfer(arg: TransferArg) -> Result<BlockIndex, TransferError> {
 }
 
 #[query]
-fn icrc1_balance_of(account: Account) -> Tokens {
+pub(super) fn icrc1_balance_of(account: Account) -> Tokens {
     get_cached_balance(account)
 }
 
@@ -679,7 +988,7 @@ fn icrc1_total_supply() -> Tokens {
 }
 
 #[query]
-fn icrc1_minting_account() -> Option<Account> {
+pub(super) fn icrc1_minting_account() -> Option<Account> {
     CONFIG.with_borrow(|config| config.get().minting_account.clone())
 }
 
@@ -817,6 +1126,7 @@ fn icrc2_allowance(arg: AllowanceArgs) -> Allowance {
 mod tests {
     use candid::{Nat, Principal};
     use icrc_ledger_types::{
+        icrc::generic_metadata_value::MetadataValue,
         icrc1::{
             account::Account,
             transfer::{TransferArg, TransferError},
@@ -827,13 +1137,16 @@ mod tests {
     use crate::{
         token::{
             api::{
-                create_token, delete_token, icrc1_balance_of, icrc1_decimals, icrc1_fee,
-                icrc1_metadata, icrc1_minting_account, icrc1_name, icrc1_supported_standards,
-                icrc1_token_symbol, icrc1_total_supply, icrc1_transfer, icrc2_allowance,
-                icrc2_approve, icrc2_transfer_from, token_created, validate_created_at_time,
-                BALANCES, TRANSACTION_LOG, TRANSACTION_WINDOW_NANOS,
+                create_token, delete_token, delete_token_on_network, get_token_config_history,
+                has_user_balances, icrc1_balance_of, icrc1_decimals, icrc1_fee, icrc1_metadata,
+                icrc1_minting_account, icrc1_name, icrc1_supported_standards, icrc1_token_symbol,
+                icrc1_total_supply, icrc1_transfer, icrc2_allowance, icrc2_approve,
+                icrc2_transfer_from, logged_balance, rebuild_balances_cache, token_created,
+                update_token_config, validate_created_at_time, BALANCES, TRANSACTION_LOG,
+                TRANSACTION_WINDOW_NANOS,
             },
-            CreateTokenArgs,
+            staking::{get_staking_apr, set_staking_apr},
+            CreateTokenArgs, DeleteTokenArgs, UpdateTokenConfigArgs,
         },
         utils::mocks::{caller, reset_timestamp, set_caller, timestamp},
         Tokens,
@@ -877,15 +1190,87 @@ mod tests {
     #[test]
     fn test_delete_token() {
         create_token_with_default_args().unwrap();
-        let result = delete_token();
+        let result = delete_token_on_network(DeleteTokenArgs::default(), true);
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), "Token deleted".to_string());
         assert!(!token_created());
     }
 
+    #[test]
+    fn test_delete_token_clears_ledger_state() {
+        create_token_with_default_args().unwrap();
+        let account = Account {
+            owner: mock_principal(),
+            subaccount: None,
+        };
+        icrc1_transfer(TransferArg {
+            from_subaccount: None,
+            to: account,
+            fee: None,
+            created_at_time: None,
+            memo: None,
+            amount: 1_000_000_usize.into(),
+        })
+        .unwrap();
+        set_staking_apr(1_000).unwrap();
+
+        let result = delete_token_on_network(DeleteTokenArgs::default(), true);
+        assert_eq!(
+            result.unwrap_err(),
+            "Users still hold token balances, use force to delete anyway".to_string()
+        );
+        assert!(token_created());
+
+        let result = delete_token_on_network(
+            DeleteTokenArgs {
+                force: true,
+                ..Default::default()
+            },
+            true,
+        );
+        assert!(result.is_ok());
+        create_token_with_default_args().unwrap();
+        assert_eq!(icrc1_balance_of(account), Tokens::from(0_usize));
+        assert_eq!(icrc1_total_supply(), Tokens::from(1_000_000_000_usize));
+        assert_eq!(get_staking_apr(), 0);
+    }
+
+    #[test]
+    fn test_delete_token_requires_confirmation() {
+        create_token_with_default_args().unwrap();
+        let result = delete_token_on_network(DeleteTokenArgs::default(), false);
+        assert!(result.is_err());
+        assert!(token_created());
+
+        let result = delete_token_on_network(
+            DeleteTokenArgs {
+                confirmation_code: Some(u64::MAX),
+                ..Default::default()
+            },
+            false,
+        );
+        assert!(result.is_err());
+        assert!(token_created());
+
+        let code = result
+            .unwrap_err()
+            .split_whitespace()
+            .find_map(|w| w.parse::<u64>().ok())
+            .unwrap();
+        let result = delete_token_on_network(
+            DeleteTokenArgs {
+                confirmation_code: Some(code),
+                ..Default::default()
+            },
+            false,
+        );
+        assert!(result.is_ok());
+        assert!(!token_created());
+    }
+
     #[test]
     fn test_delete_token_not_created() {
-        let result = delete_token();
+        let result = delete_token(None);
         assert!(result.is_err());
         assert_eq!(result.unwrap_err(), "Token not created".to_string());
     }
@@ -910,6 +1295,79 @@ mod tests {
         assert_eq!(icrc1_metadata()[4].0, "icrc1:logo");
     }
 
+    #[test]
+    fn test_update_token_config() {
+        create_token_with_default_args().unwrap();
+
+        let result = update_token_config(UpdateTokenConfigArgs {
+            token_name: Some("Renamed".to_string()),
+            transfer_fee: Some(500_usize.into()),
+            ..Default::default()
+        });
+        assert!(result.is_ok());
+
+        let expected_fee: Tokens = 500_usize.into();
+        assert_eq!(icrc1_name(), "Renamed".to_string());
+        assert_eq!(icrc1_fee(), expected_fee);
+        assert_eq!(
+            icrc1_metadata()[0].1,
+            MetadataValue::Text("Renamed".to_string())
+        );
+        assert_eq!(icrc1_metadata()[3].1, MetadataValue::Nat(expected_fee));
+
+        let history = get_token_config_history();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].changes.len(), 2);
+        assert_eq!(history[0].changes[0].old_value, "TestToken".to_string());
+
+        // nothing changed, invalid values and unauthorized callers are rejected
+        let result = update_token_config(UpdateTokenConfigArgs {
+            token_name: Some("Renamed".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(result.unwrap_err(), "Nothing to update".to_string());
+        let result = update_token_config(UpdateTokenConfigArgs {
+            token_logo: Some("ftp://logo".to_string()),
+            ..Default::default()
+        });
+        assert!(result.is_err());
+        set_caller(Some(&mock_principal().to_string()));
+        let result = update_token_config(UpdateTokenConfigArgs {
+            token_name: Some("Hijacked".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(result.unwrap_err(), "Unauthorized operation".to_string());
+        set_caller(None);
+        assert_eq!(get_token_config_history().len(), 1);
+    }
+
+    #[test]
+    fn test_update_minting_account_migrates_supply() {
+        create_token_with_default_args().unwrap();
+        let old = icrc1_minting_account().unwrap();
+        let new = Account {
+            owner: caller(),
+            subaccount: Some([1; 32]),
+        };
+
+        let result = update_token_config(UpdateTokenConfigArgs {
+            minting_account: Some(new),
+            ..Default::default()
+        });
+        assert!(result.is_ok());
+        assert_eq!(icrc1_minting_account(), Some(new));
+        assert_eq!(logged_balance(old), Tokens::from(0_usize));
+        assert_eq!(logged_balance(new), Tokens::from(1_000_000_000_usize));
+        assert_eq!(icrc1_total_supply(), Tokens::from(1_000_000_000_usize));
+        assert!(!has_user_balances());
+
+        // replaying the log leaves nothing with the previous minting account
+        rebuild_balances_cache();
+        assert_eq!(icrc1_balance_of(old), Tokens::from(0_usize));
+        assert_eq!(icrc1_balance_of(new), Tokens::from(1_000_000_000_usize));
+        assert!(!has_user_balances());
+    }
+
     #[test]
     fn test_icrc1_standards() {
         let standards = icrc1_supported_standards();
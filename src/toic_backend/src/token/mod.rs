@@ -3,3 +3,4 @@ pub(super) use api::*;
 pub mod types;
 pub(super) use types::*;
 pub(super) mod constant;
+pub mod staking;
@@ -0,0 +1,337 @@
+use std::cell::RefCell;
+
+use candid::Principal;
+#[cfg(any(not(test), rust_analyzer))]
+use ic_cdk::api::{caller, is_controller};
+use ic_cdk::{query, update};
+use ic_stable_structures::{BTreeMap, Cell};
+use icrc_ledger_types::icrc1::{
+    account::Account,
+    transfer::{BlockIndex, TransferError},
+};
+
+use crate::memory::{MEMORY_MANAGER, TOKEN_STAKE_POSITION_MEM_ID, TOKEN_STAKING_CONFIG_MEM_ID};
+#[cfg(any(not(test), rust_analyzer))]
+use crate::utils::timestamp;
+
+use super::{
+    api::{apply_tx, get_locked_balance, get_staked_owners, icrc1_minting_account, token_created},
+    StakePosition, StakePositionRefCell, StakingRewardConfig, StakingRewardConfigRefCell, Tokens,
+    TxInfo,
+};
+
+#[cfg(all(test, not(rust_analyzer)))]
+use crate::utils::mocks::{caller, is_controller, timestamp};
+
+const YEAR_NANOS: u64 = 365 * 24 * 60 * 60 * 1_000_000_000;
+const MAX_APR_BPS: u16 = 10_000;
+
+// Error codes
+const NO_STAKING_REWARDS_ERROR_CODE: usize = 3;
+
+thread_local! {
+    static STAKE_POSITION: StakePositionRefCell = RefCell::new(
+        BTreeMap::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(TOKEN_STAKE_POSITION_MEM_ID))
+        )
+    );
+
+    static STAKING_CONFIG: StakingRewardConfigRefCell = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with_borrow(|m| m.get(TOKEN_STAKING_CONFIG_MEM_ID)),
+            StakingRewardConfig::default()
+        ).expect("failed to initialize the staking config cell")
+    );
+}
+
+/// Computes the simple interest earned by `amount` between two timestamps
+fn calculate_rewards(amount: &Tokens, apr_bps: u16, from: u64, to: u64) -> Tokens {
+    let elapsed = to.saturating_sub(from);
+    amount.clone() * Tokens::from(apr_bps) * Tokens::from(elapsed)
+        / (Tokens::from(MAX_APR_BPS) * Tokens::from(YEAR_NANOS))
+}
+
+/// Moves the rewards earned since the last accrual into the position
+fn accrue(position: &mut StakePosition, apr_bps: u16, now: u64) {
+    position.accrued_rewards +=
+        calculate_rewards(&position.amount, apr_bps, position.last_accrued_at, now);
+    position.last_accrued_at = now;
+}
+
+fn get_position(owner: Principal) -> Option<StakePosition> {
+    STAKE_POSITION.with_borrow(|m| m.get(&owner))
+}
+
+/// Builds the position of a stake made before positions were tracked, accruing from `since`
+fn untracked_position(owner: Principal, since: u64) -> Option<StakePosition> {
+    let amount = get_locked_balance(owner.into());
+    (amount > 0_usize).then(|| StakePosition {
+        amount,
+        staked_at: since,
+        last_accrued_at: since,
+        ..Default::default()
+    })
+}
+
+/// Saves a position for every stake made before positions were tracked so they start accruing
+/// from `now`, returns how many were created
+pub fn migrate_stake_positions(now: u64) -> usize {
+    let positions = get_staked_owners()
+        .into_iter()
+        .filter(|owner| get_position(*owner).is_none())
+        .filter_map(|owner| untracked_position(owner, now).map(|position| (owner, position)))
+        .collect::<Vec<_>>();
+    STAKE_POSITION.with_borrow_mut(|m| {
+        for (owner, position) in positions.iter() {
+            m.insert(*owner, position.clone());
+        }
+    });
+    positions.len()
+}
+
+fn apr_bps() -> u16 {
+    STAKING_CONFIG.with_borrow(|config| config.get().apr_bps)
+}
+
+/// Adds a newly staked amount into the position of the owner
+pub(super) fn record_stake(owner: Principal, amount: Tokens, now: u64) {
+    let mut position = get_position(owner)
+        .or_else(|| untracked_position(owner, now))
+        .unwrap_or(StakePosition {
+            staked_at: now,
+            last_accrued_at: now,
+            ..Default::default()
+        });
+    accrue(&mut position, apr_bps(), now);
+    position.amount += amount;
+    STAKE_POSITION.with_borrow_mut(|m| m.insert(owner, position));
+}
+
+/// Drops every stake position and resets the staking config
+pub(super) fn clear_staking() {
+    STAKE_POSITION.with_borrow_mut(|m| m.clear_new());
+    STAKING_CONFIG.with_borrow_mut(|config| {
+        config
+            .set(StakingRewardConfig::default())
+            .expect("failed to reset the staking config")
+    });
+}
+
+fn pending_rewards(owner: Principal, now: u64) -> Tokens {
+    get_position(owner)
+        .map(|mut position| {
+            accrue(&mut position, apr_bps(), now);
+            position.accrued_rewards
+        })
+        .unwrap_or_default()
+}
+
+#[query]
+pub(super) fn get_staking_apr() -> u16 {
+    apr_bps()
+}
+
+#[update]
+pub(super) fn set_staking_apr(apr_bps: u16) -> Result<String, String> {
+    if !is_controller(&caller()) {
+        return Err("Unauthorized operation".to_string());
+    }
+    if apr_bps > MAX_APR_BPS {
+        return Err("APR cannot exceed 10000 basis points".to_string());
+    }
+
+    // settle the rewards earned with the previous rate before switching
+    let now = timestamp();
+    let previous_apr = self::apr_bps();
+    STAKE_POSITION.with_borrow_mut(|m| {
+        let owners = m.keys().collect::<Vec<_>>();
+        for owner in owners {
+            if let Some(mut position) = m.get(&owner) {
+                accrue(&mut position, previous_apr, now);
+                m.insert(owner, position);
+            }
+        }
+    });
+    STAKING_CONFIG.with_borrow_mut(|config| {
+        config
+            .set(StakingRewardConfig { apr_bps })
+            .map_err(|_| "Failed to set staking config".to_string())
+    })?;
+    Ok("Staking APR updated".to_string())
+}
+
+#[query]
+fn get_stake_position(account: Account) -> Option<StakePosition> {
+    let now = timestamp();
+    get_position(account.owner).map(|mut position| {
+        accrue(&mut position, apr_bps(), now);
+        position
+    })
+}
+
+#[query]
+fn pending_staking_rewards(account: Account) -> Tokens {
+    pending_rewards(account.owner, timestamp())
+}
+
+#[update]
+fn claim_staking_rewards() -> Result<BlockIndex, TransferError> {
+    let owner = caller();
+    let now = timestamp();
+    let mut position = get_position(owner).ok_or(TransferError::GenericError {
+        error_code: NO_STAKING_REWARDS_ERROR_CODE.into(),
+        message: "No staked tokens".to_string(),
+    })?;
+    accrue(&mut position, apr_bps(), now);
+    if position.accrued_rewards == 0_usize || !token_created() {
+        return Err(TransferError::GenericError {
+            error_code: NO_STAKING_REWARDS_ERROR_CODE.into(),
+            message: "No staking rewards to claim".to_string(),
+        });
+    }
+
+    let tx = TxInfo {
+        from: icrc1_minting_account().expect("Bug: failed to get minting account"),
+        to: Some(owner.into()),
+        amount: position.accrued_rewards.clone(),
+        spender: None,
+        memo: None,
+        fee: None,
+        created_at_time: None,
+        expected_allowance: None,
+        expires_at: None,
+        is_approval: false,
+    };
+    let block = apply_tx(tx)?;
+    position.claimed_rewards += position.accrued_rewards.clone();
+    position.accrued_rewards = Tokens::default();
+    STAKE_POSITION.with_borrow_mut(|m| m.insert(owner, position));
+    Ok(block)
+}
+
+#[cfg(test)]
+mod tests {
+    use candid::Principal;
+    use icrc_ledger_types::icrc1::{account::Account, transfer::TransferArg};
+
+    use crate::{
+        token::{
+            api::{create_token, icrc1_balance_of, icrc1_transfer, stake},
+            CreateTokenArgs, StakeTokenArgs,
+        },
+        utils::mocks::{reset_timestamp, set_caller},
+        Tokens,
+    };
+
+    use super::{
+        calculate_rewards, claim_staking_rewards, get_stake_position, migrate_stake_positions,
+        pending_staking_rewards, set_staking_apr, STAKE_POSITION, YEAR_NANOS,
+    };
+
+    #[test]
+    fn test_calculate_rewards() {
+        let amount: Tokens = 1_000_000_usize.into();
+        let rewards = calculate_rewards(&amount, 500, 0, YEAR_NANOS);
+        assert_eq!(rewards, Tokens::from(50_000_usize));
+        let rewards = calculate_rewards(&amount, 500, 0, YEAR_NANOS / 2);
+        assert_eq!(rewards, Tokens::from(25_000_usize));
+    }
+
+    #[test]
+    fn test_claim_staking_rewards() {
+        create_token(Some(CreateTokenArgs {
+            token_name: "TestToken".to_string(),
+            token_symbol: "TT".to_string(),
+            token_logo: "logo".to_string(),
+            initial_supply: 1_000_000_000_usize.into(),
+            transfer_fee: 0_usize.into(),
+        }))
+        .unwrap();
+        set_staking_apr(1_000).unwrap();
+
+        let staker = Principal::from_text("aaaaa-aa").unwrap();
+        let account = Account {
+            owner: staker,
+            subaccount: None,
+        };
+        icrc1_transfer(TransferArg {
+            from_subaccount: None,
+            to: account,
+            fee: None,
+            created_at_time: None,
+            memo: None,
+            amount: 1_000_000_usize.into(),
+        })
+        .unwrap();
+
+        set_caller(Some(&staker.to_string()));
+        stake(StakeTokenArgs {
+            from_subaccount: None,
+            amount: 1_000_000_usize.into(),
+        })
+        .unwrap();
+        let staked_at = get_stake_position(account).unwrap().staked_at;
+
+        reset_timestamp(staked_at + YEAR_NANOS);
+        assert_eq!(
+            pending_staking_rewards(account),
+            Tokens::from(100_000_usize)
+        );
+
+        reset_timestamp(staked_at + YEAR_NANOS);
+        claim_staking_rewards().unwrap();
+        assert_eq!(icrc1_balance_of(account), Tokens::from(100_000_usize));
+
+        let position = get_stake_position(account).unwrap();
+        assert_eq!(position.claimed_rewards, Tokens::from(100_000_usize));
+        assert!(claim_staking_rewards().is_err());
+        set_caller(None);
+    }
+
+    #[test]
+    fn test_claim_rewards_of_untracked_stake() {
+        create_token(Some(CreateTokenArgs {
+            token_name: "TestToken".to_string(),
+            token_symbol: "TT".to_string(),
+            token_logo: "logo".to_string(),
+            initial_supply: 1_000_000_000_usize.into(),
+            transfer_fee: 0_usize.into(),
+        }))
+        .unwrap();
+        set_staking_apr(1_000).unwrap();
+
+        let staker = Principal::from_text("aaaaa-aa").unwrap();
+        let account = Account {
+            owner: staker,
+            subaccount: None,
+        };
+        icrc1_transfer(TransferArg {
+            from_subaccount: None,
+            to: account,
+            fee: None,
+            created_at_time: None,
+            memo: None,
+            amount: 1_000_000_usize.into(),
+        })
+        .unwrap();
+        set_caller(Some(&staker.to_string()));
+        stake(StakeTokenArgs {
+            from_subaccount: None,
+            amount: 1_000_000_usize.into(),
+        })
+        .unwrap();
+        // a stake made before positions were tracked
+        STAKE_POSITION.with_borrow_mut(|m| m.remove(&staker));
+        assert!(get_stake_position(account).is_none());
+
+        reset_timestamp(1_000);
+        assert_eq!(migrate_stake_positions(1_000), 1);
+        assert_eq!(migrate_stake_positions(1_000), 0);
+        assert_eq!(get_stake_position(account).unwrap().staked_at, 1_000);
+
+        reset_timestamp(1_000 + YEAR_NANOS);
+        claim_staking_rewards().unwrap();
+        assert_eq!(icrc1_balance_of(account), Tokens::from(100_000_usize));
+        set_caller(None);
+    }
+}
@@ -22,6 +22,9 @@ pub type TransactionLog = ic_stable_structures::Vec<StorableTransaction, VMemory
 pub type TransactionLogRefCell = RefCell<TransactionLog>;
 pub type AccountBalanceRefCell = RefCell<BTreeMap<Account, StorableToken, VMemory>>;
 pub type AccountOwnerBalanceRefCell = RefCell<BTreeMap<Principal, StorableToken, VMemory>>;
+pub type ConfigAuditLogRefCell = RefCell<BTreeMap<u64, TokenConfigChange, VMemory>>;
+pub type StakePositionRefCell = RefCell<BTreeMap<Principal, StakePosition, VMemory>>;
+pub type StakingRewardConfigRefCell = RefCell<Cell<StakingRewardConfig, VMemory>>;
 pub type Tokens = Nat;
 
 #[derive(Debug)]
@@ -114,6 +117,57 @@ pub struct CreateTokenArgs {
     pub transfer_fee: Nat,
 }
 
+#[derive(Debug, CandidType, Deserialize, Clone, Default)]
+pub struct DeleteTokenArgs {
+    /// Deletes the token even when users still hold or stake a balance
+    pub force: bool,
+    /// Code returned by the first call, required outside of the local network
+    pub confirmation_code: Option<u64>,
+}
+
+#[derive(Debug, CandidType, Deserialize, Clone, Default)]
+pub struct UpdateTokenConfigArgs {
+    pub token_name: Option<String>,
+    pub token_logo: Option<String>,
+    pub transfer_fee: Option<Nat>,
+    pub minting_account: Option<Account>,
+}
+
+#[derive(Debug, CandidType, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct ConfigFieldChange {
+    pub field: String,
+    pub old_value: String,
+    pub new_value: String,
+}
+
+#[derive(Debug, CandidType, Deserialize, Serialize, Clone)]
+pub struct TokenConfigChange {
+    pub changed_by: Principal,
+    pub timestamp: u64,
+    pub changes: Vec<ConfigFieldChange>,
+}
+
+impl Storable for TokenConfigChange {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut encoded = Vec::new();
+        ciborium::into_writer(self, &mut encoded).unwrap();
+        std::borrow::Cow::Owned(encoded)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        ciborium::from_reader(bytes.as_ref()).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(Debug, CandidType, Deserialize, Serialize, Clone, Default)]
+pub struct TokenStatistics {
+    /// Accounts holding or staking a balance, the minting account excluded
+    pub holders: u64,
+    pub total_staked: Tokens,
+    pub total_supply: Tokens,
+}
+
 #[derive(Debug, CandidType, Deserialize, Clone)]
 pub struct StakeTokenArgs {
     pub from_subaccount: Option<Subaccount>,
@@ -168,3 +222,45 @@ pub fn to_transfer_from_error(err: TransferError) -> TransferFromError {
         }
     }
 }
+
+#[derive(Debug, Default, CandidType, Deserialize, Serialize, Clone)]
+pub struct StakePosition {
+    pub amount: Tokens,
+    pub staked_at: u64,
+    /// Last time the rewards were accrued into `accrued_rewards`
+    pub last_accrued_at: u64,
+    pub accrued_rewards: Tokens,
+    pub claimed_rewards: Tokens,
+}
+
+impl Storable for StakePosition {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut encoded = Vec::new();
+        ciborium::into_writer(self, &mut encoded).unwrap();
+        std::borrow::Cow::Owned(encoded)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        ciborium::from_reader(bytes.as_ref()).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(Debug, Default, CandidType, Deserialize, Serialize, Clone)]
+pub struct StakingRewardConfig {
+    /// Annual percentage rate in basis points
+    pub apr_bps: u16,
+}
+
+impl Storable for StakingRewardConfig {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut encoded = Vec::new();
+        ciborium::into_writer(self, &mut encoded).unwrap();
+        std::borrow::Cow::Owned(encoded)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        ciborium::from_reader(bytes.as_ref()).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
@@ -6,11 +6,11 @@ use ic_stable_structures::{
     BTreeMap, Cell, Storable,
 };
 use serde::{Deserialize, Serialize};
-use strum::EnumString;
+use strum::{EnumCount, EnumString};
 use thiserror::Error;
 
 pub use crate::memory::VMemory;
-use crate::token::Tokens;
+use crate::token::{TokenStatistics, Tokens};
 
 pub type SerialRefCell = RefCell<Cell<u64, VMemory>>;
 pub type BTreeMapRefCell<K, V> = RefCell<BTreeMap<K, V, VMemory>>;
@@ -57,6 +57,8 @@ pub enum ServiceError {
     TransferError { reason: String },
     #[error("{0}")]
     AiModelError(String),
+    #[error("Daily AI quota of {quota} requests is exhausted, it resets at {resets_at}")]
+    AiQuotaExceeded { quota: u32, resets_at: u64 },
 }
 
 pub trait AuditableEntity {
@@ -73,6 +75,7 @@ pub trait AuditableEntity {
     Deserialize,
     Serialize,
     EnumString,
+    EnumCount,
     PartialEq,
     PartialOrd,
     Eq,
@@ -90,6 +93,24 @@ pub enum Category {
     Adventure,
     NonFiction,
     Biography,
+    /// Category added by a controller at runtime, see `CategoryDefinition`.
+    /// Its CBOR encoding stays within the 24 bytes bound of the index keys
+    Custom(u16),
+}
+
+impl Category {
+    pub const BUILT_IN: [Category; 10] = [
+        Category::SciFi,
+        Category::Fantasy,
+        Category::Comedy,
+        Category::Romance,
+        Category::Horror,
+        Category::Thriller,
+        Category::Crime,
+        Category::Adventure,
+        Category::NonFiction,
+        Category::Biography,
+    ];
 }
 
 impl Storable for Category {
@@ -132,11 +153,95 @@ impl Storable for StorablePrincipal {
     }
 }
 
+/// Maximum size in bytes of a keyword kept in the story term index
+pub const MAX_TERM_SIZE: usize = 24;
+
+/// Maximum size in bytes of a user handle
+pub const MAX_HANDLE_SIZE: usize = 20;
+
+/// Normalized user handle, bounded to be usable as a key of the stable structures
+#[derive(Debug, CandidType, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct StorableHandle(pub String);
+
+impl Storable for StorableHandle {
+    const BOUND: Bound = Blob::<MAX_HANDLE_SIZE>::BOUND;
+
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(
+            Blob::<MAX_HANDLE_SIZE>::try_from(self.0.as_bytes())
+                .expect("handle length should not exceed MAX_HANDLE_SIZE bytes")
+                .to_bytes()
+                .into_owned(),
+        )
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Self(
+            String::from_utf8_lossy(Blob::<MAX_HANDLE_SIZE>::from_bytes(bytes).as_slice())
+                .into_owned(),
+        )
+    }
+}
+
+/// Normalized keyword, bounded to be usable in tuple keys of the stable structures
+#[derive(Debug, CandidType, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct StorableTerm(pub String);
+
+impl Storable for StorableTerm {
+    const BOUND: Bound = Blob::<MAX_TERM_SIZE>::BOUND;
+
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(
+            Blob::<MAX_TERM_SIZE>::try_from(self.0.as_bytes())
+                .expect("term length should not exceed MAX_TERM_SIZE bytes")
+                .to_bytes()
+                .into_owned(),
+        )
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Self(
+            String::from_utf8_lossy(Blob::<MAX_TERM_SIZE>::from_bytes(bytes).as_slice())
+                .into_owned(),
+        )
+    }
+}
+
+/// Keyword frequencies of a story, used to find related stories
+#[derive(Debug, CandidType, Deserialize, Serialize, Clone)]
+pub struct StoryTerms {
+    pub category: Category,
+    /// (term, occurrences) of the most frequent terms
+    pub terms: Vec<(String, u32)>,
+}
+
+impl Storable for StoryTerms {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut encoded = Vec::new();
+        ciborium::into_writer(self, &mut encoded).unwrap();
+        std::borrow::Cow::Owned(encoded)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        ciborium::from_reader(bytes.as_ref()).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
 #[derive(Debug, CandidType, Deserialize, Serialize, Clone)]
 pub struct StoryDetail {
     pub description: String,
     pub mature_content: bool,
     pub category: Category,
+    /// Normalized free-form tags, see `normalize_tag`
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// BCP-47 language tag, see `normalize_language`. Detected at publish when omitted
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Id of the original story when this story is a translation
+    #[serde(default)]
+    pub translation_of: Option<u64>,
 }
 
 impl StoryDetail {
@@ -145,6 +250,9 @@ impl StoryDetail {
             description,
             mature_content,
             category,
+            tags: vec![],
+            language: None,
+            translation_of: None,
         }
     }
 }
@@ -191,8 +299,14 @@ pub struct Story {
     pub created_at: u64,
     pub updated_at: Option<u64>,
     pub read_time: u32,
+    /// Recommendation rank, see `calculate_rank`
     pub score: Score,
     pub author_name: Option<String>,
+    #[serde(default)]
+    pub author_handle: Option<String>,
+    /// Labels given by the safety classification, or by a moderator
+    #[serde(default)]
+    pub safety_labels: Vec<SafetyLabel>,
 }
 
 impl Storable for Story {
@@ -241,6 +355,8 @@ impl Story {
             read_time: draft.read_time,
             score: 0,
             author_name: None,
+            author_handle: None,
+            safety_labels: vec![],
         }
     }
 }
@@ -311,6 +427,15 @@ pub struct User {
     pub followed_categories: Vec<Category>,
     pub followed_authors: Vec<Principal>,
     pub onboarded: bool,
+    /// Unique normalized handle, see `normalize_handle`
+    #[serde(default)]
+    pub handle: Option<String>,
+    /// Mature stories are hidden from the feeds of the user unless enabled
+    #[serde(default)]
+    pub show_mature_content: bool,
+    /// Preferred story languages, used to filter the feeds of the user when not empty
+    #[serde(default)]
+    pub languages: Vec<String>,
 }
 
 impl Storable for User {
@@ -337,10 +462,84 @@ impl User {
             followed_authors: vec![],
             created_at,
             onboarded: false,
+            handle: None,
+            show_mature_content: false,
+            languages: vec![],
         }
     }
 }
 
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub enum NotificationKind {
+    /// Mentioned with `@handle` in the content of a story
+    Mention { story_id: u64, by: Principal },
+    /// A story of the recipient received support
+    Support {
+        story_id: u64,
+        by: Principal,
+        support: SupportSize,
+    },
+    /// A story of the recipient received a tip
+    Tip {
+        story_id: u64,
+        by: Principal,
+        amount: Tokens,
+    },
+    /// Someone started following the recipient
+    NewFollower { by: Principal },
+    /// An author followed by the recipient published a story
+    NewStory { story_id: u64, author: Principal },
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub struct Notification {
+    pub id: u64,
+    pub recipient: Principal,
+    pub kind: NotificationKind,
+    pub created_at: u64,
+    pub read: bool,
+}
+
+impl Storable for Notification {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut encoded = Vec::new();
+        ciborium::into_writer(self, &mut encoded).unwrap();
+        std::borrow::Cow::Owned(encoded)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        ciborium::from_reader(bytes.as_ref()).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Profile changes, `None` keeps the current value and an empty string clears it
+#[derive(Debug, Clone, CandidType, Deserialize, Default)]
+pub struct UpdateProfileArgs {
+    pub name: Option<String>,
+    pub bio: Option<String>,
+    pub handle: Option<String>,
+    /// `https` URL or `data:image/...;base64` URI
+    pub avatar: Option<String>,
+    pub categories: Option<Vec<Category>>,
+    pub show_mature_content: Option<bool>,
+    pub languages: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub struct UserProfile {
+    pub outline: UserOutline,
+    pub handle: Option<String>,
+    pub avatar: Option<String>,
+    pub followers: u32,
+    pub following: u32,
+    pub followed_categories: Vec<Category>,
+    pub story_count: u64,
+    pub total_support_received: u64,
+    pub total_tips_received: Tokens,
+    pub joined_at: u64,
+}
+
 #[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
 pub struct Statistics {
     pub total_users: u32,
@@ -348,6 +547,65 @@ pub struct Statistics {
     pub total_drafts: u32,
     pub total_categories: u32,
     pub category_followers: Vec<(Category, u32)>,
+    pub category_stories: Vec<(Category, u32)>,
+    pub token: TokenStatistics,
+    /// Whether the counters are still being recounted, they are incomplete until it is done
+    pub rebuilding: bool,
+}
+
+/// Counters behind `Statistics`, kept up to date on every write
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct PlatformCounters {
+    /// Whether the counters were computed from the existing entities once
+    pub initialized: bool,
+    pub total_users: u32,
+    pub total_stories: u32,
+    pub total_drafts: u32,
+    pub category_followers: std::collections::BTreeMap<Category, u32>,
+    pub category_stories: std::collections::BTreeMap<Category, u32>,
+    /// Progress of the recount running in batches
+    #[serde(default)]
+    pub rebuild: Option<StatisticsRebuild>,
+}
+
+impl PlatformCounters {
+    pub fn is_ready(&self) -> bool {
+        self.initialized && self.rebuild.is_none()
+    }
+}
+
+/// Entities visited by a recount of the per-category counters, the users first and the stories
+/// after them. Changes to entities not visited yet are left to the recount
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct StatisticsRebuild {
+    /// Last user counted
+    pub last_user: Option<Principal>,
+    pub users_counted: bool,
+    /// Next story to count
+    pub next_story: u64,
+}
+
+impl StatisticsRebuild {
+    pub fn is_user_counted(&self, user: &Principal) -> bool {
+        self.users_counted || self.last_user.is_some_and(|last| *user <= last)
+    }
+
+    pub fn is_story_counted(&self, id: u64) -> bool {
+        id < self.next_story
+    }
+}
+
+impl Storable for PlatformCounters {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut encoded = Vec::new();
+        ciborium::into_writer(self, &mut encoded).unwrap();
+        std::borrow::Cow::Owned(encoded)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        ciborium::from_reader(bytes.as_ref()).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -408,48 +666,809 @@ pub enum SortBy {
     UpdatedAt,
 }
 
-// candid Args section
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub struct EmissionSchedule {
+    /// Tokens emitted on the next reward round
+    pub emission_per_round: Tokens,
+    /// Length of a reward round in seconds
+    pub round_interval_secs: u64,
+    /// Emission reduction applied after every round, in basis points
+    pub decay_bps: u16,
+    /// Floor of the emission once decay has been applied
+    pub min_emission: Tokens,
+    pub support_weight: u64,
+    pub view_weight: u64,
+    /// Weight applied for every `TIP_POINT_UNIT` of tokens tipped
+    pub tip_weight: u64,
+}
 
-#[derive(Debug, Clone, CandidType, Deserialize, Serialize, Default)]
-pub struct SaveDraftArgs {
-    pub title: Option<String>,
-    pub content: Option<String>,
-    pub detail: Option<StoryDetail>,
+impl Default for EmissionSchedule {
+    fn default() -> Self {
+        Self {
+            emission_per_round: 100_000_000_000_usize.into(),
+            round_interval_secs: 7 * 24 * 60 * 60,
+            decay_bps: 100,
+            min_emission: 10_000_000_000_usize.into(),
+            support_weight: 100,
+            view_weight: 1,
+            tip_weight: 10,
+        }
+    }
+}
+
+/// Weights of the recommendation ranking, see `calculate_rank`
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub struct RankingConfig {
+    pub support_weight: u64,
+    pub view_weight: u64,
+    /// Weight applied for every `TIP_POINT_UNIT` of tokens tipped
+    pub tip_weight: u64,
+    /// Age after which a story needs twice the engagement to keep its rank
+    pub half_life_secs: u64,
+}
+
+impl Default for RankingConfig {
+    fn default() -> Self {
+        Self {
+            support_weight: 10,
+            view_weight: 1,
+            tip_weight: 100,
+            half_life_secs: 3 * 24 * 60 * 60,
+        }
+    }
+}
+
+impl Storable for RankingConfig {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut encoded = Vec::new();
+        ciborium::into_writer(self, &mut encoded).unwrap();
+        std::borrow::Cow::Owned(encoded)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        ciborium::from_reader(bytes.as_ref()).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub struct TreasuryState {
+    pub schedule: EmissionSchedule,
+    pub paused: bool,
+    pub current_emission: Tokens,
+    /// End of the last reward period, 0 when emission has not started yet
+    pub last_round_at: u64,
+    pub total_emitted: Tokens,
+}
+
+impl Default for TreasuryState {
+    fn default() -> Self {
+        let schedule = EmissionSchedule::default();
+        Self {
+            current_emission: schedule.emission_per_round.clone(),
+            schedule,
+            paused: false,
+            last_round_at: 0,
+            total_emitted: 0_usize.into(),
+        }
+    }
+}
+
+impl Storable for TreasuryState {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut encoded = Vec::new();
+        ciborium::into_writer(self, &mut encoded).unwrap();
+        std::borrow::Cow::Owned(encoded)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        ciborium::from_reader(bytes.as_ref()).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
 }
 
 #[derive(Debug, Clone, CandidType, Deserialize, Serialize, Default)]
-pub struct StoryInteractionArgs {
-    pub id: u64,
-    pub support: Option<SupportSize>,
-    pub tip: Option<Tokens>,
+pub struct Engagement {
+    pub support: u64,
+    pub views: u64,
+    pub tips: Tokens,
+}
+
+impl Storable for Engagement {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut encoded = Vec::new();
+        ciborium::into_writer(self, &mut encoded).unwrap();
+        std::borrow::Cow::Owned(encoded)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        ciborium::from_reader(bytes.as_ref()).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Engagement {
+    pub fn new(support: u64, views: u64, tips: Tokens) -> Self {
+        Self {
+            support,
+            views,
+            tips,
+        }
+    }
+
+    pub fn merge(&mut self, other: &Engagement) {
+        self.support += other.support;
+        self.views += other.views;
+        self.tips += other.tips.clone();
+    }
+
+    /// Takes away engagement merged before, never going below zero
+    pub fn subtract(&mut self, other: &Engagement) {
+        self.support = self.support.saturating_sub(other.support);
+        self.views = self.views.saturating_sub(other.views);
+        self.tips = if self.tips > other.tips {
+            self.tips.clone() - other.tips.clone()
+        } else {
+            Tokens::default()
+        };
+    }
 }
 
+/// All-time engagement of a story or an author with the points it is indexed under
 #[derive(Debug, Clone, CandidType, Deserialize, Serialize, Default)]
-pub struct OnboardingArgs {
-    pub name: Option<String>,
-    pub bio: Option<String>,
-    pub categories: Vec<Category>,
-    pub referral_code: Option<String>,
+pub struct EngagementTotal {
+    pub engagement: Engagement,
+    pub points: u64,
 }
 
-#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
-pub enum AssistActionArgs {
-    ExpandWriting(u64),
-    GenerateDescription(u64),
+impl Storable for EngagementTotal {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut encoded = Vec::new();
+        ciborium::into_writer(self, &mut encoded).unwrap();
+        std::borrow::Cow::Owned(encoded)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        ciborium::from_reader(bytes.as_ref()).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
 }
 
 #[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
-pub struct FetchStoriesArgs {
-    pub category: Option<Category>,
-    pub author: Option<Principal>,
-    pub cursor: Option<u64>,
-    pub limit: Option<usize>,
+pub struct RewardRound {
+    pub id: u64,
+    pub period_start: u64,
+    pub period_end: u64,
+    pub emission: Tokens,
+    pub distributed: Tokens,
+    pub total_points: u64,
+    pub recipients: u32,
+    pub created_at: u64,
+    pub updated_at: Option<u64>,
+}
+
+impl Storable for RewardRound {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut encoded = Vec::new();
+        ciborium::into_writer(self, &mut encoded).unwrap();
+        std::borrow::Cow::Owned(encoded)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        ciborium::from_reader(bytes.as_ref()).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl AuditableEntity for RewardRound {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn set_id(&mut self, id: u64) {
+        self.id = id
+    }
+
+    fn set_created_at(&mut self, created_at: u64) {
+        self.created_at = created_at;
+    }
+
+    fn set_updated_at(&mut self, updated_at: u64) {
+        self.updated_at = Some(updated_at);
+    }
 }
 
 #[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
-pub struct FetchStoriesByScoreArgs {
-    pub cursor: Option<(Score, u64)>,
-    pub limit: Option<usize>,
+pub struct RewardAllocation {
+    pub round_id: u64,
+    pub recipient: Principal,
+    pub points: u64,
+    pub amount: Tokens,
+    pub block_index: Option<Tokens>,
+    pub error: Option<String>,
+}
+
+impl Storable for RewardAllocation {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut encoded = Vec::new();
+        ciborium::into_writer(self, &mut encoded).unwrap();
+        std::borrow::Cow::Owned(encoded)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        ciborium::from_reader(bytes.as_ref()).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub struct StakingTier {
+    pub name: String,
+    /// Minimum amount of staked tokens to reach the tier
+    pub min_stake: Tokens,
+    /// Number of AI assist requests allowed per day
+    pub daily_quota: u32,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub struct StakingTiers(pub Vec<StakingTier>);
+
+impl Default for StakingTiers {
+    fn default() -> Self {
+        Self(vec![
+            StakingTier {
+                name: "Bronze".to_string(),
+                min_stake: 1_000_000_usize.into(),
+                daily_quota: 10,
+            },
+            StakingTier {
+                name: "Silver".to_string(),
+                min_stake: 10_000_000_usize.into(),
+                daily_quota: 50,
+            },
+            StakingTier {
+                name: "Gold".to_string(),
+                min_stake: 100_000_000_usize.into(),
+                daily_quota: 200,
+            },
+        ])
+    }
+}
+
+impl Storable for StakingTiers {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut encoded = Vec::new();
+        ciborium::into_writer(self, &mut encoded).unwrap();
+        std::borrow::Cow::Owned(encoded)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        ciborium::from_reader(bytes.as_ref()).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub struct AiQuota {
+    pub tier: Option<String>,
+    pub staked: Tokens,
+    pub daily_quota: u32,
+    pub used: u32,
+    pub remaining: u32,
+    pub resets_at: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub enum LlmModel {
+    #[default]
+    Llama3_1_8B,
+}
+
+impl From<LlmModel> for ic_llm::Model {
+    fn from(model: LlmModel) -> Self {
+        match model {
+            LlmModel::Llama3_1_8B => ic_llm::Model::Llama3_1_8B,
+        }
+    }
+}
+
+/// The kind of an assist action, used to pick the model serving it
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub enum AssistActionKind {
+    ExpandWriting,
+    GenerateDescription,
+    SuggestTitles,
+    FixGrammar,
+    ContinueStory,
+    Summarize,
+    ChangeTone,
+    Chat,
+    SafetyCheck,
+}
+
+#[derive(Debug, Clone, Default, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub struct LlmModelConfig {
+    pub default_model: LlmModel,
+    /// Models used instead of the default one for specific actions
+    pub overrides: Vec<(AssistActionKind, LlmModel)>,
+    /// Classifies the content of the stories when they are published
+    #[serde(default)]
+    pub safety_check: bool,
+    /// LLM canister answering the chats, the one `ic_llm` calls when not set
+    #[serde(default)]
+    pub canister_id: Option<Principal>,
+    /// Chat method of the LLM canister, the one `ic_llm` calls when not set
+    #[serde(default)]
+    pub chat_method: Option<String>,
+}
+
+impl LlmModelConfig {
+    pub fn model_for(&self, action: AssistActionKind) -> LlmModel {
+        self.overrides
+            .iter()
+            .find(|(a, _)| *a == action)
+            .map_or(self.default_model, |(_, model)| *model)
+    }
+}
+
+impl Storable for LlmModelConfig {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut encoded = Vec::new();
+        ciborium::into_writer(self, &mut encoded).unwrap();
+        std::borrow::Cow::Owned(encoded)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        ciborium::from_reader(bytes.as_ref()).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub enum SafetyLabel {
+    Mature,
+    Violence,
+    Hate,
+}
+
+/// A published story whose safety classification was not conclusive
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub struct ModerationEntry {
+    pub story_id: u64,
+    /// Labels the classification was sure about, already applied to the story
+    pub labels: Vec<SafetyLabel>,
+    /// Labels the classification could not decide on
+    pub uncertain: Vec<SafetyLabel>,
+    pub queued_at: u64,
+}
+
+impl Storable for ModerationEntry {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut encoded = Vec::new();
+        ciborium::into_writer(self, &mut encoded).unwrap();
+        std::borrow::Cow::Owned(encoded)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        ciborium::from_reader(bytes.as_ref()).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub enum ChatRole {
+    User,
+    Assistant,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub struct AssistantChatMessage {
+    pub role: ChatRole,
+    pub content: String,
+    pub created_at: u64,
+}
+
+impl Storable for AssistantChatMessage {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut encoded = Vec::new();
+        ciborium::into_writer(self, &mut encoded).unwrap();
+        std::borrow::Cow::Owned(encoded)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        ciborium::from_reader(bytes.as_ref()).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// candid Args section
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize, Default)]
+pub struct SaveDraftArgs {
+    pub title: Option<String>,
+    pub content: Option<String>,
+    pub detail: Option<StoryDetail>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize, Default)]
+pub struct StoryInteractionArgs {
+    pub id: u64,
+    pub support: Option<SupportSize>,
+    pub tip: Option<Tokens>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize, Default)]
+pub struct OnboardingArgs {
+    pub name: Option<String>,
+    pub bio: Option<String>,
+    pub categories: Vec<Category>,
+    pub referral_code: Option<String>,
+    /// Unique handle claimed with the profile, see `normalize_handle`
+    pub handle: Option<String>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub enum AssistActionArgs {
+    ExpandWriting(u64),
+    GenerateDescription(u64),
+    SuggestTitles(u64),
+    FixGrammar(u64),
+    ContinueStory(u64),
+    Summarize(u64),
+    ChangeTone(u64, Tone),
+}
+
+impl AssistActionArgs {
+    pub fn draft_id(&self) -> u64 {
+        match self {
+            AssistActionArgs::ExpandWriting(id)
+            | AssistActionArgs::GenerateDescription(id)
+            | AssistActionArgs::SuggestTitles(id)
+            | AssistActionArgs::FixGrammar(id)
+            | AssistActionArgs::ContinueStory(id)
+            | AssistActionArgs::Summarize(id)
+            | AssistActionArgs::ChangeTone(id, _) => *id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub enum SelectionRange {
+    /// Character offsets `[start, end)` in the draft content
+    Offsets { start: u64, end: u64 },
+    /// Index of a paragraph, paragraphs being separated by blank lines
+    Paragraph(u32),
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub struct AssistSelection {
+    pub range: SelectionRange,
+    /// Characters around the selection, on each side, given to the model as context
+    pub context_size: Option<u32>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub struct AssistReplacement {
+    pub text: String,
+    /// Character range of the content to replace with the text, empty when it is an insertion
+    pub start: u64,
+    pub end: u64,
+}
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub enum Tone {
+    Formal,
+    Casual,
+    Humorous,
+    Dramatic,
+    Suspenseful,
+    Poetic,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub struct FetchStoriesArgs {
+    pub category: Option<Category>,
+    pub author: Option<Principal>,
+    /// Overrides the language preferences of the reader
+    pub language: Option<String>,
+    pub cursor: Option<u64>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub struct FetchStoriesByScoreArgs {
+    pub cursor: Option<(Score, u64)>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub struct FetchRewardRoundsArgs {
+    pub cursor: Option<u64>,
+    pub limit: Option<usize>,
+}
+
+/// Category managed by a controller, referenced by stories as `Category::Custom(id)`
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub struct CategoryDefinition {
+    pub id: u16,
+    pub name: String,
+    /// Inactive categories keep their stories but cannot be picked for new ones
+    pub active: bool,
+    pub created_at: u64,
+}
+
+impl Storable for CategoryDefinition {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut encoded = Vec::new();
+        ciborium::into_writer(self, &mut encoded).unwrap();
+        std::borrow::Cow::Owned(encoded)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        ciborium::from_reader(bytes.as_ref()).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub struct UpdateCategoryArgs {
+    pub name: Option<String>,
+    pub active: Option<bool>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub struct CategoryInfo {
+    pub category: Category,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub struct ReadingList {
+    pub id: u64,
+    pub owner: Principal,
+    pub name: String,
+    pub description: Option<String>,
+    /// Public lists can be read by anyone with their id
+    pub public: bool,
+    /// Stories in reading order
+    pub story_ids: Vec<u64>,
+    pub created_at: u64,
+    pub updated_at: Option<u64>,
+}
+
+impl Storable for ReadingList {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut encoded = Vec::new();
+        ciborium::into_writer(self, &mut encoded).unwrap();
+        std::borrow::Cow::Owned(encoded)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        ciborium::from_reader(bytes.as_ref()).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl AuditableEntity for ReadingList {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn set_id(&mut self, id: u64) {
+        self.id = id
+    }
+
+    fn set_created_at(&mut self, created_at: u64) {
+        self.created_at = created_at;
+    }
+
+    fn set_updated_at(&mut self, updated_at: u64) {
+        self.updated_at = Some(updated_at);
+    }
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub struct ReadingListArgs {
+    pub name: String,
+    pub description: Option<String>,
+    pub public: bool,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub struct ReadingListItem {
+    pub story_id: u64,
+    /// `None` when the story is no longer available
+    pub story: Option<Story>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub struct ReadingListDetail {
+    pub list: ReadingList,
+    pub items: Vec<ReadingListItem>,
+}
+
+/// Position of a bookmark in the list of a user, (bookmarked at, story id)
+pub type BookmarkCursor = (u64, u64);
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub struct FetchBookmarksArgs {
+    pub cursor: Option<BookmarkCursor>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ReadingProgress {
+    pub story_id: u64,
+    /// Character offset in the story content the reader stopped at
+    pub position: u64,
+    pub updated_at: u64,
+}
+
+impl Storable for ReadingProgress {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut encoded = Vec::new();
+        ciborium::into_writer(self, &mut encoded).unwrap();
+        std::borrow::Cow::Owned(encoded)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        ciborium::from_reader(bytes.as_ref()).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub struct ReadingHistoryEntry {
+    pub progress: ReadingProgress,
+    pub story: Story,
+}
+
+/// Position of a story in the reading history of a user, (read at, story id)
+pub type ReadingHistoryCursor = (u64, u64);
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub struct FetchReadingHistoryArgs {
+    pub cursor: Option<ReadingHistoryCursor>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub struct FetchNotificationsArgs {
+    pub cursor: Option<u64>,
+    pub limit: Option<usize>,
+}
+
+/// Activity received by a story within a day
+#[derive(Debug, Clone, Default, CandidType, Deserialize, Serialize)]
+pub struct StoryActivity {
+    pub support: u64,
+    pub views: u64,
+    pub tips: Tokens,
+    /// Users supporting the story for the first time
+    pub new_supporters: u32,
+}
+
+impl Storable for StoryActivity {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut encoded = Vec::new();
+        ciborium::into_writer(self, &mut encoded).unwrap();
+        std::borrow::Cow::Owned(encoded)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        ciborium::from_reader(bytes.as_ref()).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl StoryActivity {
+    pub fn record(&mut self, engagement: &Engagement, new_supporter: bool) {
+        self.support += engagement.support;
+        self.views += engagement.views;
+        self.tips += engagement.tips.clone();
+        if new_supporter {
+            self.new_supporters += 1;
+        }
+    }
+}
+
+/// Followers gained and lost by an author within a day
+#[derive(Debug, Clone, Default, CandidType, Deserialize, Serialize)]
+pub struct FollowerChange {
+    pub gained: u32,
+    pub lost: u32,
+}
+
+impl Storable for FollowerChange {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let mut encoded = Vec::new();
+        ciborium::into_writer(self, &mut encoded).unwrap();
+        std::borrow::Cow::Owned(encoded)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        ciborium::from_reader(bytes.as_ref()).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub struct DailyStoryActivity {
+    pub day: u64,
+    pub activity: StoryActivity,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub struct DailyFollowerChange {
+    pub day: u64,
+    pub change: FollowerChange,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub struct StoryAnalytics {
+    pub story_id: u64,
+    pub title: String,
+    pub total_views: ViewSize,
+    pub total_support: SupportSize,
+    pub total_tips: Tokens,
+    pub supporter_count: u32,
+    /// Days of the range with activity, in ascending order
+    pub daily: Vec<DailyStoryActivity>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub struct SupporterStanding {
+    pub supporter: UserOutline,
+    pub support: u64,
+    pub tips: Tokens,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub struct AuthorAnalytics {
+    /// First day index of the range
+    pub from_day: u64,
+    /// Last day index of the range, inclusive
+    pub to_day: u64,
+    pub followers: u32,
+    pub follower_growth: Vec<DailyFollowerChange>,
+    pub stories: Vec<StoryAnalytics>,
+    pub top_supporters: Vec<SupporterStanding>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct AuthorAnalyticsArgs {
+    /// Start of the range in nanoseconds, defaults to 30 days before `to`
+    pub from: Option<u64>,
+    /// End of the range in nanoseconds, defaults to now
+    pub to: Option<u64>,
+}
+
+/// Period covered by the trending stories and top authors
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub enum LeaderboardWindow {
+    Day,
+    Week,
+    Month,
+    AllTime,
+}
+
+impl LeaderboardWindow {
+    /// Number of daily buckets covered, `None` for all time
+    pub fn days(&self) -> Option<u64> {
+        match self {
+            Self::Day => Some(1),
+            Self::Week => Some(7),
+            Self::Month => Some(30),
+            Self::AllTime => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
+pub struct AuthorStanding {
+    pub author: UserOutline,
+    pub engagement: Engagement,
+    pub points: u64,
 }
 
 #[derive(Debug, Clone, CandidType, Deserialize, Serialize)]
@@ -1,11 +1,21 @@
+use crate::types::{MAX_HANDLE_SIZE, MAX_TERM_SIZE};
+
 /// Default reading speed in words per minute
 pub const WPM: usize = 220;
 
+/// Number of nanoseconds in a day
+pub const DAY_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
 /// Gets current timestamp inside a canister, in nanoseconds since the epoch (1970-01-01)
 pub fn timestamp() -> u64 {
     ic_cdk::api::time()
 }
 
+/// Gets the number of days since the epoch for a timestamp in nanoseconds
+pub fn day_index(timestamp: u64) -> u64 {
+    timestamp / DAY_NANOS
+}
+
 /// Estimates the read time of a text based on the number of words and the reading speed in words per minute (WPM).
 pub fn estimate_read_time(text: &str) -> u32 {
     let word_count = text.split_whitespace().count();
@@ -16,9 +26,317 @@ pub fn estimate_read_time(text: &str) -> u32 {
     minutes.try_into().unwrap_or(0)
 }
 
+/// Common words carrying no meaning on their own, ignored when extracting terms
+const STOP_WORDS: &[&str] = &[
+    "about", "after", "again", "all", "also", "and", "any", "are", "because", "been", "before",
+    "being", "but", "can", "could", "did", "does", "each", "for", "from", "had", "has", "have",
+    "her", "here", "hers", "him", "his", "how", "into", "its", "just", "like", "more", "most",
+    "not", "now", "off", "once", "one", "only", "other", "our", "out", "over", "own", "said",
+    "same", "she", "should", "some", "such", "than", "that", "the", "their", "them", "then",
+    "there", "these", "they", "this", "those", "through", "too", "under", "until", "very", "was",
+    "were", "what", "when", "where", "which", "while", "who", "whom", "why", "will", "with",
+    "would", "you", "your",
+];
+
+/// Extracts the `max_terms` most frequent keywords of a text with their number of occurrences
+pub fn extract_terms(text: &str, max_terms: usize) -> Vec<(String, u32)> {
+    let mut counts = std::collections::BTreeMap::<String, u32>::new();
+    for word in text.split(|c: char| !c.is_alphanumeric()) {
+        let word = word.to_lowercase();
+        if word.chars().count() < 3
+            || word.chars().all(|c| c.is_numeric())
+            || STOP_WORDS.contains(&word.as_str())
+        {
+            continue;
+        }
+        let mut end = word.len().min(MAX_TERM_SIZE);
+        while !word.is_char_boundary(end) {
+            end -= 1;
+        }
+        *counts.entry(word[..end].to_string()).or_default() += 1;
+    }
+    let mut terms = counts.into_iter().collect::<Vec<_>>();
+    terms.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+    terms.truncate(max_terms);
+    terms
+}
+
+/// Minimum number of characters of a user handle
+pub const MIN_HANDLE_SIZE: usize = 3;
+
+/// Normalizes a handle to lowercase without the leading `@`, returns `None` when it contains
+/// anything else than ASCII letters, digits and underscores or has an invalid length
+pub fn normalize_handle(handle: &str) -> Option<String> {
+    let handle = handle.trim();
+    let handle = handle
+        .strip_prefix('@')
+        .unwrap_or(handle)
+        .to_ascii_lowercase();
+    let valid = (MIN_HANDLE_SIZE..=MAX_HANDLE_SIZE).contains(&handle.len())
+        && handle
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_');
+    valid.then_some(handle)
+}
+
+pub const MIN_TAG_SIZE: usize = 2;
+
+/// Normalizes a tag to lowercase without the leading `#`, returns `None` when it contains
+/// anything else than letters, digits and dashes or does not fit the index key
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = tag.trim();
+    let tag = tag.strip_prefix('#').unwrap_or(tag).to_lowercase();
+    let valid = tag.chars().count() >= MIN_TAG_SIZE
+        && tag.len() <= MAX_TERM_SIZE
+        && tag.chars().all(|c| c.is_alphanumeric() || c == '-');
+    valid.then_some(tag)
+}
+
+/// BCP-47 tag of stories whose language is not known
+pub const UNDETERMINED_LANGUAGE: &str = "und";
+
+/// Primary subtag of a normalized language tag, as `en` for `en-GB`
+pub fn primary_language(tag: &str) -> &str {
+    tag.split('-').next().unwrap_or(tag)
+}
+
+/// Language tags a story is indexed under, its tag and its primary subtag, or
+/// `UNDETERMINED_LANGUAGE` when it has none
+pub fn language_index_keys(language: Option<&str>) -> Vec<String> {
+    match language {
+        Some(tag) if primary_language(tag) != tag => {
+            vec![tag.to_string(), primary_language(tag).to_string()]
+        }
+        Some(tag) => vec![tag.to_string()],
+        None => vec![UNDETERMINED_LANGUAGE.to_string()],
+    }
+}
+
+/// Normalizes a BCP-47 language tag to its conventional casing, as in `en`, `pt-BR` or
+/// `zh-Hant-TW`. Returns `None` for malformed tags or tags that do not fit the index key
+pub fn normalize_language(tag: &str) -> Option<String> {
+    let tag = tag.trim();
+    if tag.is_empty() || tag.len() > MAX_TERM_SIZE {
+        return None;
+    }
+    let mut subtags = Vec::new();
+    for (i, subtag) in tag.split(['-', '_']).enumerate() {
+        let alphabetic = subtag.chars().all(|c| c.is_ascii_alphabetic());
+        let normalized = match subtag.len() {
+            2..=3 if i == 0 && alphabetic => subtag.to_ascii_lowercase(),
+            2 if alphabetic => subtag.to_ascii_uppercase(),
+            3 if subtag.chars().all(|c| c.is_ascii_digit()) => subtag.to_string(),
+            4 if alphabetic => {
+                let lower = subtag.to_ascii_lowercase();
+                lower[..1].to_ascii_uppercase() + &lower[1..]
+            }
+            5..=8 if i > 0 && subtag.chars().all(|c| c.is_ascii_alphanumeric()) => {
+                subtag.to_ascii_lowercase()
+            }
+            _ => return None,
+        };
+        subtags.push(normalized);
+    }
+    Some(subtags.join("-"))
+}
+
+/// Characters looked at when detecting the language of a text
+const LANGUAGE_SAMPLE_SIZE: usize = 5_000;
+const MIN_LANGUAGE_STOP_WORDS: usize = 3;
+const LANGUAGE_STOP_WORDS: &[(&str, &[&str])] = &[
+    (
+        "en",
+        &[
+            "the", "and", "of", "to", "is", "was", "that", "with", "for", "he", "she", "you",
+            "not", "have",
+        ],
+    ),
+    (
+        "id",
+        &[
+            "yang", "dan", "di", "itu", "tidak", "dengan", "ini", "ke", "dari", "untuk", "aku",
+            "dia", "ada", "akan",
+        ],
+    ),
+    (
+        "es",
+        &[
+            "el", "los", "las", "que", "y", "en", "del", "por", "con", "una", "para", "pero", "su",
+            "es",
+        ],
+    ),
+    (
+        "fr",
+        &[
+            "le", "les", "des", "et", "est", "une", "dans", "pour", "pas", "du", "il", "elle",
+            "qui", "je",
+        ],
+    ),
+    (
+        "de",
+        &[
+            "der", "die", "das", "und", "ist", "nicht", "ein", "eine", "zu", "den", "mit", "sich",
+            "auf", "ich",
+        ],
+    ),
+    (
+        "pt",
+        &[
+            "o", "os", "que", "e", "do", "da", "em", "um", "uma", "para", "com", "não", "na", "ao",
+        ],
+    ),
+    (
+        "it",
+        &[
+            "il", "che", "di", "la", "un", "una", "per", "non", "sono", "del", "con", "gli",
+            "della", "è",
+        ],
+    ),
+    (
+        "nl",
+        &[
+            "de", "het", "een", "en", "van", "ik", "te", "dat", "niet", "zijn", "op", "voor",
+            "met", "die",
+        ],
+    ),
+];
+
+/// Detects the language of a text, by its script for the languages with their own script and
+/// by counting common words for the Latin ones. Returns `None` when nothing stands out
+pub fn detect_language(text: &str) -> Option<String> {
+    let sample = text.chars().take(LANGUAGE_SAMPLE_SIZE).collect::<String>();
+    let mut scripts: std::collections::BTreeMap<&str, usize> = std::collections::BTreeMap::new();
+    let mut letters = 0;
+    for c in sample.chars().filter(|c| c.is_alphabetic()) {
+        letters += 1;
+        let script = match c as u32 {
+            0x3040..=0x30FF => "ja",
+            0xAC00..=0xD7AF | 0x1100..=0x11FF => "ko",
+            0x4E00..=0x9FFF => "zh",
+            0x0400..=0x04FF => "ru",
+            0x0600..=0x06FF => "ar",
+            0x0E00..=0x0E7F => "th",
+            0x0900..=0x097F => "hi",
+            0x0370..=0x03FF => "el",
+            0x0590..=0x05FF => "he",
+            _ => continue,
+        };
+        *scripts.entry(script).or_default() += 1;
+    }
+    // kana next to kanji is Japanese rather than Chinese
+    if scripts.get("ja").is_some_and(|kana| *kana > 0) {
+        let han = scripts.remove("zh").unwrap_or_default();
+        *scripts.entry("ja").or_default() += han;
+    }
+    if let Some((script, count)) = scripts.into_iter().max_by_key(|(_, count)| *count) {
+        if count * 2 > letters {
+            return Some(script.to_string());
+        }
+    }
+
+    let words = sample
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect::<Vec<_>>();
+    LANGUAGE_STOP_WORDS
+        .iter()
+        .map(|(language, stop_words)| {
+            let hits = words
+                .iter()
+                .filter(|w| stop_words.contains(&w.as_str()))
+                .count();
+            (hits, *language)
+        })
+        .filter(|(hits, _)| *hits >= MIN_LANGUAGE_STOP_WORDS)
+        .max()
+        .map(|(_, language)| language.to_string())
+}
+
+/// Extracts the normalized handles mentioned with `@handle`, in order of first appearance.
+/// An `@` preceded by a letter or digit, as in an email address, is not a mention
+pub fn extract_mentions(text: &str, max_mentions: usize) -> Vec<String> {
+    let mut mentions: Vec<String> = Vec::new();
+    let mut previous = ' ';
+    for (i, c) in text.char_indices() {
+        if c == '@' && !previous.is_alphanumeric() && previous != '_' && previous != '@' {
+            let candidate = text[i + 1..]
+                .chars()
+                .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+                .collect::<String>();
+            if let Some(handle) = normalize_handle(&candidate) {
+                if !mentions.contains(&handle) {
+                    mentions.push(handle);
+                }
+            }
+            if mentions.len() >= max_mentions {
+                break;
+            }
+        }
+        previous = c;
+    }
+    mentions
+}
+
+/// Gets the character ranges `[start, end)` of the paragraphs, separated by blank lines
+pub fn paragraph_ranges(text: &str) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let mut start = None;
+    let mut end = 0;
+    let mut offset = 0;
+    for line in text.split('\n') {
+        let len = line.chars().count();
+        if line.trim().is_empty() {
+            if let Some(s) = start.take() {
+                ranges.push((s, end));
+            }
+        } else {
+            start.get_or_insert(offset);
+            end = offset + len;
+        }
+        offset += len + 1;
+    }
+    if let Some(s) = start {
+        ranges.push((s, end));
+    }
+    ranges
+}
+
+/// Splits a text into chunks of at most `max_chars` characters, breaking between paragraphs
+/// when possible
+pub fn chunk_text(text: &str, max_chars: usize) -> Vec<String> {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut chunks: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut current_len = 0;
+    for (start, end) in paragraph_ranges(text) {
+        for piece in chars[start..end].chunks(max_chars.max(1)) {
+            let separator = if current_len == 0 { 0 } else { 2 };
+            if current_len + separator + piece.len() > max_chars && current_len > 0 {
+                chunks.push(std::mem::take(&mut current));
+                current_len = 0;
+            } else if current_len > 0 {
+                current.push_str("\n\n");
+                current_len += 2;
+            }
+            current.extend(piece);
+            current_len += piece.len();
+        }
+    }
+    if current_len > 0 {
+        chunks.push(current);
+    }
+    chunks
+}
+
 #[cfg(test)]
 pub mod mocks {
-    use std::cell::{Cell, RefCell};
+    use std::{
+        cell::{Cell, RefCell},
+        future::Future,
+        pin::pin,
+        task::{Context, Poll, Waker},
+    };
 
     use candid::Principal;
 
@@ -52,6 +370,17 @@ pub mod mocks {
         TIMESTAMP.with(|c| c.set(time));
     }
 
+    /// Drives a future that never waits on an inter-canister call to completion
+    pub fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
     pub fn set_caller(caller: Option<&str>) {
         if let Some(caller) = caller {
             CALLER.with_borrow_mut(|s| *s = caller.to_string());
@@ -65,7 +394,10 @@ pub mod mocks {
 mod tests {
     use crate::utils::estimate_read_time;
 
-    use super::timestamp;
+    use super::{
+        chunk_text, detect_language, extract_mentions, extract_terms, language_index_keys,
+        normalize_handle, normalize_language, normalize_tag, paragraph_ranges, timestamp,
+    };
 
     #[test]
     #[should_panic]
@@ -79,4 +411,121 @@ mod tests {
         let read_time = estimate_read_time(text);
         assert_eq!(read_time, 1); // 1 minute for less than 220 words
     }
+
+    #[test]
+    fn paragraphs() {
+        let text = "Hello world\nsecond line\n\n\nNext é\n";
+        let ranges = paragraph_ranges(text);
+        assert_eq!(ranges, vec![(0, 23), (26, 32)]);
+        let chars = text.chars().collect::<Vec<_>>();
+        assert_eq!(chars[26..32].iter().collect::<String>(), "Next é");
+    }
+
+    #[test]
+    fn chunking() {
+        let text = "aaaa\n\nbbbb\n\ncccccccccc";
+        assert_eq!(chunk_text(text, 10), vec!["aaaa\n\nbbbb", "cccccccccc"]);
+        assert_eq!(
+            chunk_text(text, 4),
+            vec!["aaaa", "bbbb", "cccc", "cccc", "cc"]
+        );
+        assert!(chunk_text("  \n ", 10).is_empty());
+    }
+
+    #[test]
+    fn terms() {
+        let terms = extract_terms(
+            "The dragon and THE Dragon's cave, a cave of 1999 dragons.",
+            2,
+        );
+        assert_eq!(
+            terms,
+            vec![("cave".to_string(), 2), ("dragon".to_string(), 2)]
+        );
+        let terms = extract_terms(&"ü".repeat(30), 1);
+        assert!(terms[0].0.len() <= 24);
+    }
+
+    #[test]
+    fn handles() {
+        assert_eq!(
+            normalize_handle(" @Jane_Doe99 "),
+            Some("jane_doe99".to_string())
+        );
+        assert_eq!(normalize_handle("ab"), None);
+        assert_eq!(normalize_handle("jane.doe"), None);
+        assert_eq!(normalize_handle("jané"), None);
+        assert_eq!(normalize_handle(&"a".repeat(21)), None);
+    }
+
+    #[test]
+    fn mentions() {
+        let text =
+            "Thanks @Jane_Doe and @bob! Mail jane@example.com, @jane_doe again, @ab, (@carol)";
+        assert_eq!(
+            extract_mentions(text, 10),
+            vec![
+                "jane_doe".to_string(),
+                "bob".to_string(),
+                "carol".to_string()
+            ]
+        );
+        assert_eq!(extract_mentions(text, 1), vec!["jane_doe".to_string()]);
+    }
+
+    #[test]
+    fn test_normalize_tag() {
+        assert_eq!(
+            normalize_tag(" #Space-Opera "),
+            Some("space-opera".to_string())
+        );
+        assert_eq!(normalize_tag("Fantasía"), Some("fantasía".to_string()));
+        assert_eq!(normalize_tag("a"), None);
+        assert_eq!(normalize_tag("two words"), None);
+        assert_eq!(normalize_tag(&"a".repeat(25)), None);
+    }
+
+    #[test]
+    fn test_normalize_language() {
+        assert_eq!(normalize_language("EN"), Some("en".to_string()));
+        assert_eq!(normalize_language("pt_br"), Some("pt-BR".to_string()));
+        assert_eq!(
+            normalize_language("zh-hant-tw"),
+            Some("zh-Hant-TW".to_string())
+        );
+        assert_eq!(normalize_language("es-419"), Some("es-419".to_string()));
+        assert_eq!(normalize_language("english"), None);
+        assert_eq!(normalize_language("e"), None);
+        assert_eq!(normalize_language("en--US"), None);
+    }
+
+    #[test]
+    fn test_language_index_keys() {
+        assert_eq!(language_index_keys(Some("en-GB")), vec!["en-GB", "en"]);
+        assert_eq!(language_index_keys(Some("en")), vec!["en"]);
+        assert_eq!(language_index_keys(None), vec!["und"]);
+    }
+
+    #[test]
+    fn test_detect_language() {
+        assert_eq!(
+            detect_language("The ship was quiet and she knew that the crew was not coming back."),
+            Some("en".to_string())
+        );
+        assert_eq!(
+            detect_language(
+                "Dia tidak tahu bahwa kapal itu akan pergi dari pelabuhan dengan cepat."
+            ),
+            Some("id".to_string())
+        );
+        assert_eq!(
+            detect_language("彼女は静かな港で船を待っていた。"),
+            Some("ja".to_string())
+        );
+        assert_eq!(
+            detect_language("Корабль ушёл в море."),
+            Some("ru".to_string())
+        );
+        assert_eq!(detect_language("Lorem ipsum"), None);
+    }
 }